@@ -0,0 +1,56 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Long-lived keys for scripts, accepted by `auth_middleware` instead of access tokens
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "api_key")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub user_id: i32,
+    pub name: String,
+    //Start of the key, lets the owner tell keys apart
+    pub key_prefix: String,
+    //Only SHA-256 of the key is stored
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    //Space separated permission names, the key never gets more than the role of the user
+    pub scopes: String,
+    pub created_at: DateTimeUtc,
+    pub expires_at: DateTimeUtc,
+    pub last_used_at: Option<DateTimeUtc>,
+}
+
+impl Model {
+    pub fn scope_list(&self) -> Vec<&str> {
+        self.scopes.split_whitespace().collect()
+    }
+
+    pub fn has_scope(&self, permission: &str) -> bool {
+        self.scopes
+            .split_whitespace()
+            .any(|scope| scope == permission)
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::user::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::user::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<crate::entities::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
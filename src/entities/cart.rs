@@ -10,6 +10,8 @@ pub struct Model {
     #[sea_orm(indexed)]
     pub user_id: i32,
     pub product_id: i32,
+    //Set for products that are sold in variants
+    pub variant_id: Option<i32>,
     pub quantity: u32,
 }
 
@@ -29,6 +31,14 @@ pub enum Relation {
         to = "crate::entities::product::Column::Id",
     )]
     Product,
+    #[sea_orm(
+        belongs_to = "crate::entities::product_variant::Entity",
+        from = "crate::entities::cart::Column::VariantId",
+        to = "crate::entities::product_variant::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Variant,
 }
 
 
@@ -44,4 +54,10 @@ impl Related<crate::entities::product::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Product.def()
     }
+}
+
+impl Related<crate::entities::product_variant::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Variant.def()
+    }
 }
\ No newline at end of file
@@ -11,7 +11,10 @@ pub struct Model {
     pub file_name: String,
     #[sea_orm(unique)]
     pub path_name: String,
-    pub extension: FileExtension
+    pub extension: FileExtension,
+    //Of the stored file, uploads with the same content return the existing image
+    #[sea_orm(indexed)]
+    pub sha256: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -19,6 +22,8 @@ pub enum Relation {}
 
 impl ActiveModelBehavior for ActiveModel {}
 
+//Variants are named after the extensions
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, PartialEq, Debug, EnumIter, DeriveActiveEnum, Serialize)]
 #[sea_orm(
     enum_name = "extension_enum",
@@ -30,6 +35,25 @@ pub enum FileExtension {
     JPG,
     #[sea_orm(string_value = "png")]
     PNG,
+    #[sea_orm(string_value = "webp")]
+    WEBP,
+    #[sea_orm(string_value = "gif")]
+    GIF,
+    #[sea_orm(string_value = "avif")]
+    AVIF,
+}
+
+impl FileExtension {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/jpeg" => Some(FileExtension::JPG),
+            "image/png" => Some(FileExtension::PNG),
+            "image/webp" => Some(FileExtension::WEBP),
+            "image/gif" => Some(FileExtension::GIF),
+            "image/avif" => Some(FileExtension::AVIF),
+            _ => None,
+        }
+    }
 }
 
 impl FromStr for FileExtension {
@@ -39,6 +63,9 @@ impl FromStr for FileExtension {
         match s {
             "jpg" => Ok(FileExtension::JPG),
             "png" => Ok(FileExtension::PNG),
+            "webp" => Ok(FileExtension::WEBP),
+            "gif" => Ok(FileExtension::GIF),
+            "avif" => Ok(FileExtension::AVIF),
             _ => Err(()),
         }
     }
@@ -49,6 +76,9 @@ impl ToString for FileExtension {
         match self {
             FileExtension::JPG => "jpg".to_string(),
             FileExtension::PNG => "png".to_string(),
+            FileExtension::WEBP => "webp".to_string(),
+            FileExtension::GIF => "gif".to_string(),
+            FileExtension::AVIF => "avif".to_string(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,35 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Resized copy of the image made after upload, see `imaging::IMAGE_SIZES`
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "image_size")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub image_id: i32,
+    pub size: String,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::image::Entity",
+        from = "Column::ImageId",
+        to = "crate::entities::image::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Image,
+}
+
+impl Related<crate::entities::image::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Image.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Failed logins counted per username and per client IP, see `login` in auth_routes
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "login_throttle")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    //"user:<username>" or "ip:<address>"
+    #[sea_orm(unique)]
+    pub key: String,
+    pub failed_attempts: i32,
+    //Lockouts in a row, every next one lasts twice as long
+    pub lockouts: i32,
+    pub last_failed_at: DateTimeUtc,
+    pub locked_until: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,23 +1,61 @@
 pub mod user;
+pub mod api_key;
 pub mod product;
+pub mod product_image;
+pub mod product_variant;
 pub mod cart;
 pub mod category;
 pub mod image;
+pub mod image_size;
+pub mod login_throttle;
+pub mod money;
+pub mod oidc_login;
 pub mod order;
 pub mod order_part;
+pub mod order_status_history;
+pub mod password_reset_token;
+pub mod permission;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod role;
+pub mod role_permission;
+pub mod signing_key;
+pub mod stock_movement;
+pub mod user_identity;
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
     Argon2,
 };
 use std::sync::Arc;
-use sea_orm::{ConnectionTrait, DatabaseConnection, EntityTrait, Schema, Set, TransactionTrait};
+use sea_orm::{
+    sea_query::Index, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, Schema, Set, TransactionTrait,
+};
 use crate::entities::{
+    api_key::Entity as ApiKey,
     cart::Entity as Crate,
     category::Entity as Category,
     user::Entity as User,
     product::Entity as Product,
+    product_image::Entity as ProductImage,
+    product_variant::Entity as ProductVariant,
     image::Entity as Image,
+    image_size::Entity as ImageSize,
+    login_throttle::Entity as LoginThrottle,
+    oidc_login::Entity as OidcLogin,
+    order::Entity as Order,
+    order_part::Entity as OrderPart,
+    order_status_history::Entity as OrderStatusHistory,
+    password_reset_token::Entity as PasswordResetToken,
+    permission::Entity as Permission,
+    recovery_code::Entity as RecoveryCode,
+    refresh_token::Entity as RefreshToken,
+    role::Entity as Role,
+    role_permission::Entity as RolePermission,
+    signing_key::Entity as SigningKey,
+    stock_movement::Entity as StockMovement,
+    user_identity::Entity as UserIdentity,
 };
 
 pub async fn setup_schema(db: &DatabaseConnection) {
@@ -26,7 +64,43 @@ pub async fn setup_schema(db: &DatabaseConnection) {
     let create_category_table = schema.create_table_from_entity(Category);
     let create_user_table = schema.create_table_from_entity(User);
     let create_product_table = schema.create_table_from_entity(Product);
+    let create_product_image_table = schema.create_table_from_entity(ProductImage);
+    let create_product_variant_table = schema.create_table_from_entity(ProductVariant);
     let create_image_table = schema.create_table_from_entity(Image);
+    let create_image_size_table = schema.create_table_from_entity(ImageSize);
+    let create_order_table = schema.create_table_from_entity(Order);
+    let create_order_part_table = schema.create_table_from_entity(OrderPart);
+    let create_order_status_history_table = schema.create_table_from_entity(OrderStatusHistory);
+    let create_stock_movement_table = schema.create_table_from_entity(StockMovement);
+    let create_refresh_token_table = schema.create_table_from_entity(RefreshToken);
+    let create_password_reset_token_table = schema.create_table_from_entity(PasswordResetToken);
+    let create_login_throttle_table = schema.create_table_from_entity(LoginThrottle);
+    let create_recovery_code_table = schema.create_table_from_entity(RecoveryCode);
+    let create_api_key_table = schema.create_table_from_entity(ApiKey);
+    let create_signing_key_table = schema.create_table_from_entity(SigningKey);
+    let create_oidc_login_table = schema.create_table_from_entity(OidcLogin);
+    let create_user_identity_table = schema.create_table_from_entity(UserIdentity);
+    //One provider account can be linked to one user only
+    let create_user_identity_index = Index::create()
+        .name("idx-user_identity-issuer-subject")
+        .table(UserIdentity)
+        .col(user_identity::Column::Issuer)
+        .col(user_identity::Column::Subject)
+        .unique()
+        .to_owned();
+    let create_role_table = schema.create_table_from_entity(Role);
+    let create_permission_table = schema.create_table_from_entity(Permission);
+    let create_role_permission_table = schema.create_table_from_entity(RolePermission);
+
+    db.execute(db.get_database_backend().build(&create_role_table))
+        .await
+        .expect("Failed to create role schema");
+    db.execute(db.get_database_backend().build(&create_permission_table))
+        .await
+        .expect("Failed to create permission schema");
+    db.execute(db.get_database_backend().build(&create_role_permission_table))
+        .await
+        .expect("Failed to create role_permission schema");
 
     db.execute(db.get_database_backend().build(&create_cart_table))
         .await
@@ -40,9 +114,57 @@ pub async fn setup_schema(db: &DatabaseConnection) {
     db.execute(db.get_database_backend().build(&create_product_table))
         .await
         .expect("Failed to create product schema");
+    db.execute(db.get_database_backend().build(&create_product_image_table))
+        .await
+        .expect("Failed to create product_image schema");
+    db.execute(db.get_database_backend().build(&create_product_variant_table))
+        .await
+        .expect("Failed to create product_variant schema");
     db.execute(db.get_database_backend().build(&create_image_table))
         .await
         .expect("Failed to create image schema");
+    db.execute(db.get_database_backend().build(&create_image_size_table))
+        .await
+        .expect("Failed to create image_size schema");
+    db.execute(db.get_database_backend().build(&create_order_table))
+        .await
+        .expect("Failed to create order schema");
+    db.execute(db.get_database_backend().build(&create_order_part_table))
+        .await
+        .expect("Failed to create order_part schema");
+    db.execute(db.get_database_backend().build(&create_order_status_history_table))
+        .await
+        .expect("Failed to create order_status_history schema");
+    db.execute(db.get_database_backend().build(&create_stock_movement_table))
+        .await
+        .expect("Failed to create stock_movement schema");
+    db.execute(db.get_database_backend().build(&create_refresh_token_table))
+        .await
+        .expect("Failed to create refresh_token schema");
+    db.execute(db.get_database_backend().build(&create_password_reset_token_table))
+        .await
+        .expect("Failed to create password_reset_token schema");
+    db.execute(db.get_database_backend().build(&create_login_throttle_table))
+        .await
+        .expect("Failed to create login_throttle schema");
+    db.execute(db.get_database_backend().build(&create_recovery_code_table))
+        .await
+        .expect("Failed to create recovery_code schema");
+    db.execute(db.get_database_backend().build(&create_api_key_table))
+        .await
+        .expect("Failed to create api_key schema");
+    db.execute(db.get_database_backend().build(&create_signing_key_table))
+        .await
+        .expect("Failed to create signing_key schema");
+    db.execute(db.get_database_backend().build(&create_oidc_login_table))
+        .await
+        .expect("Failed to create oidc_login schema");
+    db.execute(db.get_database_backend().build(&create_user_identity_table))
+        .await
+        .expect("Failed to create user_identity schema");
+    db.execute(db.get_database_backend().build(&create_user_identity_index))
+        .await
+        .expect("Failed to create user_identity index");
 }
 
 pub async fn primary_settup(db: Arc<DatabaseConnection>){
@@ -56,19 +178,27 @@ pub async fn primary_settup(db: Arc<DatabaseConnection>){
     let new_admin = user::ActiveModel {
         username: Set("admin".to_owned()),
         password: Set(password_hash.clone()),
-        role: Set(user::Role::Admin),
+        role: Set(role::ADMIN.to_owned()),
+        must_change_password: Set(false),
+        totp_enabled: Set(false),
         ..Default::default()
     };
 
     let new_user = user::ActiveModel {
         username: Set("user".to_owned()),
         password: Set(password_hash),
-        role: Set(user::Role::User),
+        role: Set(role::USER.to_owned()),
+        must_change_password: Set(false),
+        totp_enabled: Set(false),
         ..Default::default()
     };
 
     match db.begin().await {
         Ok(txn) => {
+            if seed_rbac(&txn).await.is_err() {
+                let _ = txn.rollback().await;
+                panic!("Failed to pramary setup db, but function requested.");
+            }
             match user::Entity::insert_many([new_user, new_admin]).exec(&txn).await {
                 Ok(_) => match txn.commit().await {
                     Ok(_) => {
@@ -87,4 +217,93 @@ pub async fn primary_settup(db: Arc<DatabaseConnection>){
             panic!("Failed to pramary setup db, but function requested.");
         }
     }
-}
\ No newline at end of file
+}
+
+//Creates missing permissions and built-in roles.
+//Admin gets every permission on each start, user only its defaults when created.
+pub async fn seed_rbac<C: ConnectionTrait>(db: &C) -> Result<(), DbErr> {
+    for name in permission::ALL {
+        let exists = permission::Entity::find()
+            .filter(permission::Column::Name.eq(name))
+            .one(db)
+            .await?
+            .is_some();
+        if !exists {
+            permission::ActiveModel {
+                name: Set(name.to_owned()),
+                ..Default::default()
+            }
+            .insert(db)
+            .await?;
+        }
+    }
+
+    let (admin, _) = seed_role(db, role::ADMIN, "Has every permission").await?;
+    grant_missing(db, admin.id, &permission::ALL).await?;
+
+    let (user, created) = seed_role(db, role::USER, "Default role of registered users").await?;
+    if created {
+        grant_missing(db, user.id, &role::DEFAULT_USER_PERMISSIONS).await?;
+    }
+
+    Ok(())
+}
+
+async fn seed_role<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    description: &str,
+) -> Result<(role::Model, bool), DbErr> {
+    if let Some(existing) = role::Entity::find()
+        .filter(role::Column::Name.eq(name))
+        .one(db)
+        .await?
+    {
+        return Ok((existing, false));
+    }
+
+    let created = role::ActiveModel {
+        name: Set(name.to_owned()),
+        description: Set(Some(description.to_owned())),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok((created, true))
+}
+
+async fn grant_missing<C: ConnectionTrait>(
+    db: &C,
+    role_id: i32,
+    permissions: &[&str],
+) -> Result<(), DbErr> {
+    for name in permissions {
+        let permission = match permission::Entity::find()
+            .filter(permission::Column::Name.eq(*name))
+            .one(db)
+            .await?
+        {
+            Some(permission) => permission,
+            None => continue,
+        };
+
+        let granted = role_permission::Entity::find()
+            .filter(role_permission::Column::RoleId.eq(role_id))
+            .filter(role_permission::Column::PermissionId.eq(permission.id))
+            .one(db)
+            .await?
+            .is_some();
+        if !granted {
+            role_permission::ActiveModel {
+                role_id: Set(role_id),
+                permission_id: Set(permission.id),
+                ..Default::default()
+            }
+            .insert(db)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+//All prices and totals are stored as integer minor units (kopecks, cents), so sums are exact.
+//Amount is always paired with its currency.
+pub type MinorUnits = i64;
+
+#[derive(
+    Clone, Copy, Default, PartialEq, Eq, Debug, EnumIter, DeriveActiveEnum, Deserialize, Serialize,
+)]
+#[serde(rename_all = "UPPERCASE")]
+#[sea_orm(
+    enum_name = "currency_enum",
+    db_type = "String(StringLen::N(3))",
+    rs_type = "String"
+)]
+pub enum Currency {
+    #[default]
+    #[sea_orm(string_value = "RUB")]
+    Rub,
+    #[sea_orm(string_value = "USD")]
+    Usd,
+    #[sea_orm(string_value = "EUR")]
+    Eur,
+}
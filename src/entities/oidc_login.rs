@@ -0,0 +1,45 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Started OpenID Connect logins, removed once the provider redirects back
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "oidc_login")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    //Only SHA-256 of the `state` parameter is stored
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub state_hash: String,
+    //SHA-256 of the cookie set in the browser that started the login
+    #[serde(skip_serializing)]
+    pub binding_hash: String,
+    //PKCE verifier, the provider only saw its hash
+    #[serde(skip_serializing)]
+    pub code_verifier: String,
+    pub nonce: String,
+    //Set when a logged in user links the provider account
+    pub user_id: Option<i32>,
+    pub created_at: DateTimeUtc,
+    pub expires_at: DateTimeUtc,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::user::Entity",
+        from = "Column::UserId",
+        to = "crate::entities::user::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<crate::entities::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
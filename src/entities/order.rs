@@ -1,7 +1,8 @@
+use crate::entities::money::{Currency, MinorUnits};
 use crate::entities::user::Entity as User;
 use sea_orm::entity::prelude::*;
-use serde::Serialize;
-use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "order")]
@@ -10,6 +11,10 @@ pub struct Model {
     pub id: i32,
     pub status: Status,
     pub user_id: i32,
+    pub created_at: DateTimeUtc,
+    pub subtotal: MinorUnits,
+    pub total: MinorUnits,
+    pub currency: Currency,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -20,16 +25,21 @@ pub enum Relation {
         to = "crate::entities::user::Column::Id"
     )]
     User,
+    #[sea_orm(has_many = "crate::entities::order_part::Entity")]
+    OrderPart,
+    #[sea_orm(has_many = "crate::entities::order_status_history::Entity")]
+    StatusHistory,
 }
+
 impl ActiveModelBehavior for ActiveModel {}
 
-#[derive(Clone, Copy, PartialEq, Debug, EnumIter, DeriveActiveEnum, Serialize)]
+#[derive(Clone, Copy, PartialEq, Debug, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 #[sea_orm(
     enum_name = "status_enum",
     db_type = "String(StringLen::N(255))",
     rs_type = "String"
 )]
-
 pub enum Status {
     #[sea_orm(string_value = "created")]
     Created,
@@ -41,6 +51,10 @@ pub enum Status {
     Waiting,
     #[sea_orm(string_value = "received")]
     Received,
+    #[sea_orm(string_value = "cancelled")]
+    Cancelled,
+    #[sea_orm(string_value = "refunded")]
+    Refunded,
 }
 
 impl FromStr for Status {
@@ -53,20 +67,51 @@ impl FromStr for Status {
             "arriving" => Ok(Self::Arriving),
             "waiting" => Ok(Self::Waiting),
             "received" => Ok(Self::Received),
+            "cancelled" => Ok(Self::Cancelled),
+            "refunded" => Ok(Self::Refunded),
             _ => Err(format!("Invalid status: {}", s)),
         }
     }
 }
 
-impl ToString for Status {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Created => "created".to_string(),
-            Self::Processing => "processing".to_string(),
-            Self::Arriving => "arriving".to_string(),
-            Self::Waiting => "Waiting".to_string(),
-            Self::Received => "Received".to_string(),
-        }
+//Same strings as in db and in FromStr, so status survives the round trip
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            Self::Created => "created",
+            Self::Processing => "processing",
+            Self::Arriving => "arriving",
+            Self::Waiting => "waiting",
+            Self::Received => "received",
+            Self::Cancelled => "cancelled",
+            Self::Refunded => "refunded",
+        };
+        write!(f, "{value}")
+    }
+}
+
+impl Status {
+    //Order can only move forward, Received is final.
+    //Cancelled and Refunded are set only by dedicated routes, since they need a reason.
+    pub fn can_transition_to(&self, next: Status) -> bool {
+        matches!(
+            (self, next),
+            (Self::Created, Self::Processing)
+                | (Self::Processing, Self::Arriving)
+                | (Self::Arriving, Self::Waiting)
+                | (Self::Arriving, Self::Received)
+                | (Self::Waiting, Self::Received)
+        )
+    }
+
+    //Order lines can be edited by admins only until the order is sent
+    pub fn is_editable(&self) -> bool {
+        matches!(self, Self::Created | Self::Processing)
+    }
+
+    //Users may cancel only until the order is sent
+    pub fn can_be_cancelled_by_user(&self) -> bool {
+        matches!(self, Self::Created | Self::Processing)
     }
 }
 
@@ -75,3 +120,15 @@ impl Related<crate::entities::user::Entity> for Entity {
         Relation::User.def()
     }
 }
+
+impl Related<crate::entities::order_part::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::OrderPart.def()
+    }
+}
+
+impl Related<crate::entities::order_status_history::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::StatusHistory.def()
+    }
+}
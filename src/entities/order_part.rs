@@ -1,3 +1,4 @@
+use crate::entities::money::MinorUnits;
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
 
@@ -8,7 +9,13 @@ pub struct Model {
     pub id: i32,
     pub quantity: i32,
     pub product_id: i32,
+    pub variant_id: Option<i32>,
     pub order_id: i32,
+    //Snapshot of the product at checkout time, so patching a product won't change old orders
+    pub product_name: String,
+    pub category_name: String,
+    pub variant_name: Option<String>,
+    pub unit_price: MinorUnits,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -19,6 +26,14 @@ pub enum Relation {
         to = "crate::entities::product::Column::Id",
     )]
     Product,
+    #[sea_orm(
+        belongs_to = "crate::entities::product_variant::Entity",
+        from = "Column::VariantId",
+        to = "crate::entities::product_variant::Column::Id",
+        on_update = "Cascade",
+        on_delete = "SetNull"
+    )]
+    Variant,
     #[sea_orm(
         belongs_to = "crate::entities::order::Entity",
         from = "Column::OrderId",
@@ -27,6 +42,13 @@ pub enum Relation {
     Order,
 }
 
+impl Model {
+    //Variant id is nulled when the variant is deleted, its name snapshot stays
+    pub fn variant_deleted(&self) -> bool {
+        self.variant_id.is_none() && self.variant_name.is_some()
+    }
+}
+
 impl Related<crate::entities::order::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Order.def()
@@ -0,0 +1,45 @@
+use crate::entities::order::Status;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "order_status_history")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub order_id: i32,
+    //None for the entry written at checkout
+    pub from_status: Option<Status>,
+    pub to_status: Status,
+    pub changed_by: i32,
+    pub changed_at: DateTimeUtc,
+    //Reason of cancellation or refund
+    pub note: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::order::Entity",
+        from = "Column::OrderId",
+        to = "crate::entities::order::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Order,
+    #[sea_orm(
+        belongs_to = "crate::entities::user::Entity",
+        from = "Column::ChangedBy",
+        to = "crate::entities::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<crate::entities::order::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Order.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
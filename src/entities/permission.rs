@@ -0,0 +1,58 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Permissions are declared by the routers, see `auth_middleware`
+pub const PROFILE_MANAGE: &str = "profile:manage";
+//The only permission left to users that must change their password
+pub const PASSWORD_CHANGE: &str = "profile:password";
+pub const CART_USE: &str = "cart:use";
+pub const ORDER_PLACE: &str = "order:place";
+pub const CATEGORY_WRITE: &str = "category:write";
+pub const PRODUCT_WRITE: &str = "product:write";
+pub const IMAGE_WRITE: &str = "image:write";
+pub const CART_MANAGE: &str = "cart:manage";
+pub const ORDER_MANAGE: &str = "order:manage";
+pub const ORDER_REFUND: &str = "order:refund";
+pub const USER_MANAGE: &str = "user:manage";
+pub const ROLE_MANAGE: &str = "role:manage";
+pub const KEY_MANAGE: &str = "key:manage";
+
+//Every permission known to the routes, missing ones are seeded on startup
+pub const ALL: [&str; 13] = [
+    PROFILE_MANAGE,
+    PASSWORD_CHANGE,
+    CART_USE,
+    ORDER_PLACE,
+    CATEGORY_WRITE,
+    PRODUCT_WRITE,
+    IMAGE_WRITE,
+    CART_MANAGE,
+    ORDER_MANAGE,
+    ORDER_REFUND,
+    USER_MANAGE,
+    ROLE_MANAGE,
+    KEY_MANAGE,
+];
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "permission")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(unique)]
+    pub name: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "crate::entities::role_permission::Entity")]
+    RolePermission,
+}
+
+impl Related<crate::entities::role_permission::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RolePermission.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
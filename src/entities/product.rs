@@ -2,6 +2,7 @@ use sea_orm::entity::prelude::*;
 use serde::Serialize;
 use crate::entities::category::Entity as Category;
 use crate::entities::image::Entity as Image;
+use crate::entities::money::{Currency, MinorUnits};
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "products")]
@@ -10,7 +11,8 @@ pub struct Model {
     pub id: i32,
     #[sea_orm(unique)]
     pub name: String,
-    pub price: f32,
+    pub price: MinorUnits,
+    pub currency: Currency,
     #[sea_orm(column_type = "Text")]
     pub description: String,
     pub image_id: i32,
@@ -19,6 +21,9 @@ pub struct Model {
     pub is_featured: bool,
     #[sea_orm(default = true)]
     pub is_available: bool,
+    //Changed only through stock movements, see stock_movement
+    #[sea_orm(default = 0)]
+    pub stock: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -35,6 +40,10 @@ pub enum Relation {
         to = "crate::entities::image::Column::Id",
     )]
     Image,
+    #[sea_orm(has_many = "crate::entities::product_variant::Entity")]
+    Variant,
+    #[sea_orm(has_many = "crate::entities::product_image::Entity")]
+    Gallery,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -45,8 +54,21 @@ impl Related<crate::entities::category::Entity> for Entity {
     }
 }
 
+//Cover image, gallery is reached through product_image
 impl Related<crate::entities::image::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Image.def()
     }
 }
+
+impl Related<crate::entities::product_image::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Gallery.def()
+    }
+}
+
+impl Related<crate::entities::product_variant::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Variant.def()
+    }
+}
@@ -0,0 +1,50 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Gallery of the product, `product.image_id` stays the cover image
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "product_image")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub product_id: i32,
+    pub image_id: i32,
+    //Gallery is ordered by position, starting from 0
+    pub position: i32,
+    pub alt_text: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::product::Entity",
+        from = "Column::ProductId",
+        to = "crate::entities::product::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Product,
+    #[sea_orm(
+        belongs_to = "crate::entities::image::Entity",
+        from = "Column::ImageId",
+        to = "crate::entities::image::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Image,
+}
+
+impl Related<crate::entities::product::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Product.def()
+    }
+}
+
+impl Related<crate::entities::image::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Image.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+use crate::entities::money::MinorUnits;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Sellable option of the product (pack size, flavour, ...), with its own price and stock.
+//Currency is taken from the product.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "product_variant")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub product_id: i32,
+    pub name: String,
+    pub size: Option<String>,
+    pub flavour: Option<String>,
+    pub pack_count: Option<i32>,
+    pub price: MinorUnits,
+    #[sea_orm(default = true)]
+    pub is_available: bool,
+    //Changed only through stock movements, see stock_movement
+    #[sea_orm(default = 0)]
+    pub stock: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::product::Entity",
+        from = "Column::ProductId",
+        to = "crate::entities::product::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Product,
+}
+
+impl Related<crate::entities::product::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Product.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,49 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Built-in roles, seeded on startup
+pub const ADMIN: &str = "admin";
+pub const USER: &str = "user";
+
+//Permissions the built-in user role starts with, admin always gets all of them
+pub const DEFAULT_USER_PERMISSIONS: [&str; 4] = [
+    crate::entities::permission::PROFILE_MANAGE,
+    crate::entities::permission::PASSWORD_CHANGE,
+    crate::entities::permission::CART_USE,
+    crate::entities::permission::ORDER_PLACE,
+];
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "role")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    //`users.role` references the name
+    #[sea_orm(unique)]
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(has_many = "crate::entities::role_permission::Entity")]
+    RolePermission,
+}
+
+impl Related<crate::entities::role_permission::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RolePermission.def()
+    }
+}
+
+impl Related<crate::entities::permission::Entity> for Entity {
+    fn to() -> RelationDef {
+        crate::entities::role_permission::Relation::Permission.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(crate::entities::role_permission::Relation::Role.def().rev())
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,27 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+//Keys signing access tokens, see ./middleware/jwt_keys.rs
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "signing_key")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    //Sent in the `kid` header of tokens
+    #[sea_orm(unique)]
+    pub kid: String,
+    //"RS256" or "EdDSA"
+    pub algorithm: String,
+    //Base64 DER, PKCS#1 for RSA and PKCS#8 for Ed25519
+    #[serde(skip_serializing)]
+    pub private_key: String,
+    pub created_at: DateTimeUtc,
+    //Retired keys only verify tokens they signed, until `expires_at`
+    pub retired_at: Option<DateTimeUtc>,
+    pub expires_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,61 @@
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "stock_movement")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    #[sea_orm(indexed)]
+    pub product_id: i32,
+    //Set when stock of the product variant was changed
+    pub variant_id: Option<i32>,
+    //Positive when stock is added, negative when it is taken
+    pub change: i32,
+    pub reason: Reason,
+    pub order_id: Option<i32>,
+    pub changed_by: i32,
+    pub created_at: DateTimeUtc,
+    pub note: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    //Stock history is kept, product that has it can't be deleted, only hidden
+    #[sea_orm(
+        belongs_to = "crate::entities::product::Entity",
+        from = "Column::ProductId",
+        to = "crate::entities::product::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Restrict"
+    )]
+    Product,
+}
+
+impl Related<crate::entities::product::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Product.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Clone, Copy, PartialEq, Debug, EnumIter, DeriveActiveEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[sea_orm(
+    enum_name = "stock_reason_enum",
+    db_type = "String(StringLen::N(255))",
+    rs_type = "String"
+)]
+pub enum Reason {
+    #[sea_orm(string_value = "restock")]
+    Restock,
+    #[sea_orm(string_value = "adjustment")]
+    Adjustment,
+    #[sea_orm(string_value = "checkout")]
+    Checkout,
+    #[sea_orm(string_value = "cancellation")]
+    Cancellation,
+    #[sea_orm(string_value = "order_edit")]
+    OrderEdit,
+}
@@ -1,7 +1,6 @@
 use sea_orm::entity::prelude::*;
 use argon2::{password_hash::PasswordVerifier, Argon2, PasswordHash};
-use serde::{Serialize, Deserialize};
-use std::str::FromStr;
+use serde::Serialize;
 
 //use crate::entity::jwt_token;
 
@@ -13,7 +12,20 @@ pub struct Model {
     #[sea_orm(unique)]
     pub username: String,
     pub password: String,
-    pub role: Role,
+    //Used for password reset mails
+    pub email: Option<String>,
+    //Name of the role, permissions are looked up through it
+    pub role: String,
+    //Set by admin, until the password is changed only the password change route is open
+    #[sea_orm(default = false)]
+    pub must_change_password: bool,
+    //TOTP (RFC 6238) secret in base32, kept while enrollment isn't confirmed too
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    #[sea_orm(default = false)]
+    pub totp_enabled: bool,
+    //Time step of the last accepted code, so a code can't be replayed
+    pub totp_last_step: Option<i64>,
 }
 
 impl Model {
@@ -33,40 +45,21 @@ impl Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
-
-impl ActiveModelBehavior for ActiveModel {}
-
-#[derive(Clone, Copy, PartialEq, Debug, EnumIter, DeriveActiveEnum, Deserialize, Serialize)]
-#[sea_orm(
-    enum_name = "role_enum",
-    db_type = "String(StringLen::N(255))",
-    rs_type = "String"
-)]
-pub enum Role {
-    #[sea_orm(string_value = "admin")]
-    Admin,
-    #[sea_orm(string_value = "user")]
-    User,
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "crate::entities::role::Entity",
+        from = "Column::Role",
+        to = "crate::entities::role::Column::Name",
+        on_update = "Cascade",
+        on_delete = "Restrict"
+    )]
+    Role,
 }
 
-impl FromStr for Role {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "admin" => Ok(Role::Admin),
-            "user" => Ok(Role::User),
-            _ => Err(()),
-        }
+impl Related<crate::entities::role::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Role.def()
     }
 }
 
-impl ToString for Role {
-    fn to_string(&self) -> String {
-        match self {
-            Role::Admin => "admin".to_string(),
-            Role::User => "user".to_string(),
-        }
-    }
-}
\ No newline at end of file
+impl ActiveModelBehavior for ActiveModel {}
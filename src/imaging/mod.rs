@@ -0,0 +1,188 @@
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        jpeg::JpegEncoder,
+    },
+    imageops::FilterType,
+    AnimationDecoder, DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    ImageResult, Limits,
+};
+use std::io::Cursor;
+use thiserror::Error;
+
+use crate::entities::image::FileExtension;
+
+const JPEG_QUALITY: u8 = 85;
+//Sizes made after upload by width, images narrower than a size don't get it
+pub const IMAGE_SIZES: [(&str, u32); 3] = [("thumb", 160), ("medium", 640), ("large", 1280)];
+
+pub struct ResizedImage {
+    pub size: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+//Decodes and encodes the image again, which drops EXIF and other metadata.
+//EXIF orientation is applied to the pixels. CPU heavy, call it from `spawn_blocking`
+pub fn reencode(
+    data: &[u8],
+    extension: FileExtension,
+    max_pixels: u64,
+) -> Result<Vec<u8>, ImagingError> {
+    match extension {
+        FileExtension::JPG => reencode_still(data, ImageFormat::Jpeg, max_pixels),
+        FileExtension::PNG => reencode_still(data, ImageFormat::Png, max_pixels),
+        FileExtension::WEBP => reencode_still(data, ImageFormat::WebP, max_pixels),
+        FileExtension::GIF => reencode_gif(data, max_pixels),
+        //There's no AVIF decoder, it can't be stored without sanitizing
+        FileExtension::AVIF => Err(ImagingError::Unsupported(extension.to_string())),
+    }
+}
+
+//CPU heavy, call it from `spawn_blocking`
+pub fn resize(data: &[u8], extension: FileExtension) -> ImageResult<Vec<ResizedImage>> {
+    let Some(format) = image_format(extension) else {
+        return Ok(Vec::new());
+    };
+    let original = image::load_from_memory_with_format(data, format)?;
+
+    IMAGE_SIZES
+        .iter()
+        .filter(|(_, width)| original.width() > *width)
+        .map(|&(size, width)| {
+            let resized = original.resize(width, u32::MAX, FilterType::Lanczos3);
+            Ok(ResizedImage {
+                size,
+                width: resized.width(),
+                height: resized.height(),
+                data: encode(&resized, format)?,
+            })
+        })
+        .collect()
+}
+
+pub fn is_known_size(size: &str) -> bool {
+    IMAGE_SIZES.iter().any(|(name, _)| *name == size)
+}
+
+//Real format of the file by its magic bytes
+pub fn detect_format(data: &[u8]) -> Option<FileExtension> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some(FileExtension::JPG),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(FileExtension::PNG),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(FileExtension::GIF),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            Some(FileExtension::WEBP)
+        }
+        _ if is_avif(data) => Some(FileExtension::AVIF),
+        _ => None,
+    }
+}
+
+//utils
+//GIF and AVIF aren't resized, animation would be lost and there's no AVIF decoder
+fn image_format(extension: FileExtension) -> Option<ImageFormat> {
+    match extension {
+        FileExtension::JPG => Some(ImageFormat::Jpeg),
+        FileExtension::PNG => Some(ImageFormat::Png),
+        FileExtension::WEBP => Some(ImageFormat::WebP),
+        FileExtension::GIF | FileExtension::AVIF => None,
+    }
+}
+
+//ISO media file starting with `ftyp` box that lists AVIF brand as major or compatible one
+fn is_avif(data: &[u8]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false;
+    }
+    let box_size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let is_avif_brand = |brand: &[u8]| brand == b"avif" || brand == b"avis";
+
+    //Major brand, minor version, then compatible brands until the end of the box
+    is_avif_brand(&data[8..12])
+        || data
+            .get(16..box_size.min(data.len()))
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .any(is_avif_brand)
+}
+
+//Size is checked from the header, before pixels are decoded
+fn reencode_still(
+    data: &[u8],
+    format: ImageFormat,
+    max_pixels: u64,
+) -> Result<Vec<u8>, ImagingError> {
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(decoder_limits(max_pixels));
+    let mut decoder = reader.into_decoder()?;
+
+    let (width, height) = decoder.dimensions();
+    check_pixels(width as u64 * height as u64, max_pixels)?;
+
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(encode(&image, format)?)
+}
+
+//Every frame is decoded at full size, so frames count towards the limit too
+fn reencode_gif(data: &[u8], max_pixels: u64) -> Result<Vec<u8>, ImagingError> {
+    let mut decoder = GifDecoder::new(Cursor::new(data))?;
+    decoder.set_limits(decoder_limits(max_pixels))?;
+
+    let (width, height) = decoder.dimensions();
+    let frame_pixels = width as u64 * height as u64;
+    check_pixels(frame_pixels, max_pixels)?;
+
+    let mut frames = Vec::new();
+    for frame in decoder.into_frames() {
+        frames.push(frame?);
+        check_pixels(frame_pixels * frames.len() as u64, max_pixels)?;
+    }
+
+    let mut data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut data);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames)?;
+    }
+    Ok(data)
+}
+
+fn decoder_limits(max_pixels: u64) -> Limits {
+    let mut limits = Limits::default();
+    //16-bit RGBA is the biggest buffer decoders allocate
+    limits.max_alloc = Some(max_pixels * 8);
+    limits
+}
+
+fn check_pixels(pixels: u64, max_pixels: u64) -> Result<(), ImagingError> {
+    if pixels > max_pixels {
+        return Err(ImagingError::TooLarge { pixels, max_pixels });
+    }
+    Ok(())
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+    let mut data = Vec::new();
+    match format {
+        //JPEG has no alpha channel
+        ImageFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY))?,
+        _ => image.write_to(&mut Cursor::new(&mut data), format)?,
+    }
+    Ok(data)
+}
+
+#[derive(Error, Debug)]
+pub enum ImagingError {
+    #[error("Image has {pixels} pixels, the limit is {max_pixels}")]
+    TooLarge { pixels: u64, max_pixels: u64 },
+    #[error("Invalid image: {0}")]
+    Invalid(#[from] ImageError),
+    #[error("Image format {0} can't be re-encoded")]
+    Unsupported(String),
+}
@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use serde_json::json;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use super::{Mail, Mailer, MailerError};
+
+//Appends every mail as a JSON line to the file and logs it, used for development and tests
+pub struct FileMailer {
+    path: String,
+}
+
+impl FileMailer {
+    pub fn new(path: String) -> Self {
+        Self { path }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("MAIL_FILE").unwrap_or_else(|_| "mail.log".to_string()))
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError> {
+        tracing::info!(to = %mail.to, subject = %mail.subject, "Mail written to {}", self.path);
+
+        let mut line = json!({
+            "to": mail.to,
+            "subject": mail.subject,
+            "body": mail.body,
+            "sent_at": Utc::now(),
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|err| MailerError::SendFail(err.to_string()))?;
+
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(|err| MailerError::SendFail(err.to_string()))
+    }
+}
@@ -0,0 +1,42 @@
+pub mod file;
+pub mod smtp;
+
+use async_trait::async_trait;
+use dotenvy::dotenv;
+use std::sync::Arc;
+use thiserror::Error;
+
+use file::FileMailer;
+use smtp::SmtpMailer;
+
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+//Handlers get it as `Extension<Arc<dyn Mailer>>`
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError>;
+}
+
+#[derive(Error, Debug)]
+pub enum MailerError {
+    #[error("Invalid address: {0}")]
+    InvalidAddress(String),
+    #[error("Failed to build mail: {0}")]
+    BuildFail(String),
+    #[error("Failed to send mail: {0}")]
+    SendFail(String),
+}
+
+//MAILER=smtp sends through SMTP_* settings, anything else writes mails to MAIL_FILE
+pub fn from_env() -> Arc<dyn Mailer> {
+    dotenv().ok();
+    match std::env::var("MAILER").as_deref() {
+        Ok("smtp") => Arc::new(SmtpMailer::from_env()),
+        _ => Arc::new(FileMailer::from_env()),
+    }
+}
@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use super::{Mail, Mailer, MailerError};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(transport: AsyncSmtpTransport<Tokio1Executor>, from: Mailbox) -> Self {
+        Self { transport, from }
+    }
+
+    //SMTP_TLS=true uses STARTTLS, otherwise plain connection (MailHog and such)
+    pub fn from_env() -> Self {
+        let host = std::env::var("SMTP_HOST").unwrap_or_else(|_| "localhost".to_string());
+        let port = std::env::var("SMTP_PORT")
+            .ok()
+            .map(|port| port.parse::<u16>().expect("Failed to parse SMTP_PORT"))
+            .unwrap_or(1025);
+        let tls = std::env::var("SMTP_TLS").is_ok_and(|tls| tls == "true");
+
+        let mut builder = if tls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                .expect("Failed to create SMTP transport")
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)
+        }
+        .port(port);
+
+        if let (Ok(username), Ok(password)) = (
+            std::env::var("SMTP_USERNAME"),
+            std::env::var("SMTP_PASSWORD"),
+        ) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        let from = std::env::var("MAIL_FROM")
+            .unwrap_or_else(|_| "noreply@localhost".to_string())
+            .parse::<Mailbox>()
+            .expect("Failed to parse MAIL_FROM");
+
+        Self::new(builder.build(), from)
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailerError> {
+        let to = mail
+            .to
+            .parse::<Mailbox>()
+            .map_err(|err| MailerError::InvalidAddress(err.to_string()))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|err| MailerError::BuildFail(err.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|err| MailerError::SendFail(err.to_string()))
+    }
+}
@@ -1,11 +1,15 @@
 mod entities;
+mod imaging;
+mod mailer;
 mod middleware;
+mod oidc;
 mod routes;
+mod storage;
 
 use axum::{http::StatusCode, response::Response, routing::get, Json};
 use sea_orm::{Database, DatabaseConnection};
 use serde_json::json;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use crate::entities::{primary_settup, setup_schema};
 use crate::middleware::logging::{logging_middleware, to_response};
@@ -24,8 +28,12 @@ async fn main() {
     let shared_db = Arc::new(db);
 
     primary_settup(shared_db.clone()).await;
+    middleware::jwt_keys::init(shared_db.clone()).await;
 
-    let mut app = api_router(shared_db);
+    let mailer = mailer::from_env();
+    let storage = storage::from_env();
+
+    let mut app = api_router(shared_db, mailer, storage);
 
     app = app
         .route("/", get(root))
@@ -33,7 +41,13 @@ async fn main() {
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     println!("Running at {:?}", listener);
-    axum::serve(listener, app).await.unwrap();
+    //Client address is used by login throttling
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
 
 async fn root() -> Response {
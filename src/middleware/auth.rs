@@ -1,5 +1,11 @@
-use crate::entities::user::{self, Entity as UserEntity, Role};
-use crate::middleware::logging::ApiError;
+use crate::entities::{
+    api_key::{self, Entity as ApiKeyEntity},
+    permission::{self, Entity as PermissionEntity},
+    refresh_token::{self, Entity as RefreshTokenEntity},
+    role, role_permission,
+    user::{self, Entity as UserEntity},
+};
+use crate::middleware::{jwt_keys, logging::ApiError};
 
 use axum::{
     extract::{Request, State},
@@ -9,35 +15,70 @@ use axum::{
     Extension,
 };
 use chrono::{Duration, Utc};
-use dotenvy::dotenv;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use rand::RngCore;
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, JoinType, QueryFilter, QuerySelect, RelationTrait,
+};
 use serde::{Deserialize, Serialize};
-use std::{str::FromStr, sync::Arc};
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, sync::Arc};
+
+//Access tokens are short-lived, sessions are kept alive with refresh tokens
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+//Time to enter the second factor after the password was accepted
+pub const TWO_FACTOR_PENDING_TTL_MINUTES: i64 = 5;
+const TWO_FACTOR_PURPOSE: &str = "2fa";
+//API keys can be sent as bearer tokens too, the prefix tells them from access tokens
+pub const API_KEY_PREFIX: &str = "bk_";
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+//`last_used_at` of API keys isn't written more often than this
+const API_KEY_LAST_USED_PRECISION_SECONDS: i64 = 60;
 
+//`state` is the permission required by the routes behind the layer.
+//Permissions of the role are loaded once per request, nested layers reuse them.
 pub async fn auth_middleware(
-    State(state): State<Role>,
+    State(state): State<&'static str>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
     mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    let role = state;
+    let required = state;
+
+    if let Some(permissions) = req.extensions().get::<Permissions>() {
+        if permissions.has(required) {
+            return Ok(next.run(req).await);
+        }
+        req.extensions_mut().insert(ApiError::General(
+            AuthMiddlewareError::MissingPermission(required.to_string()).to_string(),
+        ));
+        return Err(StatusCode::UNAUTHORIZED);
+    }
 
+    let api_key_header = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|key| key.to_string());
     let auth_header = req
         .headers()
         .get("Authorization")
         .and_then(|h| h.to_str().ok());
 
-    let token = match auth_header {
-        Some(header) if header.starts_with("Bearer ") => match header.strip_prefix("Bearer ") {
-            Some(token) => token,
-            _ => {
-                req.extensions_mut().insert(ApiError::General(
-                    "Getting authorization token failed".to_string(),
-                ));
-                return Err(StatusCode::UNAUTHORIZED);
+    let token = match (api_key_header, auth_header) {
+        (Some(key), _) => key,
+        (None, Some(header)) if header.starts_with("Bearer ") => {
+            match header.strip_prefix("Bearer ") {
+                Some(token) => token.to_string(),
+                _ => {
+                    req.extensions_mut().insert(ApiError::General(
+                        "Getting authorization token failed".to_string(),
+                    ));
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
             }
-        },
+        }
         _ => {
             req.extensions_mut().insert(ApiError::General(
                 "Authorization bearer is not provided".to_string(),
@@ -46,15 +87,64 @@ pub async fn auth_middleware(
         }
     };
 
-    let claims: Claims = match validate_token(db.clone(), token, role).await {
-        Ok(claims) => claims,
+    let validated = if token.starts_with(API_KEY_PREFIX) {
+        validate_api_key(&*db, &token)
+            .await
+            .map(|(claims, user, key)| (claims, user, Some(key)))
+    } else {
+        validate_token(db.clone(), &token)
+            .await
+            .map(|(claims, user)| (claims, user, None))
+    };
+
+    let (claims, user, api_key) = match validated {
+        Ok(validated) => validated,
         Err(err) => {
             req.extensions_mut()
                 .insert(ApiError::General(err.to_string()));
             return Err(StatusCode::UNAUTHORIZED);
         }
     };
+
+    let mut permissions = match load_permissions(&*db, &claims.role).await {
+        Ok(permissions) => permissions,
+        Err(err) => {
+            req.extensions_mut()
+                .insert(ApiError::DbError(err.to_string()));
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    //API key gets only the scopes that the role still has
+    if let Some(key) = &api_key {
+        permissions.0.retain(|permission| key.has_scope(permission));
+    }
+
+    //Forced password reset, see `user::Model::must_change_password`
+    if user.must_change_password {
+        permissions
+            .0
+            .retain(|permission| permission == permission::PASSWORD_CHANGE);
+        if !permissions.has(required) {
+            req.extensions_mut().insert(ApiError::General(
+                AuthMiddlewareError::PasswordChangeRequired.to_string(),
+            ));
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    if !permissions.has(required) {
+        req.extensions_mut().insert(ApiError::General(
+            AuthMiddlewareError::MissingPermission(required.to_string()).to_string(),
+        ));
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
     req.extensions_mut().insert(claims);
+    req.extensions_mut().insert(permissions);
+    if let Some(key) = api_key {
+        req.extensions_mut().insert(ApiKeyAuth { key_id: key.id });
+    }
 
     Ok(next.run(req).await)
 }
@@ -63,12 +153,18 @@ pub async fn auth_middleware(
 pub struct Claims {
     pub user_id: i32,
     pub role: String,
+    //Refresh token family of the session
+    pub sid: String,
     pub exp: usize,
 }
 
-pub async fn generate_token(user_id: i32, role: String) -> Result<String, AuthMiddlewareError> {
+pub async fn generate_token(
+    user_id: i32,
+    role: String,
+    sid: String,
+) -> Result<String, AuthMiddlewareError> {
     let exp = match Utc::now()
-        .checked_add_signed(Duration::hours(24))
+        .checked_add_signed(Duration::minutes(ACCESS_TOKEN_TTL_MINUTES))
         .ok_or(AuthMiddlewareError::GenerationFail)
     {
         Ok(data) => data.timestamp() as usize,
@@ -77,62 +173,186 @@ pub async fn generate_token(user_id: i32, role: String) -> Result<String, AuthMi
         }
     };
 
-    let claims = Claims { user_id, role, exp };
+    let claims = Claims {
+        user_id,
+        role,
+        sid,
+        exp,
+    };
 
-    match encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(get_secret_key().as_bytes()),
-    ) {
+    match jwt_keys::sign(&claims) {
         Ok(token) => Ok(token),
         Err(_) => Err(AuthMiddlewareError::GenerationFail),
     }
 }
 
+//Issued by login for accounts with 2FA, only `/login/2fa` accepts it.
+//It has no role or session so it can't be decoded as access token.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingClaims {
+    pub user_id: i32,
+    pub purpose: String,
+    pub exp: usize,
+}
+
+pub fn generate_pending_token(user_id: i32) -> Result<String, AuthMiddlewareError> {
+    let exp = Utc::now()
+        .checked_add_signed(Duration::minutes(TWO_FACTOR_PENDING_TTL_MINUTES))
+        .ok_or(AuthMiddlewareError::GenerationFail)?
+        .timestamp() as usize;
+
+    let claims = PendingClaims {
+        user_id,
+        purpose: TWO_FACTOR_PURPOSE.to_owned(),
+        exp,
+    };
+
+    jwt_keys::sign(&claims).map_err(|_| AuthMiddlewareError::GenerationFail)
+}
+
+pub fn validate_pending_token(token: &str) -> Result<PendingClaims, AuthMiddlewareError> {
+    match jwt_keys::verify::<PendingClaims>(token) {
+        Ok(claims) if claims.purpose == TWO_FACTOR_PURPOSE => Ok(claims),
+        _ => Err(AuthMiddlewareError::TokenExpired),
+    }
+}
+
+//Present when the request was authenticated with an API key
+#[derive(Clone, Debug)]
+pub struct ApiKeyAuth {
+    pub key_id: i32,
+}
+
+//Permissions granted to the role of the authenticated user
+#[derive(Clone, Debug)]
+pub struct Permissions(pub HashSet<String>);
+
+impl Permissions {
+    pub fn has(&self, permission: &str) -> bool {
+        self.0.contains(permission)
+    }
+
+    //True when every permission of `other` is granted here too
+    pub fn covers(&self, other: &Permissions) -> bool {
+        other.0.is_subset(&self.0)
+    }
+}
+
+pub async fn load_permissions<C: ConnectionTrait>(
+    db: &C,
+    role_name: &str,
+) -> Result<Permissions, DbErr> {
+    let names: Vec<String> = PermissionEntity::find()
+        .join(
+            JoinType::InnerJoin,
+            permission::Relation::RolePermission.def(),
+        )
+        .join(JoinType::InnerJoin, role_permission::Relation::Role.def())
+        .filter(role::Column::Name.eq(role_name))
+        .select_only()
+        .column(permission::Column::Name)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    Ok(Permissions(names.into_iter().collect()))
+}
+
 pub async fn validate_token(
     db: Arc<DatabaseConnection>,
     token: &str,
-    req_role: Role,
-) -> Result<Claims, AuthMiddlewareError> {
-    let mut validation = Validation::new(Algorithm::HS256);
-    validation.validate_exp = true;
-
-    let token_data = match decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(get_secret_key().as_bytes()),
-        &validation,
-    ) {
-        Ok(data) => data,
+) -> Result<(Claims, user::Model), AuthMiddlewareError> {
+    let claims = match jwt_keys::verify::<Claims>(token) {
+        Ok(claims) => claims,
         Err(_) => {
             return Err(AuthMiddlewareError::TokenExpired);
         }
     };
 
-    let claims = token_data.claims;
-
-    if let Ok(role) = Role::from_str(&claims.role) {
-        match UserEntity::find_by_id(claims.user_id)
-            .filter(user::Column::Role.eq(role))
-            .one(&*db)
-            .await
-        {
-            Ok(Some(_)) => {
-                if role == req_role {
-                    return Ok(claims);
-                } else {
-                    return Err(AuthMiddlewareError::InvalidUserOrRole);
-                }
-            }
-            Ok(None) => {
-                return Err(AuthMiddlewareError::InvalidUserOrRole);
-            }
-            Err(_) => {
-                return Err(AuthMiddlewareError::InternalServerError);
-            }
+    //Token is dropped once the user gets another role
+    let user = match UserEntity::find_by_id(claims.user_id)
+        .filter(user::Column::Role.eq(&claims.role))
+        .one(&*db)
+        .await
+    {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(AuthMiddlewareError::InvalidUserOrRole);
         }
+        Err(_) => {
+            return Err(AuthMiddlewareError::InternalServerError);
+        }
+    };
+
+    //Session is alive while its family has tokens that weren't revoked
+    match RefreshTokenEntity::find()
+        .filter(refresh_token::Column::FamilyId.eq(&claims.sid))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .one(&*db)
+        .await
+    {
+        Ok(Some(_)) => Ok((claims, user)),
+        Ok(None) => Err(AuthMiddlewareError::TokenRevoked),
+        Err(_) => Err(AuthMiddlewareError::InternalServerError),
     }
+}
 
-    Err(AuthMiddlewareError::ValidationFail)
+//Claims of API key requests carry the role of the owner and "api_key:<id>" as session
+pub async fn validate_api_key<C: ConnectionTrait>(
+    db: &C,
+    key: &str,
+) -> Result<(Claims, user::Model, api_key::Model), AuthMiddlewareError> {
+    let now = Utc::now();
+
+    let (key, user) = match ApiKeyEntity::find()
+        .filter(api_key::Column::KeyHash.eq(hash_token(key)))
+        .find_also_related(UserEntity)
+        .one(db)
+        .await
+    {
+        Ok(Some((key, Some(user)))) if key.expires_at > now => (key, user),
+        Ok(Some((_, Some(_)))) => return Err(AuthMiddlewareError::TokenExpired),
+        Ok(_) => return Err(AuthMiddlewareError::InvalidApiKey),
+        Err(_) => return Err(AuthMiddlewareError::InternalServerError),
+    };
+
+    let stale = now - Duration::seconds(API_KEY_LAST_USED_PRECISION_SECONDS);
+    if ApiKeyEntity::update_many()
+        .col_expr(api_key::Column::LastUsedAt, Expr::value(now))
+        .filter(api_key::Column::Id.eq(key.id))
+        .filter(
+            Condition::any()
+                .add(api_key::Column::LastUsedAt.is_null())
+                .add(api_key::Column::LastUsedAt.lt(stale)),
+        )
+        .exec(db)
+        .await
+        .is_err()
+    {
+        return Err(AuthMiddlewareError::InternalServerError);
+    }
+
+    let claims = Claims {
+        user_id: user.id,
+        role: user.role.clone(),
+        sid: format!("api_key:{}", key.id),
+        exp: key.expires_at.timestamp() as usize,
+    };
+
+    Ok((claims, user, key))
+}
+
+//Returns random opaque token (refresh, password reset) and its hash, only the hash should be stored
+pub fn generate_random_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = hex::encode(bytes);
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 use thiserror::Error;
@@ -143,15 +363,16 @@ pub enum AuthMiddlewareError {
     InvalidUserOrRole,
     #[error("Token expired")]
     TokenExpired,
-    #[error("Failed to validate token")]
-    ValidationFail,
+    #[error("Token revoked")]
+    TokenRevoked,
+    #[error("Invalid API key")]
+    InvalidApiKey,
+    #[error("Missing permission {0}")]
+    MissingPermission(String),
+    #[error("Password change required")]
+    PasswordChangeRequired,
     #[error("Failed to generate token")]
     GenerationFail,
     #[error("Internal server error")]
     InternalServerError,
 }
-
-fn get_secret_key() -> String {
-    dotenv().ok();
-    std::env::var("SECRET").expect("SECRET not found in .env file")
-}
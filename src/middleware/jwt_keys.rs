@@ -0,0 +1,315 @@
+use crate::entities::signing_key::{self, Entity as SigningKeyEntity};
+use crate::middleware::auth::{ACCESS_TOKEN_TTL_MINUTES, TWO_FACTOR_PENDING_TTL_MINUTES};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, Duration, Utc};
+use dotenvy::dotenv;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use once_cell::sync::Lazy;
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey,
+};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+use uuid::Uuid;
+
+const RSA_KEY_BITS: usize = 2048;
+//Same as default leeway of `Validation`
+const TOKEN_LEEWAY_SECONDS: i64 = 60;
+const KEY_CHECK_INTERVAL_SECONDS: u64 = 300;
+
+//Keys are kept in the database, this is a parsed copy used to sign and verify tokens.
+//Newest key that isn't retired signs, the others only verify.
+static KEYS: Lazy<RwLock<Vec<Arc<LoadedKey>>>> = Lazy::new(|| RwLock::new(Vec::new()));
+static CONFIG: Lazy<KeyConfig> = Lazy::new(|| {
+    dotenv().ok();
+    KeyConfig {
+        algorithm: std::env::var("JWT_ALGORITHM")
+            .ok()
+            .and_then(|alg| Algorithm::from_str(&alg).ok())
+            .filter(|alg| matches!(alg, Algorithm::RS256 | Algorithm::EdDSA))
+            .unwrap_or(Algorithm::EdDSA),
+        rotation_days: std::env::var("JWT_KEY_ROTATION_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(30),
+    }
+});
+
+struct KeyConfig {
+    //Algorithm of new keys, keys of the other one still verify
+    algorithm: Algorithm,
+    rotation_days: i64,
+}
+
+struct LoadedKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Value,
+    retired: bool,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl LoadedKey {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+//Loads keys, creates the first one if needed and keeps rotating them in background
+pub async fn init(db: Arc<DatabaseConnection>) {
+    rotate_if_due(&*db)
+        .await
+        .expect("Failed to set up signing keys");
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(KEY_CHECK_INTERVAL_SECONDS));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(err) = rotate_if_due(&*db).await {
+                tracing::error!(event = "signing_key_rotation_failed", error = %err);
+            }
+        }
+    });
+}
+
+//Rotates once the signing key is older than JWT_KEY_ROTATION_DAYS, reloads keys otherwise
+pub async fn rotate_if_due<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+) -> Result<(), JwtKeyError> {
+    let active = SigningKeyEntity::find()
+        .filter(signing_key::Column::RetiredAt.is_null())
+        .order_by_desc(signing_key::Column::CreatedAt)
+        .one(db)
+        .await?;
+
+    match active {
+        Some(key) if key.created_at + Duration::days(CONFIG.rotation_days) > Utc::now() => {
+            reload(db).await
+        }
+        _ => rotate(db).await.map(|_| ()),
+    }
+}
+
+//New key signs from now on, the previous ones verify until tokens signed by them expire
+pub async fn rotate<C: ConnectionTrait + TransactionTrait>(db: &C) -> Result<String, JwtKeyError> {
+    let algorithm = CONFIG.algorithm;
+    //RSA key generation takes a while
+    let der = tokio::task::spawn_blocking(move || generate_key(algorithm))
+        .await
+        .map_err(|err| JwtKeyError::GenerationFail(err.to_string()))??;
+
+    let now = Utc::now();
+    let kid = Uuid::new_v4().to_string();
+    let expires_at = now
+        + Duration::minutes(ACCESS_TOKEN_TTL_MINUTES.max(TWO_FACTOR_PENDING_TTL_MINUTES))
+        + Duration::seconds(TOKEN_LEEWAY_SECONDS);
+
+    let txn = db.begin().await?;
+    SigningKeyEntity::update_many()
+        .col_expr(signing_key::Column::RetiredAt, Expr::value(now))
+        .col_expr(signing_key::Column::ExpiresAt, Expr::value(expires_at))
+        .filter(signing_key::Column::RetiredAt.is_null())
+        .exec(&txn)
+        .await?;
+    signing_key::ActiveModel {
+        kid: Set(kid.clone()),
+        algorithm: Set(format!("{:?}", algorithm)),
+        private_key: Set(STANDARD.encode(der)),
+        created_at: Set(now),
+        retired_at: Set(None),
+        expires_at: Set(None),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+    txn.commit().await?;
+
+    reload(db).await?;
+    Ok(kid)
+}
+
+//Drops expired keys and refreshes the parsed copy
+async fn reload<C: ConnectionTrait>(db: &C) -> Result<(), JwtKeyError> {
+    SigningKeyEntity::delete_many()
+        .filter(signing_key::Column::ExpiresAt.lte(Utc::now()))
+        .exec(db)
+        .await?;
+
+    let models = SigningKeyEntity::find()
+        .order_by_desc(signing_key::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    let keys = models
+        .into_iter()
+        .map(|model| load_key(model).map(Arc::new))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    *KEYS.write().unwrap_or_else(|err| err.into_inner()) = keys;
+    Ok(())
+}
+
+pub fn sign<T: Serialize>(claims: &T) -> Result<String, JwtKeyError> {
+    let key = KEYS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .find(|key| !key.retired)
+        .cloned()
+        .ok_or(JwtKeyError::NoSigningKey)?;
+
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    encode(&header, claims, &key.encoding).map_err(|err| JwtKeyError::SignFail(err.to_string()))
+}
+
+//Key is picked by `kid`, only the algorithm of that key is accepted
+pub fn verify<T: DeserializeOwned>(token: &str) -> Result<T, JwtKeyError> {
+    let header = decode_header(token).map_err(|err| JwtKeyError::InvalidToken(err.to_string()))?;
+    let kid = header.kid.ok_or(JwtKeyError::UnknownKey)?;
+
+    let key = KEYS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .find(|key| key.kid == kid && !key.is_expired(Utc::now()))
+        .cloned()
+        .ok_or(JwtKeyError::UnknownKey)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.validate_exp = true;
+
+    decode::<T>(token, &key.decoding, &validation)
+        .map(|data| data.claims)
+        .map_err(|err| JwtKeyError::InvalidToken(err.to_string()))
+}
+
+//Public keys of every key that may still verify tokens
+pub fn jwks() -> Value {
+    let now = Utc::now();
+    let keys: Vec<Value> = KEYS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+        .filter(|key| !key.is_expired(now))
+        .map(|key| key.jwk.clone())
+        .collect();
+
+    json!({ "keys": keys })
+}
+
+//utils
+fn generate_key(algorithm: Algorithm) -> Result<Vec<u8>, JwtKeyError> {
+    match algorithm {
+        Algorithm::EdDSA => Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map(|document| document.as_ref().to_vec())
+            .map_err(|err| JwtKeyError::GenerationFail(err.to_string())),
+        Algorithm::RS256 => RsaPrivateKey::new(&mut rand::rngs::OsRng, RSA_KEY_BITS)
+            .map_err(|err| JwtKeyError::GenerationFail(err.to_string()))?
+            .to_pkcs1_der()
+            .map(|document| document.as_bytes().to_vec())
+            .map_err(|err| JwtKeyError::GenerationFail(err.to_string())),
+        other => Err(JwtKeyError::UnsupportedAlgorithm(format!("{:?}", other))),
+    }
+}
+
+fn load_key(model: signing_key::Model) -> Result<LoadedKey, JwtKeyError> {
+    let invalid = |err: String| JwtKeyError::InvalidKey(model.kid.clone(), err);
+
+    let der = STANDARD
+        .decode(&model.private_key)
+        .map_err(|err| invalid(err.to_string()))?;
+    let algorithm =
+        Algorithm::from_str(&model.algorithm).map_err(|err| invalid(err.to_string()))?;
+
+    let (encoding, decoding, mut jwk) = match algorithm {
+        Algorithm::EdDSA => {
+            let pair = Ed25519KeyPair::from_pkcs8(&der).map_err(|err| invalid(err.to_string()))?;
+            let public = pair.public_key().as_ref();
+            (
+                EncodingKey::from_ed_der(&der),
+                DecodingKey::from_ed_der(public),
+                json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": URL_SAFE_NO_PAD.encode(public)
+                }),
+            )
+        }
+        Algorithm::RS256 => {
+            let key =
+                RsaPrivateKey::from_pkcs1_der(&der).map_err(|err| invalid(err.to_string()))?;
+            let (n, e) = (key.n().to_bytes_be(), key.e().to_bytes_be());
+            (
+                EncodingKey::from_rsa_der(&der),
+                DecodingKey::from_rsa_raw_components(&n, &e),
+                json!({
+                    "kty": "RSA",
+                    "n": URL_SAFE_NO_PAD.encode(&n),
+                    "e": URL_SAFE_NO_PAD.encode(&e)
+                }),
+            )
+        }
+        other => return Err(JwtKeyError::UnsupportedAlgorithm(format!("{:?}", other))),
+    };
+
+    jwk["kid"] = json!(model.kid);
+    jwk["alg"] = json!(model.algorithm);
+    jwk["use"] = json!("sig");
+
+    Ok(LoadedKey {
+        kid: model.kid,
+        algorithm,
+        encoding,
+        decoding,
+        jwk,
+        retired: model.retired_at.is_some(),
+        expires_at: model.expires_at,
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum JwtKeyError {
+    #[error("No signing key")]
+    NoSigningKey,
+    #[error("Unknown key")]
+    UnknownKey,
+    #[error("Invalid key {0}: {1}")]
+    InvalidKey(String, String),
+    #[error("Unsupported algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("Failed to generate key: {0}")]
+    GenerationFail(String),
+    #[error("Failed to sign token: {0}")]
+    SignFail(String),
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+    #[error("Database error: {0}")]
+    DbError(#[from] DbErr),
+}
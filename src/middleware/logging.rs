@@ -15,6 +15,17 @@ pub async fn logging_middleware(req: Request, next: Next) -> Response {
 
     let status = response.status();
     let elapsed = start.elapsed();
+    if let Some(failure) = response.extensions().get::<LoginFailure>() {
+        error!(
+            event = "login_failed",
+            username = %failure.username,
+            ip = %failure.ip,
+            reason = failure.reason,
+            failed_attempts = ?failure.failed_attempts,
+            retry_after = ?failure.retry_after,
+            "Failed login"
+        );
+    }
     println!(
         "\nCalled: {} '{}'\n> Status: {}\n> Time: {:#?}",
         method, uri, status, elapsed
@@ -50,6 +61,16 @@ pub async fn logging_middleware(req: Request, next: Next) -> Response {
     response
 }
 
+//Attached to login responses, logged as a separate structured record
+#[derive(Clone, Debug)]
+pub struct LoginFailure {
+    pub username: String,
+    pub ip: String,
+    pub reason: &'static str, //"invalid_credentials" or "locked"
+    pub failed_attempts: Option<i32>, //failed attempts of the username, None when rejected as locked
+    pub retry_after: Option<i64>, //seconds until the lock ends
+}
+
 #[derive(Clone, Debug)]
 pub enum ApiError {
     TransactionCreationFailed,
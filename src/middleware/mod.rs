@@ -1,2 +1,3 @@
 pub mod auth;
+pub mod jwt_keys;
 pub mod logging;
\ No newline at end of file
@@ -0,0 +1,252 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dotenvy::dotenv;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{AlgorithmParameters, Jwk},
+    Algorithm, DecodingKey, Validation,
+};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::sync::OnceCell;
+
+//OpenID Connect relying party, works with any provider that supports discovery
+
+const HTTP_TIMEOUT_SECONDS: u64 = 10;
+//Asymmetric algorithms only, the client secret is never used to verify tokens
+const ID_TOKEN_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::EdDSA,
+];
+
+static CONFIG: Lazy<Option<OidcConfig>> = Lazy::new(|| {
+    dotenv().ok();
+    let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+    Some(OidcConfig {
+        issuer: env("OIDC_ISSUER")?.trim_end_matches('/').to_string(),
+        client_id: env("OIDC_CLIENT_ID")?,
+        client_secret: env("OIDC_CLIENT_SECRET"),
+        redirect_uri: env("OIDC_REDIRECT_URI")?,
+        scopes: env("OIDC_SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+    })
+});
+static HTTP: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .timeout(std::time::Duration::from_secs(HTTP_TIMEOUT_SECONDS))
+        .build()
+        .expect("Failed to build HTTP client")
+});
+//Discovery is fetched on the first login, failed fetches are retried on the next one
+static PROVIDER: OnceCell<ProviderMetadata> = OnceCell::const_new();
+
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    //Public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+//Claims of the ID token used to find or create the user
+#[derive(Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+//None when OIDC_ISSUER, OIDC_CLIENT_ID or OIDC_REDIRECT_URI isn't set
+pub fn config() -> Option<&'static OidcConfig> {
+    CONFIG.as_ref()
+}
+
+pub async fn provider(config: &OidcConfig) -> Result<&'static ProviderMetadata, OidcError> {
+    PROVIDER
+        .get_or_try_init(|| async {
+            let url = format!("{}/.well-known/openid-configuration", config.issuer);
+            let metadata: ProviderMetadata = HTTP
+                .get(&url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|err| OidcError::Discovery(err.to_string()))?
+                .json()
+                .await
+                .map_err(|err| OidcError::Discovery(err.to_string()))?;
+
+            //Required by OpenID Connect Discovery, tokens are checked against it later
+            if metadata.issuer.trim_end_matches('/') != config.issuer {
+                return Err(OidcError::Discovery(format!(
+                    "Issuer {} doesn't match {}",
+                    metadata.issuer, config.issuer
+                )));
+            }
+            Ok(metadata)
+        })
+        .await
+}
+
+//Returns PKCE code verifier and its S256 challenge
+pub fn pkce_pair() -> (String, String) {
+    let verifier = random_string();
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+    (verifier, challenge)
+}
+
+pub fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn authorization_url(
+    config: &OidcConfig,
+    provider: &ProviderMetadata,
+    state: &str,
+    nonce: &str,
+    code_challenge: &str,
+) -> Result<String, OidcError> {
+    Url::parse_with_params(
+        &provider.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", &config.scopes),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", code_challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map(|url| url.to_string())
+    .map_err(|err| OidcError::Discovery(err.to_string()))
+}
+
+//Exchanges the authorization code and returns claims of the verified ID token
+pub async fn exchange_code(
+    config: &OidcConfig,
+    provider: &ProviderMetadata,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &config.redirect_uri),
+        ("client_id", &config.client_id),
+        ("code_verifier", code_verifier),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret));
+    }
+
+    let response: TokenResponse = HTTP
+        .post(&provider.token_endpoint)
+        .form(&form)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| OidcError::TokenExchange(err.to_string()))?
+        .json()
+        .await
+        .map_err(|err| OidcError::TokenExchange(err.to_string()))?;
+
+    let claims = verify_id_token(config, provider, &response.id_token).await?;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcError::InvalidIdToken("Nonce mismatch".to_string()));
+    }
+    Ok(claims)
+}
+
+//Keys are fetched every time, logins are rare and providers rotate keys
+async fn verify_id_token(
+    config: &OidcConfig,
+    provider: &ProviderMetadata,
+    id_token: &str,
+) -> Result<IdTokenClaims, OidcError> {
+    let invalid = |err: String| OidcError::InvalidIdToken(err);
+
+    let header = decode_header(id_token).map_err(|err| invalid(err.to_string()))?;
+    if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+        return Err(invalid(format!("Algorithm {:?} isn't allowed", header.alg)));
+    }
+
+    let jwks: serde_json::Value = HTTP
+        .get(&provider.jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| OidcError::Discovery(err.to_string()))?
+        .json()
+        .await
+        .map_err(|err| OidcError::Discovery(err.to_string()))?;
+
+    //Keys the library doesn't know (e.g. encryption keys) are skipped
+    let keys: Vec<Jwk> = jwks["keys"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|key| serde_json::from_value(key.clone()).ok())
+        .collect();
+
+    //Without `kid` the provider has to publish a single key
+    let jwk = match &header.kid {
+        Some(kid) => keys
+            .iter()
+            .find(|key| key.common.key_id.as_deref() == Some(kid.as_str())),
+        None if keys.len() == 1 => keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| invalid("Signing key not found".to_string()))?;
+    if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+        return Err(invalid("Symmetric keys aren't allowed".to_string()));
+    }
+    let key = DecodingKey::from_jwk(jwk).map_err(|err| invalid(err.to_string()))?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&provider.issuer]);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+    decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map(|data| data.claims)
+        .map_err(|err| invalid(err.to_string()))
+}
+
+#[derive(Error, Debug)]
+pub enum OidcError {
+    #[error("Provider discovery failed: {0}")]
+    Discovery(String),
+    #[error("Code exchange failed: {0}")]
+    TokenExchange(String),
+    #[error("Invalid ID token: {0}")]
+    InvalidIdToken(String),
+}
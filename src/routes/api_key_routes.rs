@@ -0,0 +1,313 @@
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    middleware,
+    response::Response,
+    routing::{delete, get},
+    Json, Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::entities::{api_key, permission};
+use crate::middleware::{
+    auth::{
+        auth_middleware, generate_random_token, hash_token, ApiKeyAuth, Claims, Permissions,
+        API_KEY_PREFIX,
+    },
+    logging::{to_response, ApiError},
+};
+
+//Enough to tell keys apart, the rest of the key is never shown again
+const API_KEY_VISIBLE_CHARS: usize = 8;
+
+//ROUTERS
+pub fn api_key_routes() -> Router {
+    Router::new()
+        .route("/profile/api-key", get(get_own_keys).post(create_key))
+        .route("/profile/api-key/:id", delete(delete_own_key))
+        .layer(middleware::from_fn_with_state(
+            permission::PROFILE_MANAGE,
+            auth_middleware,
+        ))
+}
+
+pub fn admin_api_key_routes() -> Router {
+    Router::new()
+        .route("/api-key", get(get_keys))
+        .route("/api-key/:id", delete(admin_delete_key))
+        .layer(middleware::from_fn_with_state(
+            permission::USER_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+//Scopes are limited to permissions the user has. The key is in the response only once.
+async fn create_key(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Extension(permissions): Extension<Permissions>,
+    api_key_auth: Option<Extension<ApiKeyAuth>>,
+    Json(payload): Json<CreateApiKey>,
+) -> Response {
+    if let Some(response) = reject_api_key_auth(api_key_auth) {
+        return response;
+    }
+
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Key needs a name up to 50 characters, scopes and expiry of 1 to 365 days"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let mut scopes: Vec<&str> = Vec::new();
+    for scope in &payload.scopes {
+        if !permission::ALL.contains(&scope.as_str()) {
+            let tmp = format!("Unknown permission {}", scope);
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            );
+        }
+        if !permissions.has(scope) {
+            let tmp = format!("Permission {} can't be granted", scope);
+            return to_response(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        if !scopes.contains(&scope.as_str()) {
+            scopes.push(scope.as_str());
+        }
+    }
+
+    let (secret, _) = generate_random_token();
+    let key = format!("{}{}", API_KEY_PREFIX, secret);
+    let now = Utc::now();
+
+    let result = api_key::ActiveModel {
+        user_id: Set(claims.user_id),
+        name: Set(payload.name),
+        key_prefix: Set(key[..API_KEY_PREFIX.len() + API_KEY_VISIBLE_CHARS].to_string()),
+        key_hash: Set(hash_token(&key)),
+        scopes: Set(scopes.join(" ")),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::days(payload.expires_in_days)),
+        last_used_at: Set(None),
+        ..Default::default()
+    }
+    .insert(&*db)
+    .await;
+
+    match result {
+        Ok(model) => to_response(
+            (
+                StatusCode::CREATED,
+                Json(json!({
+                    "message": "API key created, it won't be shown again",
+                    "key": key,
+                    "api_key": ApiKeyResponse::from(model)
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn get_own_keys(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    find_keys(&db, Some(claims.user_id)).await
+}
+
+async fn delete_own_key(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    api_key_auth: Option<Extension<ApiKeyAuth>>,
+    Path(id): Path<i32>,
+) -> Response {
+    if let Some(response) = reject_api_key_auth(api_key_auth) {
+        return response;
+    }
+
+    delete_key(&db, id, Some(claims.user_id)).await
+}
+
+async fn get_keys(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Query(query): Query<ApiKeysQuery>,
+) -> Response {
+    find_keys(&db, query.user_id).await
+}
+
+async fn admin_delete_key(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Path(id): Path<i32>,
+) -> Response {
+    delete_key(&db, id, None).await
+}
+
+//utils
+//Keys can't be used to create more keys or to outlive their expiry
+fn reject_api_key_auth(api_key_auth: Option<Extension<ApiKeyAuth>>) -> Option<Response> {
+    api_key_auth.map(|Extension(auth)| {
+        to_response(
+            (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "API keys can't be managed with an API key"
+                })),
+            ),
+            Err(ApiError::General(format!(
+                "API key {} tried to manage API keys",
+                auth.key_id
+            ))),
+        )
+    })
+}
+
+async fn find_keys(db: &DatabaseConnection, user_id: Option<i32>) -> Response {
+    let mut finder = api_key::Entity::find().order_by_asc(api_key::Column::Id);
+    if let Some(user_id) = user_id {
+        finder = finder.filter(api_key::Column::UserId.eq(user_id));
+    }
+
+    match finder.all(db).await {
+        Ok(models) => to_response(
+            Json(
+                models
+                    .into_iter()
+                    .map(ApiKeyResponse::from)
+                    .collect::<Vec<_>>(),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//`user_id` limits deleting to keys of that user
+async fn delete_key(db: &DatabaseConnection, id: i32, user_id: Option<i32>) -> Response {
+    let mut query = api_key::Entity::delete_many().filter(api_key::Column::Id.eq(id));
+    if let Some(user_id) = user_id {
+        query = query.filter(api_key::Column::UserId.eq(user_id));
+    }
+
+    match query.exec(db).await {
+        Ok(result) if result.rows_affected > 0 => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "message": "API key deleted"
+                })),
+            ),
+            Ok(()),
+        ),
+        Ok(_) => to_response(
+            (
+                StatusCode::NOT_FOUND,
+                Json(json!({
+                    "error": "API key not found"
+                })),
+            ),
+            Err(ApiError::General("API key not found".to_string())),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Structs
+#[derive(Deserialize, Validate)]
+struct CreateApiKey {
+    #[validate(length(min = 1, max = 50))]
+    name: String,
+    #[validate(length(min = 1))]
+    scopes: Vec<String>,
+    #[validate(range(min = 1, max = 365))]
+    expires_in_days: i64,
+}
+
+#[derive(Deserialize)]
+struct ApiKeysQuery {
+    user_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct ApiKeyResponse {
+    id: i32,
+    user_id: i32,
+    name: String,
+    key_prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<api_key::Model> for ApiKeyResponse {
+    fn from(model: api_key::Model) -> Self {
+        ApiKeyResponse {
+            scopes: model
+                .scope_list()
+                .into_iter()
+                .map(|scope| scope.to_string())
+                .collect(),
+            id: model.id,
+            user_id: model.user_id,
+            name: model.name,
+            key_prefix: model.key_prefix,
+            created_at: model.created_at,
+            expires_at: model.expires_at,
+            last_used_at: model.last_used_at,
+        }
+    }
+}
@@ -3,34 +3,58 @@ use argon2::{
     Argon2,
 };
 use axum::{
-    extract::{Extension, Path, Query},
-    http::StatusCode,
+    extract::{ConnectInfo, Extension, Path, Query},
+    http::{header, StatusCode},
     middleware,
     response::Response,
     routing::{delete, get, post},
     Json, Router,
 };
+use chrono::{DateTime, Duration, Utc};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
-    FromQueryResult, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DatabaseTransaction, DbErr, EntityTrait, FromQueryResult, QueryFilter, QueryOrder, QuerySelect,
+    Set, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::sync::Arc;
+use std::{net::SocketAddr, str::FromStr, sync::Arc};
 use validator::Validate;
 
-use crate::entities::user::{self, Entity as UserEntity, Role};
+use crate::entities::{
+    login_throttle, password_reset_token, permission, refresh_token, role,
+    user::{self, Entity as UserEntity},
+};
+use crate::mailer::{Mail, Mailer};
 use crate::middleware::{
-    auth::{auth_middleware, generate_token},
-    logging::{to_response, ApiError},
+    auth::{
+        auth_middleware, generate_pending_token, generate_random_token, generate_token, hash_token,
+        load_permissions, validate_pending_token, Permissions, ACCESS_TOKEN_TTL_MINUTES, REFRESH_TOKEN_TTL_DAYS,
+        TWO_FACTOR_PENDING_TTL_MINUTES,
+    },
+    logging::{to_response, ApiError, LoginFailure},
 };
+use crate::routes::{role_routes::find_role, two_factor_routes::verify_second_factor};
+use dotenvy::dotenv;
+use uuid::Uuid;
+
+//Reset tokens are sent by mail, see `forgot_password`
+const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+const LOGIN_LOCKED: &str = "Too many failed login attempts, try again later";
 
 pub fn auth_routes() -> Router {
+    //Bad throttle settings fail at boot, not at the first failed login
+    Lazy::force(&LOGIN_THROTTLE);
     Router::new()
         .route("/register", post(register_user))
         .route("/login", post(login))
+        .route("/login/2fa", post(login_two_factor))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
 }
 
 pub fn admin_users_routes() -> Router {
@@ -38,7 +62,11 @@ pub fn admin_users_routes() -> Router {
     Router::new()
         .route("/user", get(get_users).post(create_user))
         .route("/user/:id", delete(admin_delete_user).patch(patch_user))
-        .layer(middleware::from_fn_with_state(Role::Admin, auth_middleware))
+        .route("/user/:id/password-reset", post(force_password_reset))
+        .layer(middleware::from_fn_with_state(
+            permission::USER_MANAGE,
+            auth_middleware,
+        ))
 }
 
 // ROUTES
@@ -51,7 +79,7 @@ async fn register_user(
             (
                 StatusCode::BAD_REQUEST,
                 Json(json!({
-                    "error": "Failed to validate username or password"
+                    "error": "Failed to validate username, password or email"
                 })),
             ),
             Err(ApiError::ValidationFail(err.to_string())),
@@ -91,20 +119,34 @@ async fn register_user(
     let new_user = user::ActiveModel {
         username: Set(payload.username),
         password: Set(password),
-        role: Set(Role::User),
+        email: Set(payload.email),
+        role: Set(role::USER.to_owned()),
+        must_change_password: Set(false),
+        totp_enabled: Set(false),
         ..Default::default()
     };
 
     match user::Entity::insert(new_user).exec(&txn).await {
-        Ok(_) => to_response(
-            (
-                StatusCode::CREATED,
-                Json(json!({
-                    "message": "User registered successfully"
-                })),
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "message": "User registered successfully"
+                    })),
+                ),
+                Ok(()),
             ),
-            Ok(()),
-        ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
         Err(err) => to_response(
             (
                 StatusCode::CONFLICT,
@@ -119,6 +161,7 @@ async fn register_user(
 
 async fn login(
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Json(payload): Json<UserLogin>,
 ) -> Response {
     if let Some(err) = payload.validate().err() {
@@ -148,81 +191,100 @@ async fn login(
         }
     };
 
+    let ip = addr.ip().to_string();
+    let keys = [user_throttle_key(&payload.username), format!("ip:{}", ip)];
+
+    //Locked logins are rejected before checking the password
+    match locked_for(&txn, &keys).await {
+        Ok(Some(retry_after)) => {
+            let mut response = too_many_attempts(retry_after, LOGIN_LOCKED);
+            response.extensions_mut().insert(LoginFailure {
+                username: payload.username,
+                ip,
+                reason: "locked",
+                failed_attempts: None,
+                retry_after: Some(retry_after),
+            });
+            return response;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured".to_string()
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
     let result = UserEntity::find()
         .filter(user::Column::Username.eq(&*payload.username))
         .one(&txn)
         .await;
 
-    match result {
-        Ok(Some(model)) => match model.check_hash(&payload.password.clone()) {
-            Ok(()) => match generate_token(model.id, model.role.to_string()).await {
-                Ok(token) => to_response(
-                    (
-                        StatusCode::OK,
-                        Json(json!({
-                            "token": token
-                        })),
-                    ),
-                    Ok(()),
-                ),
-                Err(err) => to_response(
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(json!({
-                            "error": "Internal server error"
-                        })),
-                    ),
-                    Err(ApiError::TokenGenerationFailed(err.to_string())),
-                ),
-            },
-            Err(err) => to_response(
+    let model = match result {
+        Ok(Some(model)) if model.check_hash(&payload.password).is_ok() => model,
+        Ok(_) => {
+            return failed_login(
+                txn,
+                payload.username,
+                ip,
+                "invalid_credentials",
+                "Invalid username or password",
+            )
+            .await
+        }
+        Err(err) => {
+            return to_response(
                 (
-                    StatusCode::UNAUTHORIZED,
+                    StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({
-                        "error": "Invalid username or password".to_string()
+                        "error": "An internal server error occured".to_string()
                     })),
                 ),
-                Err(ApiError::General(err)),
-            ),
-        },
-        Ok(None) => to_response(
-            (
-                StatusCode::UNAUTHORIZED,
-                Json(json!({
-                    "error": "Invalid username or password".to_string()
-                })),
-            ),
-            Err(ApiError::General(
-                "Invalid username or password".to_string(),
-            )),
-        ),
-        Err(err) => to_response(
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "An internal server error occured".to_string()
-                })),
-            ),
-            Err(ApiError::DbError(err.to_string())),
-        ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //Session starts only after the second factor, failed attempts stay until then
+    if model.totp_enabled {
+        let _ = txn.rollback().await;
+        return two_factor_challenge(model.id);
     }
+
+    //Every login starts a new session, failed attempts of the username are forgiven
+    let result = match clear_login_throttle(&txn, &keys[0]).await {
+        Ok(_) => issue_tokens(&txn, &model, Uuid::new_v4().to_string()).await,
+        Err(err) => Err(ApiError::DbError(err.to_string())),
+    };
+    finish_token_response(txn, result).await
 }
 
-async fn create_user(
+//Second step of login for accounts with 2FA, takes the pending token from `login`
+async fn login_two_factor(
     Extension(db): Extension<Arc<DatabaseConnection>>,
-    Json(payload): Json<CreateUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<LoginTwoFactor>,
 ) -> Response {
-    if let Some(err) = payload.validate().err() {
-        return to_response(
-            (
-                StatusCode::BAD_REQUEST,
-                Json(json!({
-                    "error": "Failed to validate username or password"
-                })),
-            ),
-            Err(ApiError::ValidationFail(err.to_string())),
-        );
-    }
+    let pending = match validate_pending_token(&payload.pending_token) {
+        Ok(pending) => pending,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": "Invalid or expired two-factor token"
+                    })),
+                ),
+                Err(ApiError::General(err.to_string())),
+            );
+        }
+    };
 
     let txn = match db.begin().await {
         Ok(txn) => txn,
@@ -239,53 +301,101 @@ async fn create_user(
         }
     };
 
-    let password = match hash_password(&payload.password) {
-        Ok(password) => password,
+    let model = match UserEntity::find_by_id(pending.user_id).one(&txn).await {
+        Ok(Some(model)) if model.totp_enabled => model,
+        Ok(_) => {
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": "Invalid or expired two-factor token"
+                    })),
+                ),
+                Err(ApiError::General(
+                    "Two-factor authentication is not enabled".to_string(),
+                )),
+            );
+        }
         Err(err) => {
             return to_response(
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({
-                        "error": "An internal server error occured"
+                        "error": "An internal server error occured".to_string()
                     })),
                 ),
-                Err(ApiError::PasswordHashFailed(err.to_string())),
+                Err(ApiError::DbError(err.to_string())),
             );
         }
     };
 
-    let new_user = user::ActiveModel {
-        username: Set(payload.username),
-        password: Set(password),
-        role: Set(payload.role),
-        ..Default::default()
-    };
+    let ip = addr.ip().to_string();
+    let keys = [user_throttle_key(&model.username), format!("ip:{}", ip)];
 
-    match user::Entity::insert(new_user).exec(&txn).await {
-        Ok(_) => to_response(
-            (
-                StatusCode::CREATED,
-                Json(json!({
-                    "message": "User registered successfully"
-                })),
-            ),
-            Ok(()),
-        ),
-        Err(err) => to_response(
-            (
-                StatusCode::CONFLICT,
-                Json(json!({
-                    "error": "Username already exists"
-                })),
-            ),
-            Err(ApiError::DbError(err.to_string())),
-        ),
+    //Wrong codes count as failed logins, so codes can't be guessed with one pending token
+    match locked_for(&txn, &keys).await {
+        Ok(Some(retry_after)) => {
+            let mut response = too_many_attempts(retry_after, LOGIN_LOCKED);
+            response.extensions_mut().insert(LoginFailure {
+                username: model.username,
+                ip,
+                reason: "locked",
+                failed_attempts: None,
+                retry_after: Some(retry_after),
+            });
+            return response;
+        }
+        Ok(None) => {}
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured".to_string()
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    match verify_second_factor(&txn, &model, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return failed_login(
+                txn,
+                model.username,
+                ip,
+                "invalid_2fa_code",
+                "Invalid two-factor code",
+            )
+            .await
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured".to_string()
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
     }
+
+    let result = match clear_login_throttle(&txn, &keys[0]).await {
+        Ok(_) => issue_tokens(&txn, &model, Uuid::new_v4().to_string()).await,
+        Err(err) => Err(ApiError::DbError(err.to_string())),
+    };
+    finish_token_response(txn, result).await
 }
 
-async fn get_users(
+//Exchanges refresh token for a new pair. Used token can't be exchanged again,
+//reuse means it was leaked, so the whole session is revoked.
+async fn refresh(
     Extension(db): Extension<Arc<DatabaseConnection>>,
-    Query(query): Query<UsersQuery>,
+    Json(payload): Json<RefreshPayload>,
 ) -> Response {
     let txn = match db.begin().await {
         Ok(txn) => txn,
@@ -302,47 +412,83 @@ async fn get_users(
         }
     };
 
-    let order = match query.order.as_deref() {
-        Some("desc") => sea_orm::Order::Desc,
-        _ => sea_orm::Order::Asc,
-    };
-
-    let sort_users = match query.sort_by.as_deref() {
-        Some("username") => user::Column::Username,
-        Some("role") => user::Column::Role,
-        _ => user::Column::Id,
+    let token = match find_refresh_token(&txn, &payload.refresh_token).await {
+        Ok(token) => token,
+        Err(response) => return response,
     };
 
-    let mut user_finder = user::Entity::find();
-
-    if let Some(role) = query.role {
-        user_finder = user_finder.filter(user::Column::Role.eq(role));
+    if token.used_at.is_some() {
+        let result = match revoke_family(&txn, &token.family_id).await {
+            Ok(_) => txn.commit().await,
+            Err(err) => Err(err),
+        };
+        let tmp = "Refresh token reuse detected, session revoked".to_owned();
+        return to_response(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(match result {
+                Ok(_) => ApiError::General(tmp),
+                Err(err) => ApiError::DbError(err.to_string()),
+            }),
+        );
     }
 
-    //Well, simple enough.
-    if let Some(query) = query.query {
-        let mut query_condition =
-            Condition::any().add(user::Column::Username.contains(query.clone()));
-        let id_search = query.parse::<u32>().ok();
-        if let Some(id) = id_search {
-            query_condition = query_condition.add(user::Column::Id.eq(id));
+    let user = match UserEntity::find_by_id(token.user_id).one(&txn).await {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            let tmp = "Invalid refresh token".to_owned();
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
         }
+    };
 
-        user_finder = user_finder.filter(query_condition);
-    }
+    //Conditional update, so two parallel refreshes can't both succeed
+    let used = refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::Id.eq(token.id))
+        .filter(refresh_token::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await;
 
-    let users: Vec<AdminUserResponse> = match user_finder
-        .order_by(sort_users, order)
-        .select_only() //to select specific columns
-        .column_as(user::Column::Id, "id")
-        .column_as(user::Column::Role, "role")
-        .column_as(user::Column::Username, "username")
-        .into_model::<AdminUserResponse>()
-        .all(&txn)
-        .await
-    {
-        Ok(value) => value,
+    match used {
+        Ok(result) if result.rows_affected == 1 => {}
+        Ok(_) => {
+            let _ = txn.rollback().await;
+            let tmp = "Refresh token was already used".to_owned();
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
         Err(err) => {
+            let _ = txn.rollback().await;
             return to_response(
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
@@ -353,14 +499,15 @@ async fn get_users(
                 Err(ApiError::DbError(err.to_string())),
             );
         }
-    };
+    }
 
-    to_response(Json(users), Ok(()))
+    let result = issue_tokens(&txn, &user, token.family_id).await;
+    finish_token_response(txn, result).await
 }
 
-async fn admin_delete_user(
-    Path(id): Path<i32>,
+async fn logout(
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<RefreshPayload>,
 ) -> Response {
     let txn = match db.begin().await {
         Ok(txn) => txn,
@@ -377,24 +524,568 @@ async fn admin_delete_user(
         }
     };
 
-    match UserEntity::find_by_id(id).one(&txn).await {
-        Ok(Some(entry)) => {
-            let entry: user::ActiveModel = entry.into();
-            let result = entry.delete(&txn).await;
-            match result {
-                Ok(_) => match txn.commit().await {
-                    Ok(_) => to_response(
-                        (
-                            StatusCode::OK,
-                            Json(json!({
-                                "message": "Resource deleted successfully"
-                            })),
-                        ),
-                        Ok(()),
-                    ),
-                    Err(err) => to_response(
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
+    let token = match find_refresh_token(&txn, &payload.refresh_token).await {
+        Ok(token) => token,
+        Err(response) => return response,
+    };
+
+    let result = match revoke_family(&txn, &token.family_id).await {
+        Ok(_) => txn.commit().await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(_) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "message": "Logged out successfully"
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Answers the same whether the user exists or not: the request only counts the attempt and looks
+//the user up, token is issued and mailed in background. Requests are limited per user and per IP
+async fn forgot_password(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<ForgotPassword>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    //Every request counts, keys are separate from login ones so resets can't lock logins
+    let config = &*LOGIN_THROTTLE;
+    let keys = [
+        format!("reset:{}", payload.username),
+        format!("reset-ip:{}", addr.ip()),
+    ];
+    match locked_for(&txn, &keys).await {
+        Ok(Some(retry_after)) => {
+            return too_many_attempts(
+                retry_after,
+                "Too many password reset requests, try again later",
+            );
+        }
+        Ok(None) => {}
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let result = match register_failure(&txn, &keys[0], config.max_attempts, config).await {
+        Ok(_) => register_failure(&txn, &keys[1], config.max_attempts_per_ip, config).await,
+        Err(err) => Err(err),
+    };
+    let result = match result {
+        Ok(_) => {
+            UserEntity::find()
+                .filter(user::Column::Username.eq(&payload.username))
+                .one(&txn)
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    let result = match result {
+        Ok(user) => txn.commit().await.map(|_| user),
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok(user) => {
+            if let Some(user::Model {
+                id,
+                email: Some(email),
+                ..
+            }) = user
+            {
+                tokio::spawn(send_reset_token(db, mailer, id, email));
+            }
+            to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "If the account exists, reset instructions were sent"
+                    })),
+                ),
+                Ok(()),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Only the latest token works
+async fn send_reset_token(
+    db: Arc<DatabaseConnection>,
+    mailer: Arc<dyn Mailer>,
+    user_id: i32,
+    email: String,
+) {
+    let (token, token_hash) = generate_random_token();
+    let now = Utc::now();
+    let result = match db.begin().await {
+        Ok(txn) => {
+            let result = match password_reset_token::Entity::delete_many()
+                .filter(password_reset_token::Column::UserId.eq(user_id))
+                .filter(password_reset_token::Column::UsedAt.is_null())
+                .exec(&txn)
+                .await
+            {
+                Ok(_) => password_reset_token::ActiveModel {
+                    user_id: Set(user_id),
+                    token_hash: Set(token_hash),
+                    created_at: Set(now),
+                    expires_at: Set(now + Duration::minutes(PASSWORD_RESET_TTL_MINUTES)),
+                    used_at: Set(None),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await
+                .map(|_| ()),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(_) => txn.commit().await,
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    Err(err)
+                }
+            }
+        }
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        tracing::error!(event = "password_reset_token_failed", user_id, error = %err);
+        return;
+    }
+
+    let mail = Mail {
+        to: email,
+        subject: "Password reset".to_string(),
+        body: format!(
+            "Use this token to reset your password: {}\nIt expires in {} minutes.",
+            token, PASSWORD_RESET_TTL_MINUTES
+        ),
+    };
+    if let Err(err) = mailer.send(mail).await {
+        tracing::error!("Failed to send password reset mail: {}", err);
+    }
+}
+
+//Token is single-use, every session of the user is revoked
+async fn reset_password(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<ResetPassword>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Failed to validate new password"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let invalid_token = || {
+        let tmp = "Invalid or expired reset token".to_string();
+        to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        )
+    };
+
+    let now = Utc::now();
+    let token = match password_reset_token::Entity::find()
+        .filter(password_reset_token::Column::TokenHash.eq(hash_token(&payload.token)))
+        .filter(password_reset_token::Column::UsedAt.is_null())
+        .filter(password_reset_token::Column::ExpiresAt.gt(now))
+        .one(&txn)
+        .await
+    {
+        Ok(Some(token)) => token,
+        Ok(None) => return invalid_token(),
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //Conditional update, so concurrent requests can't use the token twice
+    match password_reset_token::Entity::update_many()
+        .col_expr(password_reset_token::Column::UsedAt, Expr::value(now))
+        .filter(password_reset_token::Column::Id.eq(token.id))
+        .filter(password_reset_token::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await
+    {
+        Ok(res) if res.rows_affected == 1 => {}
+        Ok(_) => {
+            let _ = txn.rollback().await;
+            return invalid_token();
+        }
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let password = match hash_password(&payload.new_password) {
+        Ok(password) => password,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured"
+                    })),
+                ),
+                Err(ApiError::PasswordHashFailed(err.to_string())),
+            );
+        }
+    };
+
+    let result = match user::Entity::update_many()
+        .col_expr(user::Column::Password, Expr::value(password))
+        .col_expr(user::Column::MustChangePassword, Expr::value(false))
+        .filter(user::Column::Id.eq(token.user_id))
+        .exec(&txn)
+        .await
+    {
+        Ok(_) => revoke_user_sessions(&txn, token.user_id).await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Password has been reset, please log in"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn create_user(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(permissions): Extension<Permissions>,
+    Json(payload): Json<CreateUser>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Failed to validate username or password"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    if let Err(response) = check_assignable_role(&txn, &permissions, &payload.role).await {
+        return response;
+    }
+
+    let password = match hash_password(&payload.password) {
+        Ok(password) => password,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured"
+                    })),
+                ),
+                Err(ApiError::PasswordHashFailed(err.to_string())),
+            );
+        }
+    };
+
+    let new_user = user::ActiveModel {
+        username: Set(payload.username),
+        password: Set(password),
+        role: Set(payload.role),
+        must_change_password: Set(false),
+        totp_enabled: Set(false),
+        ..Default::default()
+    };
+
+    match user::Entity::insert(new_user).exec(&txn).await {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "message": "User registered successfully"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": "Username already exists"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn get_users(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Query(query): Query<UsersQuery>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let order = match query.order.as_deref() {
+        Some("desc") => sea_orm::Order::Desc,
+        _ => sea_orm::Order::Asc,
+    };
+
+    let sort_users = match query.sort_by.as_deref() {
+        Some("username") => user::Column::Username,
+        Some("role") => user::Column::Role,
+        _ => user::Column::Id,
+    };
+
+    let mut user_finder = user::Entity::find();
+
+    if let Some(role) = query.role {
+        user_finder = user_finder.filter(user::Column::Role.eq(role));
+    }
+
+    //Well, simple enough.
+    if let Some(query) = query.query {
+        let mut query_condition =
+            Condition::any().add(user::Column::Username.contains(query.clone()));
+        let id_search = query.parse::<u32>().ok();
+        if let Some(id) = id_search {
+            query_condition = query_condition.add(user::Column::Id.eq(id));
+        }
+
+        user_finder = user_finder.filter(query_condition);
+    }
+
+    let users: Vec<AdminUserResponse> = match user_finder
+        .order_by(sort_users, order)
+        .select_only() //to select specific columns
+        .column_as(user::Column::Id, "id")
+        .column_as(user::Column::Role, "role")
+        .column_as(user::Column::Username, "username")
+        .into_model::<AdminUserResponse>()
+        .all(&txn)
+        .await
+    {
+        Ok(value) => value,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    to_response(Json(users), Ok(()))
+}
+
+async fn admin_delete_user(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(permissions): Extension<Permissions>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    match UserEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(entry)) => {
+            if let Err(response) = check_manageable_user(&txn, &permissions, &entry).await {
+                return response;
+            }
+
+            let entry: user::ActiveModel = entry.into();
+            let result = entry.delete(&txn).await;
+            match result {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource deleted successfully"
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
                             Json(json!({
                                 "error": "Internal server error"
                             })),
@@ -420,139 +1111,828 @@ async fn admin_delete_user(
             let tmp = format!("No related entry with {} id was found.", id);
             to_response(
                 (
-                    StatusCode::BAD_REQUEST,
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn patch_user(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(permissions): Extension<Permissions>,
+    Json(payload): Json<PatchUser>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    match UserEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(user)) => {
+            if let Err(response) = check_manageable_user(&txn, &permissions, &user).await {
+                return response;
+            }
+
+            let password_changed = payload.password.is_some();
+            let unlock = payload.unlock.unwrap_or(false);
+            //Lock is kept under the username it was counted for
+            let throttle_key = user_throttle_key(&user.username);
+            let mut user: user::ActiveModel = user.into();
+
+            if let Some(username) = payload.username {
+                user.username = Set(username);
+            }
+
+            if let Some(password) = payload.password {
+                let password = match hash_password(&password) {
+                    Ok(password) => password,
+                    Err(err) => {
+                        return to_response(
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Json(json!({
+                                    "error": "An internal server error occured"
+                                })),
+                            ),
+                            Err(ApiError::PasswordHashFailed(err.to_string())),
+                        );
+                    }
+                };
+                user.password = Set(password);
+            }
+
+            if let Some(role) = payload.role {
+                if let Err(response) = check_assignable_role(&txn, &permissions, &role).await {
+                    return response;
+                }
+                user.role = Set(role);
+            }
+
+            let result: Result<(), DbErr> = match user.update(&txn).await {
+                //Sessions opened with the old password are closed
+                Ok(_) if password_changed => revoke_user_sessions(&txn, id).await,
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+            let result = match result {
+                Ok(_) if unlock => clear_login_throttle(&txn, &throttle_key).await,
+                other => other,
+            };
+
+            match result {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource patched successfully"
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::CONFLICT,
+                            Json(json!({
+                                "error": "Username unique constraint failed"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to patch this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!("No related entry with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Blocks every route except password change until the user sets a new password
+async fn force_password_reset(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(permissions): Extension<Permissions>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    match UserEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(user)) => {
+            if let Err(response) = check_manageable_user(&txn, &permissions, &user).await {
+                return response;
+            }
+        }
+        Ok(None) => {
+            let tmp = format!("No related entry with {} id was found.", id);
+            return to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let result = user::Entity::update_many()
+        .col_expr(user::Column::MustChangePassword, Expr::value(true))
+        .filter(user::Column::Id.eq(id))
+        .exec(&txn)
+        .await;
+
+    match result {
+        Ok(res) if res.rows_affected == 0 => {
+            let _ = txn.rollback().await;
+            let tmp = format!("No related entry with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "User has to change password"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+//utilities
+//Creates access token and a new refresh token of the `family_id` session
+pub async fn issue_tokens<C: ConnectionTrait>(
+    db: &C,
+    user: &user::Model,
+    family_id: String,
+) -> Result<(String, String), ApiError> {
+    let (refresh_token, token_hash) = generate_random_token();
+    let now = Utc::now();
+    let new_token = refresh_token::ActiveModel {
+        user_id: Set(user.id),
+        family_id: Set(family_id.clone()),
+        token_hash: Set(token_hash),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::days(REFRESH_TOKEN_TTL_DAYS)),
+        used_at: Set(None),
+        revoked_at: Set(None),
+        ..Default::default()
+    };
+
+    refresh_token::Entity::insert(new_token)
+        .exec(db)
+        .await
+        .map_err(|err| ApiError::DbError(err.to_string()))?;
+
+    let access_token = generate_token(user.id, user.role.clone(), family_id)
+        .await
+        .map_err(|err| ApiError::TokenGenerationFailed(err.to_string()))?;
+
+    Ok((access_token, refresh_token))
+}
+
+//Commits issued tokens and builds login / refresh response
+pub async fn finish_token_response(
+    txn: DatabaseTransaction,
+    result: Result<(String, String), ApiError>,
+) -> Response {
+    let result = match result {
+        Ok(tokens) => txn
+            .commit()
+            .await
+            .map(|_| tokens)
+            .map_err(|err| ApiError::DbError(err.to_string())),
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok((token, refresh_token)) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "token": token,
+                    "refresh_token": refresh_token,
+                    "expires_in": ACCESS_TOKEN_TTL_MINUTES * 60
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(err),
+        ),
+    }
+}
+
+//Answers login of accounts with 2FA, the pending token is exchanged at `/login/2fa`
+pub fn two_factor_challenge(user_id: i32) -> Response {
+    match generate_pending_token(user_id) {
+        Ok(pending_token) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "two_factor_required": true,
+                    "pending_token": pending_token,
+                    "expires_in": TWO_FACTOR_PENDING_TTL_MINUTES * 60
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::TokenGenerationFailed(err.to_string())),
+        ),
+    }
+}
+
+//Finds refresh token that still can be used, otherwise builds 401 response
+async fn find_refresh_token<C: ConnectionTrait>(
+    db: &C,
+    token: &str,
+) -> Result<refresh_token::Model, Response> {
+    let result = refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash_token(token)))
+        .one(db)
+        .await;
+
+    match result {
+        Ok(Some(token)) if token.revoked_at.is_none() && token.expires_at > Utc::now() => Ok(token),
+        Ok(_) => {
+            let tmp = "Invalid refresh token".to_owned();
+            Err(to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
                     Json(json!({
                         "error": tmp
                     })),
                 ),
                 Err(ApiError::General(tmp)),
-            )
+            ))
         }
-        Err(err) => to_response(
+        Err(err) => Err(to_response(
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
-                    "error": "Internal server error."
+                    "error": "Internal server error"
                 })),
             ),
             Err(ApiError::DbError(err.to_string())),
-        ),
+        )),
     }
 }
 
-async fn patch_user(
-    Path(id): Path<i32>,
-    Extension(db): Extension<Arc<DatabaseConnection>>,
-    Json(payload): Json<PatchUser>,
+//Revokes every token of the session, access tokens of it are rejected too
+pub async fn revoke_family<C: ConnectionTrait>(db: &C, family_id: &str) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+//Revokes all sessions of the user, used when the password changes
+pub async fn revoke_user_sessions<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now()))
+        .filter(refresh_token::Column::UserId.eq(user_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+//Counts failed login for the username and the client IP, answers 429 once any of them is locked
+async fn failed_login(
+    txn: DatabaseTransaction,
+    username: String,
+    ip: String,
+    reason: &'static str,
+    message: &'static str,
 ) -> Response {
-    let txn = match db.begin().await {
-        Ok(txn) => txn,
-        Err(_) => {
+    let config = &*LOGIN_THROTTLE;
+
+    let result = match register_failure(
+        &txn,
+        &user_throttle_key(&username),
+        config.max_attempts,
+        config,
+    )
+    .await
+    {
+        Ok(user_throttle) => {
+            match register_failure(
+                &txn,
+                &format!("ip:{}", ip),
+                config.max_attempts_per_ip,
+                config,
+            )
+            .await
+            {
+                Ok(ip_throttle) => Ok((user_throttle, ip_throttle)),
+                Err(err) => Err(err),
+            }
+        }
+        Err(err) => Err(err),
+    };
+
+    let result = match result {
+        Ok(throttles) => txn.commit().await.map(|_| throttles),
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    let (user_throttle, ip_throttle) = match result {
+        Ok(throttles) => throttles,
+        Err(err) => {
             return to_response(
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({
-                        "error": "Internal server error"
+                        "error": "An internal server error occured".to_string()
                     })),
                 ),
-                Err(ApiError::TransactionCreationFailed),
+                Err(ApiError::DbError(err.to_string())),
             );
         }
     };
 
-    match UserEntity::find_by_id(id).one(&txn).await {
-        Ok(Some(user)) => {
-            let mut user: user::ActiveModel = user.into();
+    let now = Utc::now();
+    let retry_after = [user_throttle.locked_until, ip_throttle.locked_until]
+        .into_iter()
+        .flatten()
+        .filter(|until| *until > now)
+        .max()
+        .map(|until| retry_after_seconds(until, now));
 
-            if let Some(username) = payload.username {
-                user.username = Set(username);
-            }
+    let mut response = match retry_after {
+        Some(retry_after) => too_many_attempts(retry_after, LOGIN_LOCKED),
+        None => to_response(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": message
+                })),
+            ),
+            Err(ApiError::General(message.to_string())),
+        ),
+    };
+    response.extensions_mut().insert(LoginFailure {
+        username,
+        ip,
+        reason,
+        failed_attempts: Some(user_throttle.failed_attempts),
+        retry_after,
+    });
+    response
+}
 
-            if let Some(password) = payload.password {
-                let password = match hash_password(&password) {
-                    Ok(password) => password,
-                    Err(err) => {
-                        return to_response(
-                            (
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(json!({
-                                    "error": "An internal server error occured"
-                                })),
-                            ),
-                            Err(ApiError::PasswordHashFailed(err.to_string())),
-                        );
-                    }
-                };
-                user.password = Set(password);
-            }
+//Password check of a signed in user, e.g. before changing the password. Failures count towards
+//the same lockout as failed logins, so a stolen session can't be used to guess the password.
+//Failure is committed right away, the transaction is given back only when the password is right
+pub async fn check_current_password(
+    txn: DatabaseTransaction,
+    model: &user::Model,
+    password: &str,
+    ip: String,
+) -> Result<DatabaseTransaction, Response> {
+    let keys = [user_throttle_key(&model.username), format!("ip:{}", ip)];
 
-            if let Some(role) = payload.role {
-                user.role = Set(role);
+    match locked_for(&txn, &keys).await {
+        Ok(Some(retry_after)) => {
+            let mut response = too_many_attempts(retry_after, LOGIN_LOCKED);
+            response.extensions_mut().insert(LoginFailure {
+                username: model.username.clone(),
+                ip,
+                reason: "locked",
+                failed_attempts: None,
+                retry_after: Some(retry_after),
+            });
+            return Err(response);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            return Err(to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ));
+        }
+    }
+
+    let err = match model.check_hash(password) {
+        Ok(_) => return Ok(txn),
+        Err(err) => err,
+    };
+
+    let config = &*LOGIN_THROTTLE;
+    let result = match register_failure(&txn, &keys[0], config.max_attempts, config).await {
+        Ok(user_throttle) => {
+            register_failure(&txn, &keys[1], config.max_attempts_per_ip, config)
+                .await
+                .map(|_| user_throttle)
+        }
+        Err(err) => Err(err),
+    };
+    let result = match result {
+        Ok(user_throttle) => txn.commit().await.map(|_| user_throttle),
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok(user_throttle) => {
+            let mut response = to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": "Current password is incorrect"
+                    })),
+                ),
+                Err(ApiError::General(err)),
+            );
+            response.extensions_mut().insert(LoginFailure {
+                username: model.username.clone(),
+                ip,
+                reason: "invalid_current_password",
+                failed_attempts: Some(user_throttle.failed_attempts),
+                retry_after: None,
+            });
+            Err(response)
+        }
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+fn too_many_attempts(retry_after: i64, message: &str) -> Response {
+    to_response(
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            Json(json!({
+                "error": message
+            })),
+        ),
+        Err(ApiError::General(message.to_string())),
+    )
+}
+
+fn user_throttle_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+fn retry_after_seconds(until: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    (until - now).num_seconds().max(0) + 1
+}
+
+//Seconds until the last lock of the keys ends, None if nothing is locked
+async fn locked_for<C: ConnectionTrait>(db: &C, keys: &[String]) -> Result<Option<i64>, DbErr> {
+    let now = Utc::now();
+    let locks = login_throttle::Entity::find()
+        .filter(login_throttle::Column::Key.is_in(keys.iter().cloned()))
+        .filter(login_throttle::Column::LockedUntil.gt(now))
+        .all(db)
+        .await?;
+
+    Ok(locks
+        .into_iter()
+        .filter_map(|lock| lock.locked_until)
+        .max()
+        .map(|until| retry_after_seconds(until, now)))
+}
+
+//Locks the key once it reaches `max_attempts`, every next lock in a row lasts twice as long.
+//Counting starts over when the key was quiet for the whole window.
+async fn register_failure<C: ConnectionTrait>(
+    db: &C,
+    key: &str,
+    max_attempts: i32,
+    config: &LoginThrottleConfig,
+) -> Result<login_throttle::Model, DbErr> {
+    let now = Utc::now();
+    let existing = login_throttle::Entity::find()
+        .filter(login_throttle::Column::Key.eq(key))
+        .one(db)
+        .await?;
+
+    let (mut failed_attempts, mut lockouts) = match &existing {
+        Some(throttle) => {
+            let last_activity = throttle
+                .locked_until
+                .map_or(throttle.last_failed_at, |until| {
+                    until.max(throttle.last_failed_at)
+                });
+            if now - last_activity > Duration::seconds(config.window_seconds) {
+                (0, 0)
+            } else if throttle.locked_until.is_some_and(|until| until <= now) {
+                (0, throttle.lockouts)
+            } else {
+                (throttle.failed_attempts, throttle.lockouts)
             }
+        }
+        None => (0, 0),
+    };
 
-            let result: Result<(), DbErr> = user.update(&txn).await.map(|_| ());
+    failed_attempts += 1;
+    let mut locked_until = None;
+    if failed_attempts >= max_attempts {
+        locked_until = Some(now + Duration::seconds(config.lockout_seconds(lockouts)));
+        lockouts += 1;
+    }
 
-            match result {
-                Ok(_) => match txn.commit().await {
-                    Ok(_) => to_response(
-                        (
-                            StatusCode::OK,
-                            Json(json!({
-                                "message": "Resource patched successfully"
-                            })),
-                        ),
-                        Ok(()),
-                    ),
-                    Err(err) => to_response(
-                        (
-                            StatusCode::CONFLICT,
-                            Json(json!({
-                                "error": "Username unique constraint failed"
-                            })),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    ),
-                },
-                Err(err) => {
-                    let _ = txn.rollback().await;
-                    to_response(
-                        (
-                            StatusCode::BAD_REQUEST,
-                            Json(json!({
-                                "error": "Failed to patch this resource"
-                            })),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    )
-                }
+    match existing {
+        Some(throttle) => {
+            let mut throttle: login_throttle::ActiveModel = throttle.into();
+            throttle.failed_attempts = Set(failed_attempts);
+            throttle.lockouts = Set(lockouts);
+            throttle.last_failed_at = Set(now);
+            throttle.locked_until = Set(locked_until);
+            throttle.update(db).await
+        }
+        None => {
+            login_throttle::ActiveModel {
+                key: Set(key.to_owned()),
+                failed_attempts: Set(failed_attempts),
+                lockouts: Set(lockouts),
+                last_failed_at: Set(now),
+                locked_until: Set(locked_until),
+                ..Default::default()
             }
+            .insert(db)
+            .await
+        }
+    }
+}
+
+async fn clear_login_throttle<C: ConnectionTrait>(db: &C, key: &str) -> Result<(), DbErr> {
+    login_throttle::Entity::delete_many()
+        .filter(login_throttle::Column::Key.eq(key))
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+struct LoginThrottleConfig {
+    max_attempts: i32,
+    max_attempts_per_ip: i32,
+    lockout_seconds: i64,
+    max_lockout_seconds: i64,
+    window_seconds: i64,
+}
+
+impl LoginThrottleConfig {
+    fn lockout_seconds(&self, lockouts: i32) -> i64 {
+        self.lockout_seconds
+            .saturating_mul(1 << lockouts.clamp(0, 30))
+            .min(self.max_lockout_seconds)
+    }
+}
+
+static LOGIN_THROTTLE: Lazy<LoginThrottleConfig> = Lazy::new(|| {
+    dotenv().ok();
+    LoginThrottleConfig {
+        max_attempts: env_or("LOGIN_MAX_ATTEMPTS", 5),
+        max_attempts_per_ip: env_or("LOGIN_MAX_ATTEMPTS_PER_IP", 20),
+        lockout_seconds: env_or("LOGIN_LOCKOUT_SECONDS", 60),
+        max_lockout_seconds: env_or("LOGIN_MAX_LOCKOUT_SECONDS", 3600),
+        window_seconds: env_or("LOGIN_ATTEMPT_WINDOW_SECONDS", 900),
+    }
+});
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Failed to parse {}", name)),
+        Err(_) => default,
+    }
+}
+
+//Role can be assigned only by someone who has every permission of it,
+//otherwise `user:manage` would be enough to hand out admin
+async fn check_assignable_role<C: ConnectionTrait>(
+    db: &C,
+    permissions: &Permissions,
+    role_name: &str,
+) -> Result<(), Response> {
+    let result = match find_role(db, role_name).await {
+        Ok(Some(_)) => load_permissions(db, role_name).await.map(Some),
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(Some(role_permissions)) if permissions.covers(&role_permissions) => Ok(()),
+        Ok(Some(_)) => {
+            let tmp = format!("Role {} can't be assigned", role_name);
+            Err(to_response(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ))
         }
         Ok(None) => {
-            let tmp = format!("No related entry with {} id was found.", id);
-            to_response(
+            let tmp = format!("Role {} doesn't exist", role_name);
+            Err(to_response(
                 (
-                    StatusCode::NOT_FOUND,
+                    StatusCode::BAD_REQUEST,
                     Json(json!({
                         "error": tmp
                     })),
                 ),
                 Err(ApiError::General(tmp)),
-            )
+            ))
         }
-        Err(err) => to_response(
+        Err(err) => Err(to_response(
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
-                    "error": "Internal server error."
+                    "error": "Internal server error"
                 })),
             ),
             Err(ApiError::DbError(err.to_string())),
-        ),
+        )),
     }
 }
 
-//utilities
-fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+//Accounts with permissions the caller lacks can't be changed by them
+async fn check_manageable_user<C: ConnectionTrait>(
+    db: &C,
+    permissions: &Permissions,
+    user: &user::Model,
+) -> Result<(), Response> {
+    match load_permissions(db, &user.role).await {
+        Ok(user_permissions) if permissions.covers(&user_permissions) => Ok(()),
+        Ok(_) => {
+            let tmp = format!("User {} can't be managed", user.id);
+            Err(to_response(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ))
+        }
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
 
@@ -570,6 +1950,8 @@ struct RegisterUser {
     username: String,
     #[validate(regex(path = *PASSWORD_REGEX))]
     password: String,
+    #[validate(email)]
+    email: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug, Validate)]
@@ -578,7 +1960,7 @@ struct CreateUser {
     username: String,
     #[validate(regex(path = *PASSWORD_REGEX))]
     password: String,
-    role: Role,
+    role: String,
 }
 
 #[derive(Debug, Deserialize, Clone, Validate)]
@@ -589,20 +1971,45 @@ struct UserLogin {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct LoginTwoFactor {
+    pending_token: String,
+    code: String,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 struct PatchUser {
-    role: Option<Role>,
+    role: Option<String>,
+    //Lifts failed login lock of the account
+    unlock: Option<bool>,
     #[validate(regex(path = *USERNAME_REGEX))]
     username: Option<String>,
     #[validate(regex(path = *PASSWORD_REGEX))]
     password: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct RefreshPayload {
+    refresh_token: String,
+}
+
+#[derive(Deserialize)]
+struct ForgotPassword {
+    username: String,
+}
+
+#[derive(Deserialize, Validate)]
+struct ResetPassword {
+    token: String,
+    #[validate(regex(path = *PASSWORD_REGEX))]
+    new_password: String,
+}
+
 #[derive(Deserialize, Serialize, FromQueryResult)]
 struct AdminUserResponse {
     id: i32,
     username: String,
-    role: Role,
+    role: String,
 }
 
 #[derive(Deserialize)]
@@ -613,9 +2020,9 @@ struct UsersQuery {
     sort_by: Option<String>, //Enum better?? "id", "username", "role"
     order: Option<String>,   //Enum better??
     //filter zone
-    role: Option<Role>, //incoming should be None, "user" or "admin"
+    role: Option<String>, //name of the role
 }
 
 pub static USERNAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_]{3,25}$").unwrap());
-static PASSWORD_REGEX: Lazy<Regex> =
+pub static PASSWORD_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"^[a-zA-Z0-9!@#$%^&*()_+]{8,15}$").unwrap());
@@ -7,16 +7,22 @@ use axum::{
     Json, Router,
 };
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
-    FromQueryResult, JoinType, QueryFilter, QueryOrder, QuerySelect, RelationTrait, Set,
-    TransactionTrait,
+    sea_query::{Expr, Func, SimpleExpr},
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, DatabaseTransaction, DbErr,
+    EntityTrait, FromQueryResult, JoinType, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+    RelationTrait, Set, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::Arc;
 
-use crate::entities::user::Role;
-use crate::entities::{cart, cart::Entity as CartEntity, category, product, user};
+use crate::entities::{
+    cart,
+    cart::Entity as CartEntity,
+    category,
+    money::{Currency, MinorUnits},
+    permission, product, product_variant, user,
+};
 use crate::middleware::{
     auth::{auth_middleware, Claims},
     logging::{to_response, ApiError},
@@ -27,7 +33,10 @@ pub fn cart_routes() -> Router {
     Router::new()
         .route("/cart", get(get_cart).post(add_product))
         .route("/cart/:id", patch(patch_entry).delete(remove_product))
-        .layer(middleware::from_fn_with_state(Role::User, auth_middleware))
+        .layer(middleware::from_fn_with_state(
+            permission::CART_USE,
+            auth_middleware,
+        ))
 }
 
 pub fn admin_cart_routes() -> Router {
@@ -37,7 +46,10 @@ pub fn admin_cart_routes() -> Router {
             "/cart:id",
             patch(admin_remove_product).post(admin_patch_cart_entry),
         )
-        .layer(middleware::from_fn_with_state(Role::Admin, auth_middleware))
+        .layer(middleware::from_fn_with_state(
+            permission::CART_MANAGE,
+            auth_middleware,
+        ))
 }
 
 //Routes
@@ -64,12 +76,12 @@ async fn get_cart(
 
     let mut condition = Condition::all().add(cart::Column::UserId.eq(user_id));
 
-    //Filter zone
+    //Filter zone, price of the variant if the entry has one
     if let Some(price_bottom) = query.price_bottom {
-        condition = condition.add(product::Column::Price.gte(price_bottom));
+        condition = condition.add(Expr::expr(entry_price()).gte(price_bottom));
     }
     if let Some(price_top) = query.price_top {
-        condition = condition.add(product::Column::Price.lte(price_top));
+        condition = condition.add(Expr::expr(entry_price()).lte(price_top));
     }
     if let Some(category_ids) = query.category_ids {
         condition = condition.add(product::Column::CategoryId.is_in(category_ids));
@@ -87,10 +99,10 @@ async fn get_cart(
         _ => sea_orm::Order::Asc,
     };
 
-    let sort_column = match query.sort_by.as_deref() {
-        Some("price") => product::Column::Price,
-        Some("availability") => product::Column::IsAvailable,
-        _ => product::Column::Name,
+    let sort_column: SimpleExpr = match query.sort_by.as_deref() {
+        Some("price") => entry_price(),
+        Some("availability") => Expr::col((product::Entity, product::Column::IsAvailable)).into(),
+        _ => Expr::col((product::Entity, product::Column::Name)).into(),
     };
 
     let sort_cart_column = match query.sort_by.as_deref() {
@@ -108,9 +120,13 @@ async fn get_cart(
         .filter(condition)
         .join(JoinType::InnerJoin, cart::Relation::Product.def())
         .join(JoinType::InnerJoin, product::Relation::Category.def())
+        .join(JoinType::LeftJoin, cart::Relation::Variant.def())
         .column_as(product::Column::Id, "product_id")
         .column_as(product::Column::Name, "name")
-        .column_as(product::Column::Price, "price")
+        .column_as(cart::Column::VariantId, "variant_id")
+        .column_as(product_variant::Column::Name, "variant_name")
+        .column_as(entry_price(), "price")
+        .column_as(product::Column::Currency, "currency")
         .column_as(product::Column::ImageId, "image_id")
         .column_as(category::Column::Name, "category_name")
         .column_as(product::Column::IsAvailable, "is_available");
@@ -165,17 +181,50 @@ async fn add_product(
         .one(&txn)
         .await
     {
-        Ok(Some(_)) => {
+        Ok(Some(product)) => {
             if payload.quantity > 0 {
-                //If entry already exist in db, so we would expand it, instead of creating second one.
-                if let Ok(Some(entry)) = CartEntity::find()
+                let variant = match find_variant(&txn, &product, payload.variant_id).await {
+                    Ok(variant) => variant,
+                    Err(response) => return response,
+                };
+
+                let variant_condition = match payload.variant_id {
+                    Some(variant_id) => cart::Column::VariantId.eq(variant_id),
+                    None => cart::Column::VariantId.is_null(),
+                };
+                let existing = CartEntity::find()
                     .filter(cart::Column::ProductId.eq(payload.product_id))
+                    .filter(variant_condition)
                     .filter(cart::Column::UserId.eq(user_id))
                     .one(&txn)
                     .await
-                {
+                    .ok()
+                    .flatten();
+
+                let in_cart = existing.as_ref().map_or(0, |entry| entry.quantity);
+                let quantity = match in_cart.checked_add(payload.quantity) {
+                    Some(quantity) => quantity,
+                    None => {
+                        let tmp = "Quantity is too big".to_owned();
+                        return to_response(
+                            (
+                                StatusCode::BAD_REQUEST,
+                                Json(json!({
+                                    "error": tmp
+                                })),
+                            ),
+                            Err(ApiError::ValidationFail(tmp)),
+                        );
+                    }
+                };
+                if let Some(response) = check_stock(&product, variant.as_ref(), quantity) {
+                    return response;
+                }
+
+                //If entry already exist in db, so we would expand it, instead of creating second one.
+                if let Some(entry) = existing {
                     let mut entry: cart::ActiveModel = entry.into();
-                    entry.quantity = Set(entry.quantity.unwrap() + payload.quantity);
+                    entry.quantity = Set(quantity);
                     let result = entry.update(&txn).await.map(|_| ());
                     match result {
                         Ok(_) => {
@@ -217,6 +266,7 @@ async fn add_product(
                 let new_entry = cart::ActiveModel {
                     user_id: Set(user_id),
                     product_id: Set(payload.product_id),
+                    variant_id: Set(payload.variant_id),
                     quantity: Set(payload.quantity),
                     ..Default::default()
                 };
@@ -405,14 +455,31 @@ async fn patch_entry(
 
     match CartEntity::find_by_id(id)
         .filter(cart::Column::UserId.eq(user_id))
+        .find_also_related(product::Entity)
         .one(&txn)
         .await
     {
-        Ok(Some(entry)) => {
+        Ok(Some((entry, product))) => {
+            let variant = match entry.variant_id {
+                Some(variant_id) => product_variant::Entity::find_by_id(variant_id)
+                    .one(&txn)
+                    .await
+                    .ok()
+                    .flatten(),
+                None => None,
+            };
+
+            if let Some(response) = product
+                .as_ref()
+                .and_then(|product| check_stock(product, variant.as_ref(), payload.quantity))
+            {
+                return response;
+            }
+
             let mut entry: cart::ActiveModel = entry.into();
 
             let result: Result<(), DbErr> = match payload.quantity {
-                value if value == 0 => entry.delete(&txn).await.map(|_| ()),
+                0 => entry.delete(&txn).await.map(|_| ()),
                 _ => {
                     entry.quantity = Set(payload.quantity);
                     entry.update(&txn).await.map(|_| ())
@@ -552,12 +619,16 @@ async fn get_carts(
             .column_as(cart::Column::Quantity, "quantity")
             .column_as(product::Column::Id, "product_id")
             .column_as(product::Column::Name, "product_name")
-            .column_as(product::Column::Price, "product_price")
+            .column_as(cart::Column::VariantId, "variant_id")
+            .column_as(product_variant::Column::Name, "variant_name")
+            .column_as(entry_price(), "product_price")
+            .column_as(product::Column::Currency, "currency")
             .column_as(product::Column::IsAvailable, "is_available")
             .column_as(category::Column::Id, "category_id")
             .column_as(category::Column::Name, "category_name")
             .join(JoinType::InnerJoin, cart::Relation::Product.def())
             .join(JoinType::InnerJoin, product::Relation::Category.def())
+            .join(JoinType::LeftJoin, cart::Relation::Variant.def())
             .into_model::<PrepareCartItem>()
             .all(&txn)
             .await
@@ -577,16 +648,41 @@ async fn get_carts(
         };
 
         let mut cart: Vec<CartItem> = Vec::new();
-        let mut total = 0.0;
-        let mut total_available = 0.0;
+        //Minor units of different currencies aren't summed together
+        let mut totals: Vec<CartTotal> = Vec::new();
         let mut total_quantity: u32 = 0;
         for item in cart_items {
-            let total_price = item.quantity as f64 * item.product_price;
-            if item.is_available {
-                total_available += total_price;
+            let total_price = match (item.quantity as MinorUnits).checked_mul(item.product_price) {
+                Some(total_price) => total_price,
+                None => return cart_total_overflow(user.id),
+            };
+            let index = match totals.iter().position(|t| t.currency == item.currency) {
+                Some(index) => index,
+                None => {
+                    totals.push(CartTotal {
+                        currency: item.currency,
+                        total: 0,
+                        total_available: 0,
+                    });
+                    totals.len() - 1
+                }
+            };
+            let total = &mut totals[index];
+            let sums = (
+                total.total.checked_add(total_price),
+                total.total_available.checked_add(total_price),
+                total_quantity.checked_add(item.quantity),
+            );
+            match sums {
+                (Some(sum), Some(sum_available), Some(quantity)) => {
+                    total.total = sum;
+                    if item.is_available {
+                        total.total_available = sum_available;
+                    }
+                    total_quantity = quantity;
+                }
+                _ => return cart_total_overflow(user.id),
             }
-            total += total_price;
-            total_quantity += item.quantity;
 
             if let (Some(total_entries_bottom), Some(total_entries_top)) =
                 (query.total_entries_bottom, query.total_entries_top)
@@ -601,7 +697,10 @@ async fn get_carts(
                 product: ProductItem {
                     id: item.product_id,
                     name: item.product_name,
+                    variant_id: item.variant_id,
+                    variant_name: item.variant_name,
                     price: item.product_price,
+                    currency: item.currency,
                 },
                 category: CategoryItem {
                     id: item.category_id,
@@ -620,6 +719,11 @@ async fn get_carts(
         if let (Some(cart_total_bottom), Some(cart_total_top)) =
             (query.cart_total_bottom, query.cart_total_top)
         {
+            let currency = query.currency.unwrap_or_default();
+            let total_available = totals
+                .iter()
+                .find(|t| t.currency == currency)
+                .map_or(0, |t| t.total_available);
             if cart_total_bottom > total_available || cart_total_top < total_available {
                 //makes sense to take total_available instead of total
                 continue;
@@ -630,8 +734,7 @@ async fn get_carts(
             id: user.id,
             role: user.role,
             cart,
-            total,
-            total_available,
+            totals,
         });
     }
     to_response(Json(user_cart_list), Ok(()))
@@ -744,7 +847,7 @@ async fn admin_patch_cart_entry(
             let mut entry: cart::ActiveModel = entry.into();
 
             let result: Result<(), DbErr> = match payload.quantity {
-                value if value == 0 => entry.delete(&txn).await.map(|_| ()),
+                0 => entry.delete(&txn).await.map(|_| ()),
                 _ => {
                     entry.quantity = Set(payload.quantity);
                     entry.update(&txn).await.map(|_| ())
@@ -808,6 +911,116 @@ async fn admin_patch_cart_entry(
         ),
     }
 }
+//utils
+//Builds error response if cart can't hold that many items of the product (or its variant)
+fn check_stock(
+    product: &product::Model,
+    variant: Option<&product_variant::Model>,
+    quantity: u32,
+) -> Option<Response> {
+    let stock = variant.map_or(product.stock, |variant| variant.stock);
+    if quantity as i64 <= stock as i64 {
+        return None;
+    }
+
+    let tmp = match variant {
+        Some(variant) => format!(
+            "Only {} items of variant {} of product {} are in stock",
+            stock, variant.id, product.id
+        ),
+        None => format!(
+            "Only {} items of product {} are in stock",
+            stock, product.id
+        ),
+    };
+    Some(to_response(
+        (
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": tmp
+            })),
+        ),
+        Err(ApiError::ValidationFail(tmp)),
+    ))
+}
+
+//Cart totals are summed in MinorUnits, overflowing sum can't be shown
+fn cart_total_overflow(user_id: i32) -> Response {
+    let tmp = format!("Cart total of user {} is too large", user_id);
+    to_response(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": tmp
+            })),
+        ),
+        Err(ApiError::ValidationFail(tmp)),
+    )
+}
+
+//Products with variants can be added only as one of their variants
+async fn find_variant(
+    txn: &DatabaseTransaction,
+    product: &product::Model,
+    variant_id: Option<i32>,
+) -> Result<Option<product_variant::Model>, Response> {
+    let result = match variant_id {
+        Some(variant_id) => product_variant::Entity::find_by_id(variant_id)
+            .filter(product_variant::Column::ProductId.eq(product.id))
+            .one(txn)
+            .await
+            .map(|variant| match variant {
+                Some(variant) => Ok(Some(variant)),
+                None => Err(format!(
+                    "No variant with {} id was found for product {}",
+                    variant_id, product.id
+                )),
+            }),
+        None => product_variant::Entity::find()
+            .filter(product_variant::Column::ProductId.eq(product.id))
+            .count(txn)
+            .await
+            .map(|count| match count {
+                0 => Ok(None),
+                _ => Err(format!(
+                    "Product {} is sold in variants, variant_id is required",
+                    product.id
+                )),
+            }),
+    };
+
+    match result {
+        Ok(Ok(variant)) => Ok(variant),
+        Ok(Err(tmp)) => Err(to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        )),
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+//Variant price replaces the product one
+fn entry_price() -> SimpleExpr {
+    Func::coalesce([
+        Expr::col((product_variant::Entity, product_variant::Column::Price)).into(),
+        Expr::col((product::Entity, product::Column::Price)).into(),
+    ])
+    .into()
+}
+
 //Structs
 #[derive(Deserialize, Debug)]
 struct CartQuery {
@@ -817,8 +1030,8 @@ struct CartQuery {
     sort_by: Option<String>, //Enum better?? "price", "quantity", "availability", "name"
     order: Option<String>,   //Enum better??
     //filter zone
-    price_top: Option<i32>,
-    price_bottom: Option<i32>,
+    price_top: Option<MinorUnits>,
+    price_bottom: Option<MinorUnits>,
     category_ids: Option<Vec<i32>>,
     only_available: Option<bool>,
     only_featured: Option<bool>,
@@ -835,10 +1048,12 @@ struct AdminCartsQuery {
     sort_by: Option<String>, //Enum better?? "id", "username", "role"
     order: Option<String>,   //Enum better??
     //filter zone
-    role: Option<Role>, //incoming should be None, "user" or "admin"
+    role: Option<String>, //name of the role
     non_empty: Option<bool>,
-    cart_total_bottom: Option<f64>,
-    cart_total_top: Option<f64>,
+    cart_total_bottom: Option<MinorUnits>,
+    cart_total_top: Option<MinorUnits>,
+    //Currency of the cart total filter, default one when not set
+    currency: Option<Currency>,
     total_entries_bottom: Option<u32>,
     total_entries_top: Option<u32>,
 }
@@ -850,7 +1065,10 @@ struct PrepareCartItem {
     quantity: u32,
     product_id: i32,
     product_name: String,
-    product_price: f64,
+    variant_id: Option<i32>,
+    variant_name: Option<String>,
+    product_price: MinorUnits,
+    currency: Currency,
     is_available: bool,
     category_id: i32,
     category_name: String,
@@ -861,7 +1079,10 @@ struct PrepareCartItem {
 struct ProductItem {
     id: i32,
     name: String,
-    price: f64,
+    variant_id: Option<i32>,
+    variant_name: Option<String>,
+    price: MinorUnits,
+    currency: Currency,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -876,22 +1097,30 @@ struct CartItem {
     product: ProductItem,
     category: CategoryItem,
     quantity: u32,
-    price: f64,
+    price: MinorUnits,
     is_available: bool,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+struct CartTotal {
+    currency: Currency,
+    total: MinorUnits,
+    total_available: MinorUnits,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct UsersEntry {
     id: i32,
-    role: Role,
+    role: String,
     cart: Vec<CartItem>,
-    total: f64,
-    total_available: f64,
+    //One entry per currency in the cart
+    totals: Vec<CartTotal>,
 }
 
 #[derive(Deserialize, Debug)]
 struct AddProduct {
     product_id: i32,
+    variant_id: Option<i32>,
     quantity: u32, //maybe u16 is enough...
 }
 
@@ -904,7 +1133,10 @@ struct PatchCart {
 struct CartResponse {
     product_id: i32,
     name: String,
-    price: f64,
+    variant_id: Option<i32>,
+    variant_name: Option<String>,
+    price: MinorUnits,
+    currency: Currency,
     image_id: i32,
     category_name: String,
     is_available: bool,
@@ -15,7 +15,7 @@ use serde_json::json;
 use std::sync::Arc;
 use validator::Validate;
 
-use crate::entities::{category, category::Entity as CategoryEntity, image, user::Role};
+use crate::entities::{category, category::Entity as CategoryEntity, image, permission};
 use crate::middleware::{
     auth::auth_middleware,
     logging::{to_response, ApiError},
@@ -36,7 +36,7 @@ pub fn admin_category_routes() -> Router {
             patch(patch_category).delete(delete_category),
         )
         .layer(middleware::from_fn_with_state(
-            Role::Admin,
+            permission::CATEGORY_WRITE,
             auth_middleware,
         ))
 }
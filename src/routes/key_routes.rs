@@ -0,0 +1,71 @@
+use axum::{
+    extract::Extension,
+    http::{header, StatusCode},
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use sea_orm::DatabaseConnection;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::entities::permission;
+use crate::middleware::{
+    auth::auth_middleware,
+    jwt_keys,
+    logging::{to_response, ApiError},
+};
+
+//ROUTERS
+//Other services verify access tokens with these keys
+pub fn jwks_routes() -> Router {
+    Router::new().route("/.well-known/jwks.json", get(get_jwks))
+}
+
+pub fn admin_key_routes() -> Router {
+    Router::new()
+        .route("/key/rotate", post(rotate_key))
+        .layer(middleware::from_fn_with_state(
+            permission::KEY_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+async fn get_jwks() -> Response {
+    to_response(
+        (
+            StatusCode::OK,
+            //Keys are checked for rotation every few minutes
+            [(header::CACHE_CONTROL, "public, max-age=300")],
+            Json(jwt_keys::jwks()),
+        ),
+        Ok(()),
+    )
+}
+
+//Rotates right away, e.g. when a key leaked. Tokens of the old key stay valid until they expire.
+async fn rotate_key(Extension(db): Extension<Arc<DatabaseConnection>>) -> Response {
+    match jwt_keys::rotate(&*db).await {
+        Ok(kid) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "message": "Signing key rotated",
+                    "kid": kid
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::General(err.to_string())),
+        ),
+    }
+}
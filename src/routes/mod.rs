@@ -1,24 +1,43 @@
+pub mod api_key_routes;
 pub mod auth_routes;
 pub mod cart_routes;
 pub mod category_routes;
+pub mod key_routes;
+pub mod oidc_routes;
+pub mod order_routes;
 pub mod product_routes;
 pub mod profile_routes;
+pub mod role_routes;
+pub mod two_factor_routes;
 pub mod upload_routes;
 
 use axum::{Extension, Router};
 use sea_orm::DatabaseConnection;
 use std::sync::Arc;
 
+use crate::mailer::Mailer;
+use crate::storage::Storage;
+
 use {
+    api_key_routes::{admin_api_key_routes, api_key_routes},
     auth_routes::{auth_routes, admin_users_routes},
     cart_routes::{cart_routes, admin_cart_routes},
     profile_routes::profile_routes,
+    key_routes::{admin_key_routes, jwks_routes},
+    oidc_routes::{oidc_link_routes, oidc_routes},
+    role_routes::admin_role_routes,
+    two_factor_routes::two_factor_routes,
     category_routes::{admin_category_routes, category_routes},
+    order_routes::{admin_order_routes, order_routes},
     product_routes::{admin_product_routes, product_routes},
-    upload_routes::{public_image_router, upload_routes},
+    upload_routes::{admin_image_routes, public_image_router, upload_routes},
 };
 
-pub fn api_router(db: Arc<DatabaseConnection>) -> Router {
+pub fn api_router(
+    db: Arc<DatabaseConnection>,
+    mailer: Arc<dyn Mailer>,
+    storage: Arc<dyn Storage>,
+) -> Router {
     //does it need to be async?
     let user_routes = auth_routes();
     let category_routes = category_routes();
@@ -29,20 +48,44 @@ pub fn api_router(db: Arc<DatabaseConnection>) -> Router {
     let cart_routes = cart_routes();
     let public_image_router = public_image_router();
     let profile_router = profile_routes();
+    let two_factor_router = two_factor_routes();
+    let api_key_router = api_key_routes();
+    let admin_api_key_router = admin_api_key_routes();
     let admin_cart_routes = admin_cart_routes();
+    let order_routes = order_routes();
+    let admin_order_routes = admin_order_routes();
     let admin_users_router = admin_users_routes();
+    let admin_role_routes = admin_role_routes();
+    let jwks_routes = jwks_routes();
+    let oidc_routes = oidc_routes();
+    let oidc_link_routes = oidc_link_routes();
+    let admin_key_routes = admin_key_routes();
+    let admin_image_routes = admin_image_routes();
 
     Router::new()
         .nest("/", user_routes)
         .nest("/", public_image_router)
+        .nest("/", jwks_routes)
+        .nest("/", oidc_routes)
         .nest("/api", category_routes)
         .nest("/api", product_routes)
         .nest("/api", upload_routes)
         .nest("/api", cart_routes)
+        .nest("/api", order_routes)
         .nest("/api", profile_router)
+        .nest("/api", two_factor_router)
+        .nest("/api", api_key_router)
+        .nest("/api", oidc_link_routes)
         .nest("/api/admin", admin_category_routes)
         .nest("/api/admin", admin_product_routes)
         .nest("/api/admin", admin_cart_routes)
+        .nest("/api/admin", admin_order_routes)
         .nest("/api/admin", admin_users_router)
+        .nest("/api/admin", admin_role_routes)
+        .nest("/api/admin", admin_key_routes)
+        .nest("/api/admin", admin_api_key_router)
+        .nest("/api/admin", admin_image_routes)
         .layer(Extension(db))
+        .layer(Extension(mailer))
+        .layer(Extension(storage))
 }
@@ -0,0 +1,560 @@
+use axum::{
+    extract::{Extension, Query},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set, TransactionTrait,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::entities::{
+    oidc_login, permission, role,
+    user::{self, Entity as UserEntity},
+    user_identity,
+};
+use crate::middleware::{
+    auth::{auth_middleware, generate_random_token, hash_token, ApiKeyAuth, Claims},
+    logging::{to_response, ApiError},
+};
+use crate::oidc::{self, IdTokenClaims, OidcError};
+use crate::routes::auth_routes::{
+    finish_token_response, hash_password, issue_tokens, two_factor_challenge, USERNAME_REGEX,
+};
+
+//Time to sign in at the provider
+const OIDC_LOGIN_TTL_MINUTES: i64 = 10;
+//Ties the login to the browser that started it, see `callback`
+const OIDC_BINDING_COOKIE: &str = "oidc_binding";
+
+//ROUTERS
+pub fn oidc_routes() -> Router {
+    Router::new()
+        .route("/auth/oidc/start", get(start))
+        .route("/auth/oidc/callback", get(callback))
+}
+
+pub fn oidc_link_routes() -> Router {
+    Router::new()
+        .route("/profile/oidc/link", post(link))
+        .layer(middleware::from_fn_with_state(
+            permission::PROFILE_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+//Redirects the browser to the provider, it comes back to `callback`
+async fn start(Extension(db): Extension<Arc<DatabaseConnection>>) -> Response {
+    match begin_login(&*db, None).await {
+        Ok((url, cookie)) => {
+            to_response(([(header::SET_COOKIE, cookie)], Redirect::to(&url)), Ok(()))
+        }
+        Err(response) => response,
+    }
+}
+
+//Same flow for a logged in user, the provider account gets linked instead of a new user.
+//Linked account logs in with a full session, so scoped API keys can't link one.
+async fn link(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    api_key_auth: Option<Extension<ApiKeyAuth>>,
+) -> Response {
+    if let Some(Extension(auth)) = api_key_auth {
+        return to_response(
+            (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "Provider accounts can't be linked with an API key"
+                })),
+            ),
+            Err(ApiError::General(format!(
+                "API key {} tried to link a provider account",
+                auth.key_id
+            ))),
+        );
+    }
+
+    match begin_login(&*db, Some(claims.user_id)).await {
+        Ok((url, cookie)) => to_response(
+            (
+                StatusCode::OK,
+                [(header::SET_COOKIE, cookie)],
+                Json(json!({
+                    "authorization_url": url
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(response) => response,
+    }
+}
+
+//Finds the user by `sub` of the provider, creates one on the first login
+async fn callback(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    headers: HeaderMap,
+    Query(query): Query<CallbackQuery>,
+) -> Response {
+    let config = match oidc::config() {
+        Some(config) => config,
+        None => return not_configured(),
+    };
+
+    if let Some(error) = query.error {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Login was rejected by the provider"
+                })),
+            ),
+            Err(ApiError::General(format!(
+                "OIDC provider error {}: {}",
+                error,
+                query.error_description.unwrap_or_default()
+            ))),
+        );
+    }
+
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            let tmp = "Missing code or state".to_string();
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            );
+        }
+    };
+
+    //State is single-use, the row is removed before talking to the provider
+    let login = match take_login(&*db, &state).await {
+        Ok(Some(login)) if login.expires_at > Utc::now() => login,
+        Ok(_) => {
+            let tmp = "Unknown or expired login".to_string();
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //Otherwise a callback URL of someone else's login would sign the browser into their account
+    if binding_cookie(&headers).map(hash_token) != Some(login.binding_hash) {
+        let tmp = "Login was started in another browser".to_string();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    let provider = match oidc::provider(config).await {
+        Ok(provider) => provider,
+        Err(err) => return provider_error(err),
+    };
+
+    let claims = match oidc::exchange_code(
+        config,
+        provider,
+        &code,
+        &login.code_verifier,
+        &login.nonce,
+    )
+    .await
+    {
+        Ok(claims) => claims,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": "OpenID Connect login failed"
+                    })),
+                ),
+                Err(ApiError::General(err.to_string())),
+            );
+        }
+    };
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = match login.user_id {
+        Some(user_id) => link_identity(&txn, user_id, &config.issuer, &claims.sub).await,
+        None => find_or_create_user(&txn, &config.issuer, &claims).await,
+    };
+
+    let model = match result {
+        Ok(model) => model,
+        Err(response) => {
+            let _ = txn.rollback().await;
+            return response;
+        }
+    };
+
+    //Provider login doesn't replace the second factor of the account
+    if model.totp_enabled {
+        return match txn.commit().await {
+            Ok(_) => two_factor_challenge(model.id),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        };
+    }
+
+    let result = issue_tokens(&txn, &model, Uuid::new_v4().to_string()).await;
+    finish_token_response(txn, result).await
+}
+
+//utils
+//Stores state, nonce and PKCE verifier of the login,
+//returns URL of the provider and the binding cookie for the browser
+async fn begin_login<C: ConnectionTrait>(
+    db: &C,
+    user_id: Option<i32>,
+) -> Result<(String, String), Response> {
+    let config = oidc::config().ok_or_else(not_configured)?;
+    let provider = oidc::provider(config).await.map_err(provider_error)?;
+
+    let state = oidc::random_string();
+    let binding = oidc::random_string();
+    let nonce = oidc::random_string();
+    let (code_verifier, code_challenge) = oidc::pkce_pair();
+    let url = oidc::authorization_url(config, provider, &state, &nonce, &code_challenge)
+        .map_err(provider_error)?;
+
+    let now = Utc::now();
+    let db_error = |err: sea_orm::DbErr| {
+        to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )
+    };
+
+    //Abandoned logins are cleaned up here
+    oidc_login::Entity::delete_many()
+        .filter(oidc_login::Column::ExpiresAt.lte(now))
+        .exec(db)
+        .await
+        .map_err(db_error)?;
+
+    oidc_login::ActiveModel {
+        state_hash: Set(hash_token(&state)),
+        binding_hash: Set(hash_token(&binding)),
+        code_verifier: Set(code_verifier),
+        nonce: Set(nonce),
+        user_id: Set(user_id),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::minutes(OIDC_LOGIN_TTL_MINUTES)),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(db_error)?;
+
+    //Lax is still sent on the redirect back from the provider
+    let secure = if config.redirect_uri.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{}={}; Path=/auth/oidc; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        OIDC_BINDING_COOKIE,
+        binding,
+        OIDC_LOGIN_TTL_MINUTES * 60,
+        secure
+    );
+
+    Ok((url, cookie))
+}
+
+fn binding_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == OIDC_BINDING_COOKIE)
+        .map(|(_, value)| value)
+}
+
+//Returns the login and removes it, None when it's unknown or was already used
+async fn take_login<C: ConnectionTrait>(
+    db: &C,
+    state: &str,
+) -> Result<Option<oidc_login::Model>, sea_orm::DbErr> {
+    let login = match oidc_login::Entity::find()
+        .filter(oidc_login::Column::StateHash.eq(hash_token(state)))
+        .one(db)
+        .await?
+    {
+        Some(login) => login,
+        None => return Ok(None),
+    };
+
+    let result = oidc_login::Entity::delete_by_id(login.id).exec(db).await?;
+    Ok((result.rows_affected == 1).then_some(login))
+}
+
+async fn find_identity<C: ConnectionTrait>(
+    db: &C,
+    issuer: &str,
+    subject: &str,
+) -> Result<Option<user_identity::Model>, Response> {
+    user_identity::Entity::find()
+        .filter(user_identity::Column::Issuer.eq(issuer))
+        .filter(user_identity::Column::Subject.eq(subject))
+        .one(db)
+        .await
+        .map_err(|err| {
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        })
+}
+
+async fn insert_identity<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    issuer: &str,
+    subject: &str,
+) -> Result<(), Response> {
+    user_identity::ActiveModel {
+        user_id: Set(user_id),
+        issuer: Set(issuer.to_string()),
+        subject: Set(subject.to_string()),
+        created_at: Set(Utc::now()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map(|_| ())
+    .map_err(|err| {
+        to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )
+    })
+}
+
+async fn find_user<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<user::Model, Response> {
+    match UserEntity::find_by_id(user_id).one(db).await {
+        Ok(Some(model)) => Ok(model),
+        Ok(None) => Err(to_response(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "Unauthorized access"
+                })),
+            ),
+            Err(ApiError::General("User not found".to_string())),
+        )),
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+async fn link_identity<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    issuer: &str,
+    subject: &str,
+) -> Result<user::Model, Response> {
+    match find_identity(db, issuer, subject).await? {
+        Some(identity) if identity.user_id == user_id => {}
+        Some(_) => {
+            let tmp = "Provider account is linked to another user".to_string();
+            return Err(to_response(
+                (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ));
+        }
+        None => insert_identity(db, user_id, issuer, subject).await?,
+    }
+
+    find_user(db, user_id).await
+}
+
+//New users get `preferred_username` when it's valid and free, and an unknown random password
+async fn find_or_create_user<C: ConnectionTrait>(
+    db: &C,
+    issuer: &str,
+    claims: &IdTokenClaims,
+) -> Result<user::Model, Response> {
+    if let Some(identity) = find_identity(db, issuer, &claims.sub).await? {
+        return find_user(db, identity.user_id).await;
+    }
+
+    let internal_error = |err: ApiError| {
+        to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(err),
+        )
+    };
+
+    let mut username = None;
+    if let Some(preferred) = claims
+        .preferred_username
+        .as_ref()
+        .filter(|name| USERNAME_REGEX.is_match(name))
+    {
+        match UserEntity::find()
+            .filter(user::Column::Username.eq(preferred))
+            .one(db)
+            .await
+        {
+            Ok(None) => username = Some(preferred.clone()),
+            Ok(Some(_)) => {}
+            Err(err) => return Err(internal_error(ApiError::DbError(err.to_string()))),
+        }
+    }
+    let username =
+        username.unwrap_or_else(|| format!("oidc_{}", &Uuid::new_v4().simple().to_string()[..12]));
+
+    let (random_password, _) = generate_random_token();
+    let password = hash_password(&random_password)
+        .map_err(|err| internal_error(ApiError::PasswordHashFailed(err.to_string())))?;
+
+    let model = user::ActiveModel {
+        username: Set(username),
+        password: Set(password),
+        //Only verified addresses, password reset mails go there
+        email: Set(claims
+            .email
+            .clone()
+            .filter(|_| claims.email_verified == Some(true))),
+        role: Set(role::USER.to_owned()),
+        must_change_password: Set(false),
+        totp_enabled: Set(false),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|err| internal_error(ApiError::DbError(err.to_string())))?;
+
+    insert_identity(db, model.id, issuer, &claims.sub).await?;
+    Ok(model)
+}
+
+fn not_configured() -> Response {
+    let tmp = "OpenID Connect login is not configured".to_string();
+    to_response(
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "error": tmp
+            })),
+        ),
+        Err(ApiError::General(tmp)),
+    )
+}
+
+fn provider_error(err: OidcError) -> Response {
+    to_response(
+        (
+            StatusCode::BAD_GATEWAY,
+            Json(json!({
+                "error": "OpenID Connect provider is unavailable"
+            })),
+        ),
+        Err(ApiError::General(err.to_string())),
+    )
+}
+
+//Structs
+#[derive(Deserialize)]
+struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
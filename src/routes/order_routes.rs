@@ -0,0 +1,1398 @@
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    middleware,
+    response::Response,
+    routing::{get, patch, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, FromQueryResult, JoinType, QueryFilter, QueryOrder, QuerySelect, RelationTrait,
+    Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use validator::Validate;
+
+use crate::entities::{
+    cart::{self, Entity as CartEntity},
+    category,
+    money::{Currency, MinorUnits},
+    order::{self, Entity as OrderEntity, Status},
+    order_part::{self, Entity as OrderPartEntity},
+    order_status_history::{self, Entity as OrderStatusHistoryEntity},
+    permission, product, product_variant,
+    stock_movement::Reason,
+    user,
+};
+use crate::middleware::{
+    auth::{auth_middleware, Claims},
+    logging::{to_response, ApiError},
+};
+use crate::routes::product_routes::{put_stock, take_stock, StockItem, MAX_STOCK_QUANTITY};
+
+//ROUTERS
+pub fn order_routes() -> Router {
+    Router::new()
+        .route("/order", get(get_orders).post(checkout))
+        .route("/order/:id", get(get_order))
+        .route("/order/:id/cancel", post(cancel_order))
+        .layer(middleware::from_fn_with_state(
+            permission::ORDER_PLACE,
+            auth_middleware,
+        ))
+}
+
+pub fn admin_order_routes() -> Router {
+    Router::new()
+        .route("/order", get(admin_get_orders))
+        .route("/order/:id", get(admin_get_order))
+        .route(
+            "/order/:id/item/:item_id",
+            patch(admin_patch_order_item).delete(admin_remove_order_item),
+        )
+        .route("/order/:id/status", patch(patch_order_status))
+        .route("/order/:id/cancel", post(admin_cancel_order))
+        .route(
+            "/order/:id/refund",
+            post(admin_refund_order).route_layer(middleware::from_fn_with_state(
+                permission::ORDER_REFUND,
+                auth_middleware,
+            )),
+        )
+        .layer(middleware::from_fn_with_state(
+            permission::ORDER_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+async fn checkout(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let user_id = claims.user_id;
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let cart_items = match CartEntity::find()
+        .filter(cart::Column::UserId.eq(user_id))
+        .select_only()
+        .column_as(cart::Column::Quantity, "quantity")
+        .column_as(product::Column::Id, "product_id")
+        .column_as(product::Column::Name, "product_name")
+        .column_as(product::Column::Price, "unit_price")
+        .column_as(product::Column::Currency, "currency")
+        .column_as(product::Column::IsAvailable, "is_available")
+        .column_as(category::Column::Name, "category_name")
+        .column_as(category::Column::IsAvailable, "category_available")
+        .column_as(cart::Column::VariantId, "variant_id")
+        .column_as(product_variant::Column::Name, "variant_name")
+        .column_as(product_variant::Column::Price, "variant_price")
+        .column_as(product_variant::Column::IsAvailable, "variant_available")
+        .join(JoinType::InnerJoin, cart::Relation::Product.def())
+        .join(JoinType::InnerJoin, product::Relation::Category.def())
+        .join(JoinType::LeftJoin, cart::Relation::Variant.def())
+        .into_model::<PrepareOrderItem>()
+        .all(&txn)
+        .await
+    {
+        Ok(items) => items,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if cart_items.is_empty() {
+        let _ = txn.rollback().await;
+        let tmp = "Cart is empty".to_owned();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    //Whole order is rejected, if at least one product can't be bought
+    let unavailable: Vec<i32> = cart_items
+        .iter()
+        .filter(|item| {
+            !(item.is_available
+                && item.category_available
+                && item.variant_available.unwrap_or(true))
+        })
+        .map(|item| item.product_id)
+        .collect();
+
+    if !unavailable.is_empty() {
+        let _ = txn.rollback().await;
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Some products are not available",
+                    "product_ids": unavailable
+                })),
+            ),
+            Err(ApiError::General(format!(
+                "Unavailable products in cart: {:?}",
+                unavailable
+            ))),
+        );
+    }
+
+    //Totals are summed in minor units of a single currency
+    let currency = cart_items[0].currency;
+    if cart_items.iter().any(|item| item.currency != currency) {
+        let _ = txn.rollback().await;
+        let tmp = "Products with different currencies can't be ordered together".to_owned();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    let subtotal = cart_items.iter().try_fold(0 as MinorUnits, |sum, item| {
+        item.price()
+            .checked_mul(item.quantity as MinorUnits)?
+            .checked_add(sum)
+    });
+    let subtotal = match subtotal {
+        Some(subtotal) => subtotal,
+        None => {
+            let _ = txn.rollback().await;
+            let tmp = "Order total is too large".to_owned();
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            );
+        }
+    };
+
+    let new_order = order::ActiveModel {
+        status: Set(Status::Created),
+        user_id: Set(user_id),
+        created_at: Set(Utc::now()),
+        subtotal: Set(subtotal),
+        total: Set(subtotal),
+        currency: Set(currency),
+        ..Default::default()
+    };
+
+    let order_id = match OrderEntity::insert(new_order).exec(&txn).await {
+        Ok(result) => result.last_insert_id,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //Stock is taken with a conditional update, so parallel checkouts can't oversell
+    for item in &cart_items {
+        match take_stock(
+            &txn,
+            StockItem {
+                product_id: item.product_id,
+                variant_id: item.variant_id,
+            },
+            item.quantity as i32,
+            Reason::Checkout,
+            Some(order_id),
+            user_id,
+            None,
+        )
+        .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = txn.rollback().await;
+                return to_response(
+                    (
+                        StatusCode::CONFLICT,
+                        Json(json!({
+                            "error": "Not enough stock",
+                            "product_ids": [item.product_id]
+                        })),
+                    ),
+                    Err(ApiError::ValidationFail(format!(
+                        "Not enough stock of product {}",
+                        item.product_id
+                    ))),
+                );
+            }
+            Err(err) => {
+                let _ = txn.rollback().await;
+                return to_response(
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({
+                            "error": "Internal server error"
+                        })),
+                    ),
+                    Err(ApiError::DbError(err.to_string())),
+                );
+            }
+        }
+    }
+
+    let parts = cart_items.into_iter().map(|item| order_part::ActiveModel {
+        quantity: Set(item.quantity as i32),
+        product_id: Set(item.product_id),
+        variant_id: Set(item.variant_id),
+        order_id: Set(order_id),
+        unit_price: Set(item.price()),
+        product_name: Set(item.product_name),
+        category_name: Set(item.category_name),
+        variant_name: Set(item.variant_name),
+        ..Default::default()
+    });
+
+    if let Err(err) = OrderPartEntity::insert_many(parts).exec(&txn).await {
+        let _ = txn.rollback().await;
+        return to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        );
+    }
+
+    let history_entry = order_status_history::ActiveModel {
+        order_id: Set(order_id),
+        from_status: Set(None),
+        to_status: Set(Status::Created),
+        changed_by: Set(user_id),
+        changed_at: Set(Utc::now()),
+        note: Set(None),
+        ..Default::default()
+    };
+
+    if let Err(err) = OrderStatusHistoryEntity::insert(history_entry)
+        .exec(&txn)
+        .await
+    {
+        let _ = txn.rollback().await;
+        return to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        );
+    }
+
+    if let Err(err) = CartEntity::delete_many()
+        .filter(cart::Column::UserId.eq(user_id))
+        .exec(&txn)
+        .await
+    {
+        let _ = txn.rollback().await;
+        return to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        );
+    }
+
+    match txn.commit().await {
+        Ok(_) => to_response(
+            (
+                StatusCode::CREATED,
+                Json(json!({
+                    "message": "Order created successfully",
+                    "order_id": order_id,
+                    "total": subtotal
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn get_orders(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Query(query): Query<OrderQuery>,
+) -> Response {
+    let user_id = claims.user_id;
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let mut condition = Condition::all().add(order::Column::UserId.eq(user_id));
+
+    //Filter zone
+    if let Some(status) = query.status {
+        condition = condition.add(order::Column::Status.eq(status));
+    }
+
+    //Sorting zone
+    let order = match query.order.as_deref() {
+        Some("desc") => sea_orm::Order::Desc,
+        _ => sea_orm::Order::Asc,
+    };
+
+    let sort_column = match query.sort_by.as_deref() {
+        Some("total") => order::Column::Total,
+        _ => order::Column::CreatedAt,
+    };
+
+    //Pagination zone
+    //Pages start at 1, page=0 is treated as the first one
+    let page: u64 = query.page.unwrap_or(1).max(1);
+    let page_size: u64 = query.page_size.unwrap_or(10);
+
+    let items = OrderEntity::find()
+        .filter(condition)
+        .order_by(sort_column, order)
+        .limit(page_size)
+        .offset((page - 1).saturating_mul(page_size))
+        .all(&txn)
+        .await;
+
+    match items {
+        Ok(items) => to_response(Json(items), Ok(())),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Internal server error"})),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn get_order(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = OrderEntity::find_by_id(id)
+        .filter(order::Column::UserId.eq(claims.user_id))
+        .one(&txn)
+        .await;
+
+    match result {
+        Ok(Some(order)) => match order_details(&txn, order).await {
+            Ok(details) => to_response((StatusCode::OK, Json(details)), Ok(())),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Ok(None) => {
+            let tmp = format!("No order with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn admin_get_orders(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Query(query): Query<AdminOrdersQuery>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let mut condition = Condition::all();
+
+    //Filter zone
+    if let Some(status) = query.status {
+        condition = condition.add(order::Column::Status.eq(status));
+    }
+    if let Some(user_id) = query.user_id {
+        condition = condition.add(order::Column::UserId.eq(user_id));
+    }
+    if let Some(date_from) = query.date_from {
+        condition = condition.add(order::Column::CreatedAt.gte(date_from));
+    }
+    if let Some(date_to) = query.date_to {
+        condition = condition.add(order::Column::CreatedAt.lte(date_to));
+    }
+    if let Some(total_bottom) = query.total_bottom {
+        condition = condition.add(order::Column::Total.gte(total_bottom));
+    }
+    if let Some(total_top) = query.total_top {
+        condition = condition.add(order::Column::Total.lte(total_top));
+    }
+
+    //Well, simple enough.
+    if let Some(query) = query.query {
+        let mut query_condition =
+            Condition::any().add(user::Column::Username.contains(query.clone()));
+        let id_search = query.parse::<u32>().ok();
+        if let Some(id) = id_search {
+            query_condition = query_condition.add(order::Column::Id.eq(id));
+        }
+
+        condition = condition.add(query_condition);
+    }
+
+    //Sorting zone
+    let order = match query.order.as_deref() {
+        Some("desc") => sea_orm::Order::Desc,
+        _ => sea_orm::Order::Asc,
+    };
+
+    let sort_column = match query.sort_by.as_deref() {
+        Some("date") => order::Column::CreatedAt,
+        Some("total") => order::Column::Total,
+        Some("status") => order::Column::Status,
+        Some("user_id") => order::Column::UserId,
+        _ => order::Column::Id,
+    };
+
+    //Pagination zone
+    //Pages start at 1, page=0 is treated as the first one
+    let page: u64 = query.page.unwrap_or(1).max(1);
+    let page_size: u64 = query.page_size.unwrap_or(10);
+
+    let items = OrderEntity::find()
+        .filter(condition)
+        .join(JoinType::InnerJoin, order::Relation::User.def())
+        .column_as(user::Column::Username, "username")
+        .order_by(sort_column, order)
+        .limit(page_size)
+        .offset((page - 1).saturating_mul(page_size))
+        .into_model::<AdminOrderResponse>()
+        .all(&txn)
+        .await;
+
+    match items {
+        Ok(items) => to_response(Json(items), Ok(())),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Internal server error"})),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn admin_get_order(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    match OrderEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(order)) => match order_details(&txn, order).await {
+            Ok(details) => to_response((StatusCode::OK, Json(details)), Ok(())),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Ok(None) => {
+            let tmp = format!("No order with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn admin_patch_order_item(
+    Path((id, item_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<PatchOrderItem>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": format!("Quantity should be at most {}", MAX_STOCK_QUANTITY)
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    edit_order_item(db, id, item_id, payload.quantity, claims.user_id).await
+}
+
+async fn admin_remove_order_item(
+    Path((id, item_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    edit_order_item(db, id, item_id, 0, claims.user_id).await
+}
+
+async fn patch_order_status(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<PatchOrderStatus>,
+) -> Response {
+    change_order_status(
+        db,
+        id,
+        None,
+        claims.user_id,
+        payload.status,
+        None,
+        |from, to| from.can_transition_to(to),
+    )
+    .await
+}
+
+async fn cancel_order(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CancelOrder>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Reason should be between 3 and 255 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    //User can cancel only his own orders, and only before they are sent
+    change_order_status(
+        db,
+        id,
+        Some(claims.user_id),
+        claims.user_id,
+        Status::Cancelled,
+        Some(payload.reason),
+        |from, _| from.can_be_cancelled_by_user(),
+    )
+    .await
+}
+
+async fn admin_cancel_order(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CancelOrder>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Reason should be between 3 and 255 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    change_order_status(
+        db,
+        id,
+        None,
+        claims.user_id,
+        Status::Cancelled,
+        Some(payload.reason),
+        |from, _| !matches!(from, Status::Cancelled | Status::Refunded),
+    )
+    .await
+}
+
+async fn admin_refund_order(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CancelOrder>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Reason should be between 3 and 255 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    change_order_status(
+        db,
+        id,
+        None,
+        claims.user_id,
+        Status::Refunded,
+        Some(payload.reason),
+        |from, _| from != Status::Refunded,
+    )
+    .await
+}
+
+//utils
+//Sets quantity of the order line (0 removes it) and recomputes order totals
+async fn edit_order_item(
+    db: Arc<DatabaseConnection>,
+    id: i32,
+    item_id: i32,
+    quantity: u32,
+    changed_by: i32,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let order = match OrderEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(order)) => order,
+        Ok(None) => {
+            let tmp = format!("No order with {} id was found.", id);
+            return to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if !order.status.is_editable() {
+        let tmp = format!("Order with status {} can't be edited", order.status);
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    let lines = match OrderPartEntity::find()
+        .filter(order_part::Column::OrderId.eq(id))
+        .all(&txn)
+        .await
+    {
+        Ok(lines) => lines,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let line = match lines.iter().find(|line| line.id == item_id) {
+        Some(line) => line.clone(),
+        None => {
+            let tmp = format!("No item with {} id was found in order {}.", item_id, id);
+            return to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+    };
+
+    //Stock of a deleted variant can't be taken or returned
+    if line.variant_deleted() && quantity as i32 != line.quantity {
+        let tmp = format!("Variant of item {} was deleted", item_id);
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    //Empty order should be cancelled instead
+    if quantity == 0 && lines.len() == 1 {
+        let tmp = "Can't remove the last item of the order".to_owned();
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    let subtotal = lines.iter().try_fold(0 as MinorUnits, |sum, entry| {
+        let entry_quantity = if entry.id == item_id {
+            quantity as MinorUnits
+        } else {
+            entry.quantity as MinorUnits
+        };
+        entry.unit_price.checked_mul(entry_quantity)?.checked_add(sum)
+    });
+    let subtotal = match subtotal {
+        Some(subtotal) => subtotal,
+        None => {
+            let tmp = "Order total is too large".to_owned();
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            );
+        }
+    };
+
+    let change = quantity as i32 - line.quantity;
+    let product_id = line.product_id;
+    let stock_item = StockItem {
+        product_id,
+        variant_id: line.variant_id,
+    };
+    let stock_result = match change {
+        0 => Ok(true),
+        _ if change > 0 => {
+            take_stock(
+                &txn,
+                stock_item,
+                change,
+                Reason::OrderEdit,
+                Some(id),
+                changed_by,
+                None,
+            )
+            .await
+        }
+        _ => {
+            put_stock(
+                &txn,
+                stock_item,
+                -change,
+                Reason::OrderEdit,
+                Some(id),
+                changed_by,
+                None,
+            )
+            .await
+        }
+    };
+
+    match stock_result {
+        Ok(true) => {}
+        Ok(false) => {
+            let _ = txn.rollback().await;
+            let tmp = if change > 0 {
+                format!("Not enough stock of product {}", product_id)
+            } else {
+                format!(
+                    "Stock of product {} can't go above {}",
+                    product_id, MAX_STOCK_QUANTITY
+                )
+            };
+            return to_response(
+                (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            );
+        }
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let mut line: order_part::ActiveModel = line.into();
+    let result: Result<(), DbErr> = match quantity {
+        0 => line.delete(&txn).await.map(|_| ()),
+        _ => {
+            line.quantity = Set(quantity as i32);
+            line.update(&txn).await.map(|_| ())
+        }
+    };
+
+    let mut order: order::ActiveModel = order.into();
+    order.subtotal = Set(subtotal);
+    order.total = Set(subtotal);
+
+    let result = match result {
+        Ok(_) => order.update(&txn).await.map(|_| ()),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Resource patched successfully",
+                        "total": subtotal
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": "Failed to patch this resource"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+//Puts every line of the order back to stock
+async fn return_order_stock<C: ConnectionTrait>(
+    db: &C,
+    order_id: i32,
+    changed_by: i32,
+) -> Result<(), DbErr> {
+    let lines = OrderPartEntity::find()
+        .filter(order_part::Column::OrderId.eq(order_id))
+        .all(db)
+        .await?;
+
+    for line in lines {
+        //Base product stock isn't inflated with the units of a deleted variant
+        if line.variant_deleted() {
+            tracing::warn!(
+                event = "order_stock_not_returned",
+                order_id,
+                product_id = line.product_id,
+                quantity = line.quantity,
+                reason = "variant_deleted"
+            );
+            continue;
+        }
+
+        let returned = put_stock(
+            db,
+            StockItem {
+                product_id: line.product_id,
+                variant_id: line.variant_id,
+            },
+            line.quantity,
+            Reason::Cancellation,
+            Some(order_id),
+            changed_by,
+            None,
+        )
+        .await?;
+        //Cancellation still goes through, the line just isn't restocked
+        if !returned {
+            tracing::warn!(
+                event = "order_stock_not_returned",
+                order_id,
+                product_id = line.product_id,
+                quantity = line.quantity
+            );
+        }
+    }
+
+    Ok(())
+}
+
+//Collects order lines and status history of the order
+async fn order_details<C: ConnectionTrait>(
+    db: &C,
+    order: order::Model,
+) -> Result<OrderDetailsResponse, DbErr> {
+    let items = OrderPartEntity::find()
+        .filter(order_part::Column::OrderId.eq(order.id))
+        .select_only()
+        .column_as(order_part::Column::Id, "id")
+        .column_as(order_part::Column::ProductId, "product_id")
+        .column_as(order_part::Column::VariantId, "variant_id")
+        .column_as(order_part::Column::ProductName, "name")
+        .column_as(order_part::Column::VariantName, "variant_name")
+        .column_as(order_part::Column::UnitPrice, "price")
+        .column_as(order_part::Column::Quantity, "quantity")
+        .column_as(product::Column::ImageId, "image_id")
+        .column_as(order_part::Column::CategoryName, "category_name")
+        .column_as(product::Column::IsAvailable, "is_available")
+        .join(JoinType::InnerJoin, order_part::Relation::Product.def())
+        .into_model::<OrderLineResponse>()
+        .all(db)
+        .await?;
+
+    let history = OrderStatusHistoryEntity::find()
+        .filter(order_status_history::Column::OrderId.eq(order.id))
+        .order_by(order_status_history::Column::ChangedAt, sea_orm::Order::Asc)
+        .all(db)
+        .await?;
+
+    Ok(OrderDetailsResponse {
+        order,
+        items,
+        history,
+    })
+}
+
+//Moves order to `to` status and writes history entry, if `allowed` agrees with the transition.
+//`owner_id` limits search to orders of that user.
+async fn change_order_status(
+    db: Arc<DatabaseConnection>,
+    id: i32,
+    owner_id: Option<i32>,
+    changed_by: i32,
+    to: Status,
+    note: Option<String>,
+    allowed: impl Fn(Status, Status) -> bool,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let mut finder = OrderEntity::find_by_id(id);
+    if let Some(owner_id) = owner_id {
+        finder = finder.filter(order::Column::UserId.eq(owner_id));
+    }
+
+    let order = match finder.one(&txn).await {
+        Ok(Some(order)) => order,
+        Ok(None) => {
+            let tmp = format!("No order with {} id was found.", id);
+            return to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let from = order.status;
+    if !allowed(from, to) {
+        let tmp = format!("Can't change order status from {} to {}", from, to);
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    //Cancelled goods go back to stock, as well as refunded ones that were never sent
+    let restock = to == Status::Cancelled || (to == Status::Refunded && from.is_editable());
+
+    let mut order: order::ActiveModel = order.into();
+    order.status = Set(to);
+
+    let history_entry = order_status_history::ActiveModel {
+        order_id: Set(id),
+        from_status: Set(Some(from)),
+        to_status: Set(to),
+        changed_by: Set(changed_by),
+        changed_at: Set(Utc::now()),
+        note: Set(note),
+        ..Default::default()
+    };
+
+    let result = match order.update(&txn).await {
+        Ok(_) => OrderStatusHistoryEntity::insert(history_entry)
+            .exec(&txn)
+            .await
+            .map(|_| ()),
+        Err(err) => Err(err),
+    };
+
+    let result = match result {
+        Ok(_) if restock => return_order_stock(&txn, id, changed_by).await,
+        other => other,
+    };
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Resource patched successfully"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": "Failed to patch this resource"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+//Structs
+//Preparing order lines by checkout
+#[derive(Debug, Deserialize, FromQueryResult)]
+struct PrepareOrderItem {
+    quantity: u32,
+    product_id: i32,
+    product_name: String,
+    unit_price: MinorUnits,
+    currency: Currency,
+    is_available: bool,
+    category_name: String,
+    category_available: bool,
+    //Set for products that are sold in variants
+    variant_id: Option<i32>,
+    variant_name: Option<String>,
+    variant_price: Option<MinorUnits>,
+    variant_available: Option<bool>,
+}
+
+impl PrepareOrderItem {
+    //Variant price replaces the product one
+    fn price(&self) -> MinorUnits {
+        self.variant_price.unwrap_or(self.unit_price)
+    }
+}
+
+#[derive(Deserialize)]
+struct PatchOrderStatus {
+    status: Status,
+}
+
+#[derive(Deserialize, Validate)]
+struct CancelOrder {
+    #[validate(length(min = 3, max = 255))]
+    reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OrderQuery {
+    //sort zone
+    sort_by: Option<String>, //Enum better?? "date", "total"
+    order: Option<String>,   //Enum better??
+    //filter zone
+    status: Option<Status>,
+    //pagination zone
+    page: Option<u64>, //required by sea_orm to be u64, why? trait into u64 or something
+    page_size: Option<u64>, //required by sea_orm to be u64, why? trait into u64 or something
+}
+
+//Fields are the same as in CartResponse, but name, price and category_name are taken from the order snapshot
+#[derive(Serialize, FromQueryResult)]
+struct OrderLineResponse {
+    id: i32,
+    product_id: i32,
+    variant_id: Option<i32>,
+    name: String,
+    variant_name: Option<String>,
+    price: MinorUnits,
+    quantity: i32,
+    image_id: i32,
+    category_name: String,
+    is_available: bool,
+}
+
+#[derive(Serialize)]
+struct OrderDetailsResponse {
+    #[serde(flatten)]
+    order: order::Model,
+    items: Vec<OrderLineResponse>,
+    history: Vec<order_status_history::Model>,
+}
+
+#[derive(Deserialize)]
+struct AdminOrdersQuery {
+    //Query
+    query: Option<String>,
+    //Sort zone
+    sort_by: Option<String>, //Enum better?? "id", "date", "total", "status", "user_id"
+    order: Option<String>,   //Enum better??
+    //filter zone
+    status: Option<Status>,
+    user_id: Option<i32>,
+    date_from: Option<DateTime<Utc>>,
+    date_to: Option<DateTime<Utc>>,
+    total_bottom: Option<MinorUnits>,
+    total_top: Option<MinorUnits>,
+    //pagination zone
+    page: Option<u64>, //required by sea_orm to be u64, why? trait into u64 or something
+    page_size: Option<u64>, //required by sea_orm to be u64, why? trait into u64 or something
+}
+
+#[derive(Serialize, FromQueryResult)]
+struct AdminOrderResponse {
+    id: i32,
+    status: Status,
+    user_id: i32,
+    username: String,
+    created_at: DateTime<Utc>,
+    subtotal: MinorUnits,
+    total: MinorUnits,
+    currency: Currency,
+}
+
+#[derive(Deserialize, Validate)]
+struct PatchOrderItem {
+    #[validate(range(max = MAX_STOCK_QUANTITY))]
+    quantity: u32,
+}
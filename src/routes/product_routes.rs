@@ -6,9 +6,11 @@ use axum::{
     routing::{get, patch, post},
     Json, Router,
 };
+use chrono::Utc;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, FromQueryResult,
-    JoinType, QueryFilter, QueryOrder, QuerySelect, RelationTrait, Set, TransactionTrait,
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DbErr, EntityTrait, FromQueryResult, JoinType, QueryFilter, QueryOrder, QuerySelect,
+    RelationTrait, Set, TransactionTrait,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -17,12 +19,16 @@ use validator::Validate;
 
 use crate::entities::{
     category, image,
+    money::{Currency, MinorUnits},
+    permission,
     product::{self, Entity as ProductEntity},
+    product_image::{self, Entity as ProductImageEntity},
+    product_variant::{self, Entity as ProductVariantEntity},
+    stock_movement::{self, Entity as StockMovementEntity, Reason},
     user,
-    user::Role,
 };
 use crate::middleware::{
-    auth::auth_middleware,
+    auth::{auth_middleware, Claims},
     logging::{to_response, ApiError},
 };
 
@@ -37,15 +43,40 @@ pub fn admin_product_routes() -> Router {
     Router::new()
         .route("/product", post(create_product).get(admin_get_products))
         .route("/product/:id", patch(patch_product).delete(delete_product))
+        .route(
+            "/product/:id/variant",
+            get(admin_get_variants).post(create_variant),
+        )
+        .route(
+            "/product/:id/variant/:variant_id",
+            patch(patch_variant).delete(delete_variant),
+        )
+        .route(
+            "/product/:id/image",
+            post(attach_product_image).put(reorder_product_images),
+        )
+        .route(
+            "/product/:id/image/:image_id",
+            patch(patch_product_image).delete(detach_product_image),
+        )
+        .route("/product/:id/restock", post(restock_product))
+        .route(
+            "/product/:id/stock",
+            get(get_stock_movements).post(adjust_stock),
+        )
         .layer(middleware::from_fn_with_state(
-            Role::Admin,
+            permission::PRODUCT_WRITE,
             auth_middleware,
         ))
 }
 
+//Stock is stored as i32, bigger quantities would wrap around
+pub const MAX_STOCK_QUANTITY: u32 = i32::MAX as u32;
+
 //ROUTES
 async fn create_product(
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
     Json(payload): Json<CreateProduct>,
 ) -> Response {
     if let Some(err) = payload.validate().err() {
@@ -53,7 +84,7 @@ async fn create_product(
             (
                 StatusCode::BAD_REQUEST,
                 Json(json!({
-                    "error": "Name length should be at least 3 characters"
+                    "error": "Name length should be at least 3 characters and price can't be negative"
                 })),
             ),
             Err(ApiError::ValidationFail(err.to_string())),
@@ -77,18 +108,42 @@ async fn create_product(
 
     match user::Entity::find_by_id(payload.image_id).one(&txn).await {
         Ok(Some(_)) => {
+            let stock = payload.stock.unwrap_or_default() as i32;
             let new_product = product::ActiveModel {
                 name: Set(payload.name),
                 price: Set(payload.price),
+                currency: Set(payload.currency.unwrap_or_default()),
                 description: Set(payload.description),
                 image_id: Set(payload.image_id),
                 category_id: Set(payload.category_id),
                 is_featured: Set(payload.is_featured.unwrap_or_default()),
-                is_available: Set(payload.is_available.unwrap_or_default()),
+                //Nothing to sell without stock
+                is_available: Set(payload.is_available.unwrap_or_default() && stock > 0),
+                stock: Set(0),
                 ..Default::default()
             };
 
-            match product::Entity::insert(new_product).exec(&txn).await {
+            //Initial stock is logged as the first restock
+            let result = match product::Entity::insert(new_product).exec(&txn).await {
+                Ok(result) if stock > 0 => put_stock(
+                    &txn,
+                    StockItem {
+                        product_id: result.last_insert_id,
+                        variant_id: None,
+                    },
+                    stock,
+                    Reason::Restock,
+                    None,
+                    claims.user_id,
+                    Some("Initial stock".to_owned()),
+                )
+                .await
+                .map(|_| ()),
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            match result {
                 Ok(_) => match txn.commit().await {
                     Ok(_) => to_response(
                         (
@@ -225,6 +280,7 @@ async fn get_products(
         .column_as(product::Column::Id, "product_id")
         .column_as(product::Column::Name, "name")
         .column_as(product::Column::Price, "price")
+        .column_as(product::Column::Currency, "currency")
         .column_as(product::Column::Description, "description")
         .column_as(product::Column::ImageId, "image_id")
         .column_as(category::Column::Name, "category_name")
@@ -272,6 +328,7 @@ async fn get_product(
         .column_as(product::Column::Id, "id")
         .column_as(product::Column::Name, "name")
         .column_as(product::Column::Price, "price")
+        .column_as(product::Column::Currency, "currency")
         .column_as(product::Column::Description, "description")
         .column_as(product::Column::ImageId, "image_id")
         .column_as(category::Column::Name, "category_name")
@@ -279,11 +336,11 @@ async fn get_product(
         .one(&txn)
         .await;
 
-    match result {
-        Ok(Some(prod)) => to_response((StatusCode::OK, Json(prod)), Ok(())),
+    let product = match result {
+        Ok(Some(product)) => product,
         Ok(None) => {
             let tmp = format!("No product with {} id was found.", id);
-            to_response(
+            return to_response(
                 (
                     StatusCode::BAD_REQUEST,
                     Json(json!({
@@ -291,8 +348,74 @@ async fn get_product(
                     })),
                 ),
                 Err(ApiError::General(tmp)),
-            )
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let images = ProductImageEntity::find()
+        .filter(product_image::Column::ProductId.eq(id))
+        .select_only()
+        .column_as(product_image::Column::ImageId, "image_id")
+        .column_as(product_image::Column::Position, "position")
+        .column_as(product_image::Column::AltText, "alt_text")
+        .order_by(product_image::Column::Position, sea_orm::Order::Asc)
+        .into_model::<GalleryImageResponse>()
+        .all(&txn)
+        .await;
+
+    let images = match images {
+        Ok(images) => images,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
         }
+    };
+
+    let variants = ProductVariantEntity::find()
+        .filter(product_variant::Column::ProductId.eq(id))
+        .filter(product_variant::Column::IsAvailable.eq(true))
+        .select_only()
+        .column_as(product_variant::Column::Id, "id")
+        .column_as(product_variant::Column::Name, "name")
+        .column_as(product_variant::Column::Size, "size")
+        .column_as(product_variant::Column::Flavour, "flavour")
+        .column_as(product_variant::Column::PackCount, "pack_count")
+        .column_as(product_variant::Column::Price, "price")
+        .order_by(product_variant::Column::Price, sea_orm::Order::Asc)
+        .into_model::<VariantResponse>()
+        .all(&txn)
+        .await;
+
+    match variants {
+        Ok(variants) => to_response(
+            (
+                StatusCode::OK,
+                Json(ProductDetailsResponse {
+                    product,
+                    images,
+                    variants,
+                }),
+            ),
+            Ok(()),
+        ),
         Err(err) => to_response(
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -305,8 +428,8 @@ async fn get_product(
     }
 }
 
-async fn admin_get_products(
-    Query(params): Query<AdminProductsQuery>,
+async fn admin_get_variants(
+    Path(id): Path<i32>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
 ) -> Response {
     let txn = match db.begin().await {
@@ -324,71 +447,13 @@ async fn admin_get_products(
         }
     };
 
-    let mut condition = Condition::all();
-
-    //Filter zone
-    if let Some(price_bottom) = params.price_bottom {
-        condition = condition.add(product::Column::Price.gte(price_bottom));
-    }
-    if let Some(price_top) = params.price_top {
-        condition = condition.add(product::Column::Price.lte(price_top));
-    }
-    if let Some(category_ids) = params.category_ids {
-        condition = condition.add(product::Column::CategoryId.is_in(category_ids));
-    }
-    if params.only_available.unwrap_or(false) {
-        condition = condition.add(product::Column::IsAvailable.eq(true));
-    }
-    if params.only_featured.unwrap_or(false) {
-        condition = condition.add(product::Column::IsFeatured.eq(true))
-    }
-
-    //Sorting zone
-    let order = match params.order.as_deref() {
-        Some("desc") => sea_orm::Order::Desc,
-        _ => sea_orm::Order::Asc,
-    };
-
-    let sort_column = match params.sort_by.as_deref() {
-        Some("price") => product::Column::Price,
-        Some("is_available") => product::Column::IsAvailable,
-        Some("is_featured") => product::Column::IsFeatured,
-        Some("name") => product::Column::Name,
-        Some("image_id") => product::Column::ImageId,
-        Some("category_id") => product::Column::CategoryId,
-        _ => product::Column::Id,
-    };
-
-    condition = condition.add(category::Column::IsAvailable.eq(true));
-
-    //Pagination zone
-    let page: u64 = params.page.unwrap_or(1);
-    let page_size: u64 = params.page_size.unwrap_or(10);
-
-    //Response buidling
-    let mut items = product::Entity::find();
-
-    //adding query
-    if let Some(query) = params.query {
-        let mut query_condition =
-            Condition::any().add(category::Column::Name.contains(query.clone()));
-        let id_search = query.parse::<u32>().ok();
-        if let Some(id) = id_search {
-            query_condition = query_condition.add(category::Column::Id.eq(id));
-        }
-
-        items = items.filter(query_condition);
-    }
-
-    let items = items
-        .filter(condition)
-        .order_by(sort_column, order)
-        .limit(page_size)
-        .offset((page - 1) * page_size)
+    let result = ProductVariantEntity::find()
+        .filter(product_variant::Column::ProductId.eq(id))
+        .order_by(product_variant::Column::Id, sea_orm::Order::Asc)
         .all(&txn)
         .await;
 
-    match items {
+    match result {
         Ok(items) => to_response(Json(items), Ok(())),
         Err(err) => to_response(
             (
@@ -400,11 +465,24 @@ async fn admin_get_products(
     }
 }
 
-async fn patch_product(
+async fn create_variant(
     Path(id): Path<i32>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
-    Json(payload): Json<PatchProductPayload>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<CreateVariant>,
 ) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Name length should be at least 3 characters and price can't be negative"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
     let txn = match db.begin().await {
         Ok(txn) => txn,
         Err(_) => {
@@ -420,148 +498,1330 @@ async fn patch_product(
         }
     };
 
-    let result = ProductEntity::find_by_id(id).one(&txn).await;
-    match result {
-        Ok(Some(product)) => {
-            let mut product: product::ActiveModel = product.into();
-
-            if let Some(name) = payload.name.clone() {
-                //or just skip that, if validation fails?
-                if let Some(err) = payload.validate().err() {
-                    return to_response(
-                        (
-                            StatusCode::BAD_REQUEST,
-                            Json(json!({
-                                "error": "Name length should be at least 3 characters"
-                            })),
-                        ),
-                        Err(ApiError::ValidationFail(err.to_string())),
-                    );
-                }
-                product.name = Set(name);
-            }
-
-            if let Some(price) = payload.price {
-                product.price = Set(price);
-            }
-
-            if let Some(description) = payload.description {
-                product.description = Set(description);
-            }
-
-            if let Some(image_id) = payload.image_id {
-                match image::Entity::find_by_id(image_id).one(&txn).await {
-                    Ok(_) => product.image_id = Set(image_id),
-                    Err(err) => {
-                        return to_response(
-                            (
-                                StatusCode::BAD_REQUEST,
-                                Json(json!({
-                                    "error": format!("No image with {image_id} id was found")
-                                })),
-                            ),
-                            Err(ApiError::DbError(err.to_string())),
-                        );
-                    }
-                }
-            }
-
-            if let Some(category_id) = payload.category_id {
-                match category::Entity::find_by_id(category_id).one(&txn).await {
-                    Ok(_) => product.category_id = Set(category_id),
-                    Err(err) => {
-                        return to_response(
-                            (
-                                StatusCode::BAD_REQUEST,
-                                Json(json!({
-                                    "error": format!("No category with {category_id} id was found")
-                                })),
-                            ),
-                            Err(ApiError::DbError(err.to_string())),
-                        );
-                    }
-                }
-            }
-
-            if let Some(is_featured) = payload.is_featured {
-                product.is_featured = Set(is_featured);
-            }
-
-            if let Some(is_available) = payload.is_available {
-                product.is_available = Set(is_available);
-            }
-
-            let result = product.update(&txn).await;
-            match result {
-                Ok(_) => match txn.commit().await {
-                    Ok(_) => to_response(
-                        (
-                            StatusCode::OK,
-                            Json(json!({
-                                "message": "Resource patched successfully."
-                            })),
-                        ),
-                        Ok(()),
-                    ),
-                    Err(err) => to_response(
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Json(json!({"error": "Internal server error"})),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    ),
-                },
-                Err(err) => {
-                    //DB Failed / unique constraint
-                    let _ = txn.rollback().await;
-                    to_response(
-                        (
-                            StatusCode::BAD_REQUEST,
-                            Json(json!({
-                                "error": "Failed to patch this resource"
-                            })),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    )
-                }
-            }
-        }
+    let product = match ProductEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(product)) => product,
         Ok(None) => {
-            let tmp = format!("No image with {} id was found.", id);
-            to_response(
+            let tmp = format!("No product with {} id was found.", id);
+            return to_response(
                 (
-                    StatusCode::BAD_REQUEST,
+                    StatusCode::NOT_FOUND,
                     Json(json!({
                         "error": tmp
                     })),
                 ),
                 Err(ApiError::General(tmp)),
-            )
+            );
         }
-        Err(err) => to_response(
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({
-                    "error": "Internal server error."
-                })),
-            ),
-            Err(ApiError::DbError(err.to_string())),
-        ),
-    }
-}
-
-async fn delete_product(
-    Path(id): Path<i32>,
-    Extension(db): Extension<Arc<DatabaseConnection>>,
-) -> Response {
-    let txn = match db.begin().await {
-        Ok(txn) => txn,
-        Err(_) => {
+        Err(err) => {
             return to_response(
                 (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(json!({
-                        "error": "Internal server error"
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let stock = payload.stock.unwrap_or_default() as i32;
+    let is_available = payload.is_available.unwrap_or(true) && stock > 0;
+    let new_variant = product_variant::ActiveModel {
+        product_id: Set(id),
+        name: Set(payload.name),
+        size: Set(payload.size),
+        flavour: Set(payload.flavour),
+        pack_count: Set(payload.pack_count.map(|count| count as i32)),
+        price: Set(payload.price),
+        is_available: Set(is_available),
+        stock: Set(0),
+        ..Default::default()
+    };
+
+    let variant_id = match ProductVariantEntity::insert(new_variant).exec(&txn).await {
+        Ok(result) => result.last_insert_id,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //Initial stock is logged as the first restock
+    let mut result = match stock {
+        0 => Ok(true),
+        _ => {
+            put_stock(
+                &txn,
+                StockItem {
+                    product_id: id,
+                    variant_id: Some(variant_id),
+                },
+                stock,
+                Reason::Restock,
+                None,
+                claims.user_id,
+                Some("Initial stock".to_owned()),
+            )
+            .await
+        }
+    };
+
+    //Product with a stocked variant has something to sell
+    if is_available && !product.is_available && result.is_ok() {
+        let mut product: product::ActiveModel = product.into();
+        product.is_available = Set(true);
+        result = product.update(&txn).await.map(|_| true);
+    }
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "message": "Variant created successfully",
+                        "variant_id": variant_id
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn patch_variant(
+    Path((id, variant_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<PatchVariantPayload>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Name length should be at least 3 characters and price can't be negative"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductVariantEntity::find_by_id(variant_id)
+        .filter(product_variant::Column::ProductId.eq(id))
+        .one(&txn)
+        .await;
+
+    match result {
+        Ok(Some(variant)) => {
+            let mut variant: product_variant::ActiveModel = variant.into();
+
+            if let Some(name) = payload.name {
+                variant.name = Set(name);
+            }
+
+            if let Some(size) = payload.size {
+                variant.size = Set(Some(size));
+            }
+
+            if let Some(flavour) = payload.flavour {
+                variant.flavour = Set(Some(flavour));
+            }
+
+            if let Some(pack_count) = payload.pack_count {
+                variant.pack_count = Set(Some(pack_count as i32));
+            }
+
+            if let Some(price) = payload.price {
+                variant.price = Set(price);
+            }
+
+            if let Some(is_available) = payload.is_available {
+                variant.is_available = Set(is_available);
+            }
+
+            match variant.update(&txn).await {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource patched successfully."
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({"error": "Internal server error"})),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to patch this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!(
+                "No variant with {} id was found for product {}.",
+                variant_id, id
+            );
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Cart entries of the variant are removed with it, order lines keep their snapshot
+async fn delete_variant(
+    Path((id, variant_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductVariantEntity::find_by_id(variant_id)
+        .filter(product_variant::Column::ProductId.eq(id))
+        .one(&txn)
+        .await;
+
+    match result {
+        Ok(Some(variant)) => {
+            let variant: product_variant::ActiveModel = variant.into();
+            match variant.delete(&txn).await {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource deleted successfully."
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({"error": "Internal server error"})),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to delete this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!(
+                "No variant with {} id was found for product {}.",
+                variant_id, id
+            );
+            to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn admin_get_products(
+    Query(params): Query<AdminProductsQuery>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let mut condition = Condition::all();
+
+    //Filter zone
+    if let Some(price_bottom) = params.price_bottom {
+        condition = condition.add(product::Column::Price.gte(price_bottom));
+    }
+    if let Some(price_top) = params.price_top {
+        condition = condition.add(product::Column::Price.lte(price_top));
+    }
+    if let Some(category_ids) = params.category_ids {
+        condition = condition.add(product::Column::CategoryId.is_in(category_ids));
+    }
+    if params.only_available.unwrap_or(false) {
+        condition = condition.add(product::Column::IsAvailable.eq(true));
+    }
+    if params.only_featured.unwrap_or(false) {
+        condition = condition.add(product::Column::IsFeatured.eq(true))
+    }
+
+    //Sorting zone
+    let order = match params.order.as_deref() {
+        Some("desc") => sea_orm::Order::Desc,
+        _ => sea_orm::Order::Asc,
+    };
+
+    let sort_column = match params.sort_by.as_deref() {
+        Some("price") => product::Column::Price,
+        Some("is_available") => product::Column::IsAvailable,
+        Some("is_featured") => product::Column::IsFeatured,
+        Some("name") => product::Column::Name,
+        Some("image_id") => product::Column::ImageId,
+        Some("category_id") => product::Column::CategoryId,
+        _ => product::Column::Id,
+    };
+
+    condition = condition.add(category::Column::IsAvailable.eq(true));
+
+    //Pagination zone
+    let page: u64 = params.page.unwrap_or(1);
+    let page_size: u64 = params.page_size.unwrap_or(10);
+
+    //Response buidling
+    let mut items = product::Entity::find();
+
+    //adding query
+    if let Some(query) = params.query {
+        let mut query_condition =
+            Condition::any().add(category::Column::Name.contains(query.clone()));
+        let id_search = query.parse::<u32>().ok();
+        if let Some(id) = id_search {
+            query_condition = query_condition.add(category::Column::Id.eq(id));
+        }
+
+        items = items.filter(query_condition);
+    }
+
+    let items = items
+        .filter(condition)
+        .order_by(sort_column, order)
+        .limit(page_size)
+        .offset((page - 1) * page_size)
+        .all(&txn)
+        .await;
+
+    match items {
+        Ok(items) => to_response(Json(items), Ok(())),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Internal server error"})),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn patch_product(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<PatchProductPayload>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductEntity::find_by_id(id).one(&txn).await;
+    match result {
+        Ok(Some(product)) => {
+            let mut product: product::ActiveModel = product.into();
+
+            if let Some(name) = payload.name.clone() {
+                //or just skip that, if validation fails?
+                if let Some(err) = payload.validate().err() {
+                    return to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Name length should be at least 3 characters"
+                            })),
+                        ),
+                        Err(ApiError::ValidationFail(err.to_string())),
+                    );
+                }
+                product.name = Set(name);
+            }
+
+            if let Some(price) = payload.price {
+                if price < 0 {
+                    let tmp = "Price can't be negative".to_owned();
+                    return to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": tmp
+                            })),
+                        ),
+                        Err(ApiError::ValidationFail(tmp)),
+                    );
+                }
+                product.price = Set(price);
+            }
+
+            if let Some(currency) = payload.currency {
+                product.currency = Set(currency);
+            }
+
+            if let Some(description) = payload.description {
+                product.description = Set(description);
+            }
+
+            if let Some(image_id) = payload.image_id {
+                match image::Entity::find_by_id(image_id).one(&txn).await {
+                    Ok(_) => product.image_id = Set(image_id),
+                    Err(err) => {
+                        return to_response(
+                            (
+                                StatusCode::BAD_REQUEST,
+                                Json(json!({
+                                    "error": format!("No image with {image_id} id was found")
+                                })),
+                            ),
+                            Err(ApiError::DbError(err.to_string())),
+                        );
+                    }
+                }
+            }
+
+            if let Some(category_id) = payload.category_id {
+                match category::Entity::find_by_id(category_id).one(&txn).await {
+                    Ok(_) => product.category_id = Set(category_id),
+                    Err(err) => {
+                        return to_response(
+                            (
+                                StatusCode::BAD_REQUEST,
+                                Json(json!({
+                                    "error": format!("No category with {category_id} id was found")
+                                })),
+                            ),
+                            Err(ApiError::DbError(err.to_string())),
+                        );
+                    }
+                }
+            }
+
+            if let Some(is_featured) = payload.is_featured {
+                product.is_featured = Set(is_featured);
+            }
+
+            if let Some(is_available) = payload.is_available {
+                product.is_available = Set(is_available);
+            }
+
+            let result = product.update(&txn).await;
+            match result {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource patched successfully."
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({"error": "Internal server error"})),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    //DB Failed / unique constraint
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to patch this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!("No image with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn delete_product(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductEntity::find_by_id(id).one(&txn).await;
+    match result {
+        Ok(Some(product)) => {
+            //Stock history is an audit trail, it isn't deleted with the product
+            let has_history = StockMovementEntity::find()
+                .filter(stock_movement::Column::ProductId.eq(id))
+                .one(&txn)
+                .await
+                .map(|movement| movement.is_some());
+            let result = match has_history {
+                Ok(true) => {
+                    let _ = txn.rollback().await;
+                    let tmp = format!("Product {} has stock history, hide it instead", id);
+                    return to_response(
+                        (
+                            StatusCode::CONFLICT,
+                            Json(json!({
+                                "error": tmp
+                            })),
+                        ),
+                        Err(ApiError::ValidationFail(tmp)),
+                    );
+                }
+                Ok(false) => {
+                    let product: product::ActiveModel = product.into();
+                    product.delete(&txn).await
+                }
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource deleted successfully."
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({"error": "Internal server error"})),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    //DB Failed / unique constraint
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to delete this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!("No image with {} id was found.", id);
+            to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn attach_product_image(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<AttachImagePayload>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Alt text should be at most 255 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let found = match ProductEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(_)) => image::Entity::find_by_id(payload.image_id)
+            .one(&txn)
+            .await
+            .map(|image| {
+                image.map_or_else(
+                    || Err(format!("Image with id {} not found", payload.image_id)),
+                    |_| Ok(()),
+                )
+            }),
+        Ok(None) => Ok(Err(format!("No product with {} id was found.", id))),
+        Err(err) => Err(err),
+    };
+
+    match found {
+        Ok(Ok(())) => {}
+        Ok(Err(tmp)) => {
+            return to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let gallery = match ProductImageEntity::find()
+        .filter(product_image::Column::ProductId.eq(id))
+        .all(&txn)
+        .await
+    {
+        Ok(gallery) => gallery,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if gallery
+        .iter()
+        .any(|entry| entry.image_id == payload.image_id)
+    {
+        let tmp = format!(
+            "Image {} is already in the gallery of product {}",
+            payload.image_id, id
+        );
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    //Image is appended by default, otherwise images after it are shifted
+    let position = payload.position.map_or(gallery.len(), |position| {
+        (position as usize).min(gallery.len())
+    }) as i32;
+
+    let new_entry = product_image::ActiveModel {
+        product_id: Set(id),
+        image_id: Set(payload.image_id),
+        position: Set(position),
+        alt_text: Set(payload.alt_text),
+        ..Default::default()
+    };
+
+    let result = match ProductImageEntity::update_many()
+        .col_expr(
+            product_image::Column::Position,
+            Expr::col(product_image::Column::Position).add(1),
+        )
+        .filter(product_image::Column::ProductId.eq(id))
+        .filter(product_image::Column::Position.gte(position))
+        .exec(&txn)
+        .await
+    {
+        Ok(_) => ProductImageEntity::insert(new_entry)
+            .exec(&txn)
+            .await
+            .map(|_| ()),
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::CREATED,
+                    Json(json!({
+                        "message": "Image attached successfully",
+                        "position": position
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn patch_product_image(
+    Path((id, image_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<PatchProductImagePayload>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Alt text should be at most 255 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductImageEntity::find()
+        .filter(product_image::Column::ProductId.eq(id))
+        .filter(product_image::Column::ImageId.eq(image_id))
+        .one(&txn)
+        .await;
+
+    match result {
+        Ok(Some(entry)) => {
+            let mut entry: product_image::ActiveModel = entry.into();
+            entry.alt_text = Set(payload.alt_text);
+
+            match entry.update(&txn).await {
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => to_response(
+                        (
+                            StatusCode::OK,
+                            Json(json!({
+                                "message": "Resource patched successfully."
+                            })),
+                        ),
+                        Ok(()),
+                    ),
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({"error": "Internal server error"})),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    to_response(
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(json!({
+                                "error": "Failed to patch this resource"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    )
+                }
+            }
+        }
+        Ok(None) => {
+            let tmp = format!(
+                "Image {} is not in the gallery of product {}.",
+                image_id, id
+            );
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn detach_product_image(
+    Path((id, image_id)): Path<(i32, i32)>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = ProductImageEntity::find()
+        .filter(product_image::Column::ProductId.eq(id))
+        .filter(product_image::Column::ImageId.eq(image_id))
+        .one(&txn)
+        .await;
+
+    match result {
+        Ok(Some(entry)) => match remove_from_gallery(&txn, entry).await {
+            Ok(_) => match txn.commit().await {
+                Ok(_) => to_response(
+                    (
+                        StatusCode::OK,
+                        Json(json!({
+                            "message": "Image detached successfully."
+                        })),
+                    ),
+                    Ok(()),
+                ),
+                Err(err) => to_response(
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({"error": "Internal server error"})),
+                    ),
+                    Err(ApiError::DbError(err.to_string())),
+                ),
+            },
+            Err(err) => {
+                let _ = txn.rollback().await;
+                to_response(
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(json!({
+                            "error": "Failed to delete this resource"
+                        })),
+                    ),
+                    Err(ApiError::DbError(err.to_string())),
+                )
+            }
+        },
+        Ok(None) => {
+            let tmp = format!(
+                "Image {} is not in the gallery of product {}.",
+                image_id, id
+            );
+            to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        }
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error."
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn reorder_product_images(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Json(payload): Json<ReorderImagesPayload>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let gallery = match ProductImageEntity::find()
+        .filter(product_image::Column::ProductId.eq(id))
+        .all(&txn)
+        .await
+    {
+        Ok(gallery) => gallery,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //New order should be a permutation of the current gallery
+    let mut current: Vec<i32> = gallery.iter().map(|entry| entry.image_id).collect();
+    let mut requested = payload.image_ids.clone();
+    current.sort_unstable();
+    requested.sort_unstable();
+    if current != requested {
+        let tmp =
+            "Image ids should list every image of the product gallery exactly once".to_owned();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    let mut result = Ok(());
+    for (position, image_id) in payload.image_ids.iter().enumerate() {
+        result = ProductImageEntity::update_many()
+            .col_expr(
+                product_image::Column::Position,
+                Expr::value(position as i32),
+            )
+            .filter(product_image::Column::ProductId.eq(id))
+            .filter(product_image::Column::ImageId.eq(*image_id))
+            .exec(&txn)
+            .await
+            .map(|_| ());
+        if result.is_err() {
+            break;
+        }
+    }
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Gallery reordered successfully."
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": "Internal server error"})),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn restock_product(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<RestockPayload>,
+) -> Response {
+    if payload.quantity == 0 || payload.quantity > MAX_STOCK_QUANTITY {
+        let tmp = format!("Quantity should be between 1 and {}", MAX_STOCK_QUANTITY);
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let item = StockItem {
+        product_id: id,
+        variant_id: payload.variant_id,
+    };
+    if let Err(response) = check_stock_item(&txn, &item).await {
+        return response;
+    }
+
+    //Restocking a sold out item puts it back on sale
+    let reopened = match item.variant_id {
+        Some(variant_id) => ProductVariantEntity::update_many()
+            .col_expr(product_variant::Column::IsAvailable, Expr::value(true))
+            .filter(product_variant::Column::Id.eq(variant_id))
+            .filter(product_variant::Column::Stock.lte(0))
+            .exec(&txn)
+            .await
+            .map(|_| ()),
+        None => ProductEntity::update_many()
+            .col_expr(product::Column::IsAvailable, Expr::value(true))
+            .filter(product::Column::Id.eq(id))
+            .filter(product::Column::Stock.lte(0))
+            .exec(&txn)
+            .await
+            .map(|_| ()),
+    };
+
+    let result = match reopened {
+        Ok(_) => {
+            put_stock(
+                &txn,
+                item,
+                payload.quantity as i32,
+                Reason::Restock,
+                None,
+                claims.user_id,
+                payload.note,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(true) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Product restocked successfully."
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": "Internal server error"})),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Ok(false) => {
+            let _ = txn.rollback().await;
+            let tmp = format!("Stock can't go above {}", MAX_STOCK_QUANTITY);
+            to_response(
+                (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::ValidationFail(tmp)),
+            )
+        }
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn adjust_stock(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<AdjustStockPayload>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Adjustment should have a non zero change and a note of at least 3 characters"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
                     })),
                 ),
                 Err(ApiError::TransactionCreationFailed),
@@ -569,58 +1829,272 @@ async fn delete_product(
         }
     };
 
-    let result = ProductEntity::find_by_id(id).one(&txn).await;
+    let item = StockItem {
+        product_id: id,
+        variant_id: payload.variant_id,
+    };
+    if let Err(response) = check_stock_item(&txn, &item).await {
+        return response;
+    }
+
+    let note = Some(payload.note);
+    let result = if payload.change > 0 {
+        put_stock(
+            &txn,
+            item,
+            payload.change,
+            Reason::Adjustment,
+            None,
+            claims.user_id,
+            note,
+        )
+        .await
+    } else {
+        take_stock(
+            &txn,
+            item,
+            -payload.change,
+            Reason::Adjustment,
+            None,
+            claims.user_id,
+            note,
+        )
+        .await
+    };
+
     match result {
-        Ok(Some(product)) => {
-            let product: product::ActiveModel = product.into();
-            let result = product.delete(&txn).await;
-            match result {
-                Ok(_) => match txn.commit().await {
-                    Ok(_) => to_response(
-                        (
-                            StatusCode::OK,
-                            Json(json!({
-                                "message": "Resource deleted successfully."
-                            })),
-                        ),
-                        Ok(()),
-                    ),
-                    Err(err) => to_response(
-                        (
-                            StatusCode::INTERNAL_SERVER_ERROR,
-                            Json(json!({"error": "Internal server error"})),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    ),
-                },
-                Err(err) => {
-                    //DB Failed / unique constraint
-                    let _ = txn.rollback().await;
-                    to_response(
-                        (
-                            StatusCode::BAD_REQUEST,
-                            Json(json!({
-                                "error": "Failed to delete this resource"
-                            })),
-                        ),
-                        Err(ApiError::DbError(err.to_string())),
-                    )
-                }
-            }
-        }
-        Ok(None) => {
-            let tmp = format!("No image with {} id was found.", id);
+        Ok(true) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Stock adjusted successfully."
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": "Internal server error"})),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Ok(false) => {
+            let _ = txn.rollback().await;
+            let tmp = if payload.change > 0 {
+                format!("Stock can't go above {}", MAX_STOCK_QUANTITY)
+            } else {
+                "Stock can't go below zero".to_owned()
+            };
             to_response(
                 (
-                    StatusCode::BAD_REQUEST,
+                    StatusCode::CONFLICT,
                     Json(json!({
                         "error": tmp
                     })),
                 ),
-                Err(ApiError::General(tmp)),
+                Err(ApiError::ValidationFail(tmp)),
+            )
+        }
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error."
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
             )
         }
+    }
+}
+
+async fn get_stock_movements(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let result = StockMovementEntity::find()
+        .filter(stock_movement::Column::ProductId.eq(id))
+        .order_by(stock_movement::Column::CreatedAt, sea_orm::Order::Desc)
+        .all(&txn)
+        .await;
+
+    match result {
+        Ok(items) => to_response(Json(items), Ok(())),
         Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": "Internal server error"})),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//utils
+//Atomically takes `quantity` from product (or variant) stock and logs the movement.
+//Returns false if there is not enough stock, so concurrent checkouts can't oversell.
+pub async fn take_stock<C: ConnectionTrait>(
+    db: &C,
+    item: StockItem,
+    quantity: i32,
+    reason: Reason,
+    order_id: Option<i32>,
+    changed_by: i32,
+    note: Option<String>,
+) -> Result<bool, DbErr> {
+    //Sold out products and variants are hidden automatically
+    let taken = match item.variant_id {
+        Some(variant_id) => {
+            let result = ProductVariantEntity::update_many()
+                .col_expr(
+                    product_variant::Column::Stock,
+                    Expr::col(product_variant::Column::Stock).sub(quantity),
+                )
+                .filter(product_variant::Column::Id.eq(variant_id))
+                .filter(product_variant::Column::ProductId.eq(item.product_id))
+                .filter(product_variant::Column::Stock.gte(quantity))
+                .exec(db)
+                .await?;
+
+            ProductVariantEntity::update_many()
+                .col_expr(product_variant::Column::IsAvailable, Expr::value(false))
+                .filter(product_variant::Column::Id.eq(variant_id))
+                .filter(product_variant::Column::Stock.lte(0))
+                .exec(db)
+                .await?;
+
+            result.rows_affected > 0
+        }
+        None => {
+            let result = ProductEntity::update_many()
+                .col_expr(
+                    product::Column::Stock,
+                    Expr::col(product::Column::Stock).sub(quantity),
+                )
+                .filter(product::Column::Id.eq(item.product_id))
+                .filter(product::Column::Stock.gte(quantity))
+                .exec(db)
+                .await?;
+
+            ProductEntity::update_many()
+                .col_expr(product::Column::IsAvailable, Expr::value(false))
+                .filter(product::Column::Id.eq(item.product_id))
+                .filter(product::Column::Stock.lte(0))
+                .exec(db)
+                .await?;
+
+            result.rows_affected > 0
+        }
+    };
+
+    if !taken {
+        return Ok(false);
+    }
+
+    log_stock_movement(db, item, -quantity, reason, order_id, changed_by, note).await?;
+    Ok(true)
+}
+
+//Returns `quantity` to product (or variant) stock and logs the movement.
+//Returns false if product or variant doesn't exist or stock would go above MAX_STOCK_QUANTITY.
+pub async fn put_stock<C: ConnectionTrait>(
+    db: &C,
+    item: StockItem,
+    quantity: i32,
+    reason: Reason,
+    order_id: Option<i32>,
+    changed_by: i32,
+    note: Option<String>,
+) -> Result<bool, DbErr> {
+    let result = match item.variant_id {
+        Some(variant_id) => {
+            ProductVariantEntity::update_many()
+                .col_expr(
+                    product_variant::Column::Stock,
+                    Expr::col(product_variant::Column::Stock).add(quantity),
+                )
+                .filter(product_variant::Column::Id.eq(variant_id))
+                .filter(product_variant::Column::ProductId.eq(item.product_id))
+                .filter(product_variant::Column::Stock.lte(MAX_STOCK_QUANTITY as i32 - quantity))
+                .exec(db)
+                .await?
+        }
+        None => {
+            ProductEntity::update_many()
+                .col_expr(
+                    product::Column::Stock,
+                    Expr::col(product::Column::Stock).add(quantity),
+                )
+                .filter(product::Column::Id.eq(item.product_id))
+                .filter(product::Column::Stock.lte(MAX_STOCK_QUANTITY as i32 - quantity))
+                .exec(db)
+                .await?
+        }
+    };
+
+    if result.rows_affected == 0 {
+        return Ok(false);
+    }
+
+    log_stock_movement(db, item, quantity, reason, order_id, changed_by, note).await?;
+    Ok(true)
+}
+
+//Checks that product (or variant of it) exists, so stock updates can tell a missing item from a stock limit
+async fn check_stock_item<C: ConnectionTrait>(db: &C, item: &StockItem) -> Result<(), Response> {
+    let exists = match item.variant_id {
+        Some(variant_id) => ProductVariantEntity::find_by_id(variant_id)
+            .filter(product_variant::Column::ProductId.eq(item.product_id))
+            .one(db)
+            .await
+            .map(|variant| variant.is_some()),
+        None => ProductEntity::find_by_id(item.product_id)
+            .one(db)
+            .await
+            .map(|product| product.is_some()),
+    };
+
+    match exists {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let tmp = match item.variant_id {
+                Some(variant_id) => format!(
+                    "No variant with {} id was found for product {}.",
+                    variant_id, item.product_id
+                ),
+                None => format!("No product with {} id was found.", item.product_id),
+            };
+            Err(to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ))
+        }
+        Err(err) => Err(to_response(
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({
@@ -628,21 +2102,73 @@ async fn delete_product(
                 })),
             ),
             Err(ApiError::DbError(err.to_string())),
-        ),
+        )),
     }
 }
 
+//Removes image from the gallery and closes the gap in positions
+pub async fn remove_from_gallery<C: ConnectionTrait>(
+    db: &C,
+    entry: product_image::Model,
+) -> Result<(), DbErr> {
+    let (product_id, position) = (entry.product_id, entry.position);
+    let entry: product_image::ActiveModel = entry.into();
+    entry.delete(db).await?;
+
+    ProductImageEntity::update_many()
+        .col_expr(
+            product_image::Column::Position,
+            Expr::col(product_image::Column::Position).sub(1),
+        )
+        .filter(product_image::Column::ProductId.eq(product_id))
+        .filter(product_image::Column::Position.gt(position))
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+async fn log_stock_movement<C: ConnectionTrait>(
+    db: &C,
+    item: StockItem,
+    change: i32,
+    reason: Reason,
+    order_id: Option<i32>,
+    changed_by: i32,
+    note: Option<String>,
+) -> Result<(), DbErr> {
+    let movement = stock_movement::ActiveModel {
+        product_id: Set(item.product_id),
+        variant_id: Set(item.variant_id),
+        change: Set(change),
+        reason: Set(reason),
+        order_id: Set(order_id),
+        changed_by: Set(changed_by),
+        created_at: Set(Utc::now()),
+        note: Set(note),
+        ..Default::default()
+    };
+
+    StockMovementEntity::insert(movement)
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
 //Structs
 #[derive(Deserialize, Clone, Debug, Validate)]
 struct CreateProduct {
     #[validate(length(min = 3))]
     name: String,
-    price: f32,
+    #[validate(range(min = 0))]
+    price: MinorUnits,
+    currency: Option<Currency>,
     description: String,
     image_id: i32,
     category_id: i32,
     is_featured: Option<bool>,
     is_available: Option<bool>,
+    #[validate(range(max = MAX_STOCK_QUANTITY))]
+    stock: Option<u32>,
 }
 
 #[derive(Deserialize)]
@@ -653,8 +2179,8 @@ struct GetProductsQuery {
     sort_by: Option<String>, //Enum better?? "price", "is_available", "name"
     order: Option<String>,   //Enum better??
     //filter zone
-    price_top: Option<i32>,
-    price_bottom: Option<i32>,
+    price_top: Option<MinorUnits>,
+    price_bottom: Option<MinorUnits>,
     category_ids: Option<Vec<i32>>,
     only_available: Option<bool>,
     //pagination zone
@@ -670,8 +2196,8 @@ struct AdminProductsQuery {
     sort_by: Option<String>, //Enum better?? "id,", "price", "is_available", "is_featured", "name", "image_id", "category_id"
     order: Option<String>,   //Enum better??
     //filter zone
-    price_top: Option<i32>,
-    price_bottom: Option<i32>,
+    price_top: Option<MinorUnits>,
+    price_bottom: Option<MinorUnits>,
     category_ids: Option<Vec<i32>>,
     only_available: Option<bool>,
     only_featured: Option<bool>,
@@ -684,7 +2210,8 @@ struct AdminProductsQuery {
 struct PatchProductPayload {
     #[validate(length(min = 3))]
     name: Option<String>,
-    price: Option<f32>,
+    price: Option<MinorUnits>,
+    currency: Option<Currency>,
     description: Option<String>,
     image_id: Option<i32>,
     category_id: Option<i32>,
@@ -696,8 +2223,113 @@ struct PatchProductPayload {
 struct ProductResponse {
     id: i32,
     name: String,
-    price: f32,
+    price: MinorUnits,
+    currency: Currency,
     description: String,
     image_id: i32,
     category_name: String,
 }
+
+#[derive(Serialize)]
+struct ProductDetailsResponse {
+    #[serde(flatten)]
+    product: ProductResponse,
+    images: Vec<GalleryImageResponse>,
+    variants: Vec<VariantResponse>,
+}
+
+#[derive(Serialize, FromQueryResult)]
+struct GalleryImageResponse {
+    image_id: i32,
+    position: i32,
+    alt_text: Option<String>,
+}
+
+#[derive(Serialize, FromQueryResult)]
+struct VariantResponse {
+    id: i32,
+    name: String,
+    size: Option<String>,
+    flavour: Option<String>,
+    pack_count: Option<i32>,
+    price: MinorUnits,
+}
+
+#[derive(Deserialize, Validate)]
+struct CreateVariant {
+    #[validate(length(min = 3))]
+    name: String,
+    size: Option<String>,
+    flavour: Option<String>,
+    #[validate(range(max = MAX_STOCK_QUANTITY))]
+    pack_count: Option<u32>,
+    #[validate(range(min = 0))]
+    price: MinorUnits,
+    is_available: Option<bool>,
+    #[validate(range(max = MAX_STOCK_QUANTITY))]
+    stock: Option<u32>,
+}
+
+#[derive(Deserialize, Validate)]
+struct PatchVariantPayload {
+    #[validate(length(min = 3))]
+    name: Option<String>,
+    size: Option<String>,
+    flavour: Option<String>,
+    #[validate(range(max = MAX_STOCK_QUANTITY))]
+    pack_count: Option<u32>,
+    #[validate(range(min = 0))]
+    price: Option<MinorUnits>,
+    is_available: Option<bool>,
+}
+
+#[derive(Deserialize, Validate)]
+struct AttachImagePayload {
+    image_id: i32,
+    #[validate(length(max = 255))]
+    alt_text: Option<String>,
+    position: Option<u32>,
+}
+
+#[derive(Deserialize, Validate)]
+struct PatchProductImagePayload {
+    #[validate(length(max = 255))]
+    alt_text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReorderImagesPayload {
+    image_ids: Vec<i32>,
+}
+
+//Stock of the product itself, or of one of its variants
+#[derive(Clone, Copy, Debug)]
+pub struct StockItem {
+    pub product_id: i32,
+    pub variant_id: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct RestockPayload {
+    quantity: u32,
+    variant_id: Option<i32>,
+    note: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+struct AdjustStockPayload {
+    #[validate(custom(function = "validate_change"))]
+    change: i32,
+    variant_id: Option<i32>,
+    #[validate(length(min = 3))]
+    note: String,
+}
+
+//i32::MIN has no positive counterpart to take from the stock
+fn validate_change(change: i32) -> Result<(), validator::ValidationError> {
+    match change {
+        0 => Err(validator::ValidationError::new("zero_change")),
+        i32::MIN => Err(validator::ValidationError::new("change_out_of_range")),
+        _ => Ok(()),
+    }
+}
@@ -1,24 +1,45 @@
 use axum::{
-    extract::Extension, http::StatusCode, middleware, response::Response, routing::get, Json,
-    Router,
+    extract::{ConnectInfo, Extension},
+    http::StatusCode,
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Json, Router,
 };
 use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set, TransactionTrait};
 use serde::Deserialize;
 use serde_json::json;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use validator::Validate;
 
-use crate::entities::user::{ActiveModel, Entity as UserEntity, Role};
+use crate::entities::{
+    permission,
+    user::{ActiveModel, Entity as UserEntity},
+};
 use crate::middleware::{
     auth::{auth_middleware, Claims},
     logging::{to_response, ApiError},
 };
-use crate::routes::auth_routes::USERNAME_REGEX;
+use crate::routes::auth_routes::{
+    check_current_password, hash_password, revoke_user_sessions, PASSWORD_REGEX, USERNAME_REGEX,
+};
 
 pub fn profile_routes() -> Router {
+    //Stays open when admin forces a password reset
+    let password_routes = Router::new()
+        .route("/profile/password", post(change_password))
+        .layer(middleware::from_fn_with_state(
+            permission::PASSWORD_CHANGE,
+            auth_middleware,
+        ));
+
     Router::new()
         .route("/profile", get(get_profile).patch(patch_profile))
-        .layer(middleware::from_fn_with_state(Role::User, auth_middleware))
+        .layer(middleware::from_fn_with_state(
+            permission::PROFILE_MANAGE,
+            auth_middleware,
+        ))
+        .merge(password_routes)
 }
 
 async fn get_profile(
@@ -42,13 +63,14 @@ async fn get_profile(
         }
     };
 
-    //yes, just a username.
     match UserEntity::find_by_id(user_id).one(&txn).await {
         Ok(Some(model)) => to_response(
             (
                 StatusCode::OK,
                 Json(json!({
-                    "username": format!("{}", model.username)
+                    "username": format!("{}", model.username),
+                    "email": model.email,
+                    "two_factor_enabled": model.totp_enabled
                 })),
             ),
             Ok(()),
@@ -86,7 +108,7 @@ async fn patch_profile(
             (
                 StatusCode::BAD_REQUEST,
                 Json(json!({
-                    "error": "Failed to validate username"
+                    "error": "Failed to validate username or email"
                 })),
             ),
             Err(ApiError::ValidationFail(err.to_string())),
@@ -108,12 +130,14 @@ async fn patch_profile(
         }
     };
 
-    //yes, just a username.
     //yes, nested, i hate it.
     match UserEntity::find_by_id(user_id).one(&txn).await {
         Ok(Some(model)) => {
             let mut model: ActiveModel = model.into();
             model.username = Set(payload.username);
+            if let Some(email) = payload.email {
+                model.email = Set(Some(email));
+            }
             let result = model.update(&txn).await.map(|_| ());
             match result {
                 Ok(_) => match txn.commit().await {
@@ -171,8 +195,164 @@ async fn patch_profile(
     }
 }
 
+//Every session of the user is revoked, so it has to log in again
+async fn change_password(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<ChangePassword>,
+) -> Response {
+    let user_id = claims.user_id;
+
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Failed to validate new password"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    if payload.current_password == payload.new_password {
+        let tmp = "New password should differ from the current one".to_string();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let model = match UserEntity::find_by_id(user_id).one(&txn).await {
+        Ok(Some(model)) => model,
+        Ok(None) => {
+            return to_response(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({
+                        "error": "Unauthorized access"
+                    })),
+                ),
+                Err(ApiError::General("User profile not found".to_string())),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let txn = match check_current_password(
+        txn,
+        &model,
+        &payload.current_password,
+        addr.ip().to_string(),
+    )
+    .await
+    {
+        Ok(txn) => txn,
+        Err(response) => return response,
+    };
+
+    let password = match hash_password(&payload.new_password) {
+        Ok(password) => password,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "An internal server error occured"
+                    })),
+                ),
+                Err(ApiError::PasswordHashFailed(err.to_string())),
+            );
+        }
+    };
+
+    let mut model: ActiveModel = model.into();
+    model.password = Set(password);
+    model.must_change_password = Set(false);
+
+    let result = match model.update(&txn).await {
+        Ok(_) => revoke_user_sessions(&txn, user_id).await,
+        Err(err) => Err(err),
+    };
+
+    match result {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Password changed, please log in again"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
 #[derive(Deserialize, Validate)]
 struct PatchProfile {
     #[validate(regex(path = *USERNAME_REGEX))]
     username: String,
+    #[validate(email)]
+    email: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+struct ChangePassword {
+    current_password: String,
+    #[validate(regex(path = *PASSWORD_REGEX))]
+    new_password: String,
 }
@@ -0,0 +1,625 @@
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    middleware,
+    response::Response,
+    routing::{delete, get, put},
+    Json, Router,
+};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    JoinType, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, RelationTrait, Set,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{collections::HashSet, sync::Arc};
+use validator::Validate;
+
+use crate::entities::{
+    permission::{self, Entity as PermissionEntity},
+    role::{self, Entity as RoleEntity},
+    role_permission, user,
+};
+use crate::middleware::{
+    auth::{auth_middleware, load_permissions, Permissions},
+    logging::{to_response, ApiError},
+};
+
+//ROUTERS
+pub fn admin_role_routes() -> Router {
+    Router::new()
+        .route("/role", get(get_roles).post(create_role))
+        .route("/role/:id", delete(delete_role))
+        .route("/role/:id/permission", put(put_role_permissions))
+        .route("/permission", get(get_permissions))
+        .layer(middleware::from_fn_with_state(
+            permission::ROLE_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+async fn get_roles(Extension(db): Extension<Arc<DatabaseConnection>>) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let roles = match RoleEntity::find()
+        .order_by_asc(role::Column::Id)
+        .all(&txn)
+        .await
+    {
+        Ok(roles) => roles,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    //(role_id, permission name) for every grant
+    let grants: Vec<(i32, String)> = match role_permission::Entity::find()
+        .join(
+            JoinType::InnerJoin,
+            role_permission::Relation::Permission.def(),
+        )
+        .order_by_asc(permission::Column::Name)
+        .select_only()
+        .column(role_permission::Column::RoleId)
+        .column(permission::Column::Name)
+        .into_tuple()
+        .all(&txn)
+        .await
+    {
+        Ok(grants) => grants,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let response: Vec<RoleResponse> = roles
+        .into_iter()
+        .map(|role| RoleResponse {
+            permissions: grants
+                .iter()
+                .filter(|(role_id, _)| *role_id == role.id)
+                .map(|(_, name)| name.clone())
+                .collect(),
+            id: role.id,
+            name: role.name,
+            description: role.description,
+        })
+        .collect();
+
+    to_response(Json(response), Ok(()))
+}
+
+async fn create_role(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(caller_permissions): Extension<Permissions>,
+    Json(payload): Json<CreateRole>,
+) -> Response {
+    if let Some(err) = payload.validate().err() {
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "Role name should be 3 to 25 characters long"
+                })),
+            ),
+            Err(ApiError::ValidationFail(err.to_string())),
+        );
+    }
+
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let permissions = match find_permissions(&txn, &payload.permissions).await {
+        Ok(Ok(permissions)) => permissions,
+        Ok(Err(tmp)) => {
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if let Some(response) = check_grantable(&caller_permissions, &permissions) {
+        return response;
+    }
+
+    let new_role = role::ActiveModel {
+        name: Set(payload.name),
+        description: Set(payload.description),
+        ..Default::default()
+    };
+
+    let role = match new_role.insert(&txn).await {
+        Ok(role) => role,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            return to_response(
+                (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": "Role already exists"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if let Err(err) = set_permissions(&txn, role.id, &permissions).await {
+        let _ = txn.rollback().await;
+        return to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        );
+    }
+
+    match txn.commit().await {
+        Ok(_) => to_response(
+            (
+                StatusCode::CREATED,
+                Json(json!({
+                    "message": "Role created successfully",
+                    "role_id": role.id
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+async fn delete_role(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let role = match find_editable_role(&txn, id).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    let holders = match user::Entity::find()
+        .filter(user::Column::Role.eq(&role.name))
+        .count(&txn)
+        .await
+    {
+        Ok(holders) => holders,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if holders > 0 {
+        let tmp = format!("Role {} is assigned to {} users", role.name, holders);
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    //Grants are removed by cascade
+    match RoleEntity::delete_by_id(role.id).exec(&txn).await {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Role deleted successfully"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+//Replaces all permissions of the role, takes effect on the next request of its users
+async fn put_role_permissions(
+    Path(id): Path<i32>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(caller_permissions): Extension<Permissions>,
+    Json(payload): Json<RolePermissionsPayload>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let role = match find_editable_role(&txn, id).await {
+        Ok(role) => role,
+        Err(response) => return response,
+    };
+
+    //Role with more permissions than the caller has is out of their reach
+    match load_permissions(&txn, &role.name).await {
+        Ok(role_permissions) if caller_permissions.covers(&role_permissions) => {}
+        Ok(_) => {
+            let tmp = format!("Role {} can't be changed", role.name);
+            return to_response(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let permissions = match find_permissions(&txn, &payload.permissions).await {
+        Ok(Ok(permissions)) => permissions,
+        Ok(Err(tmp)) => {
+            return to_response(
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            );
+        }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    if let Some(response) = check_grantable(&caller_permissions, &permissions) {
+        return response;
+    }
+
+    match set_permissions(&txn, role.id, &permissions).await {
+        Ok(_) => match txn.commit().await {
+            Ok(_) => to_response(
+                (
+                    StatusCode::OK,
+                    Json(json!({
+                        "message": "Role permissions updated successfully"
+                    })),
+                ),
+                Ok(()),
+            ),
+            Err(err) => to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            ),
+        },
+        Err(err) => {
+            let _ = txn.rollback().await;
+            to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            )
+        }
+    }
+}
+
+async fn get_permissions(Extension(db): Extension<Arc<DatabaseConnection>>) -> Response {
+    match PermissionEntity::find()
+        .order_by_asc(permission::Column::Name)
+        .all(&*db)
+        .await
+    {
+        Ok(permissions) => to_response(Json(permissions), Ok(())),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//utils
+pub async fn find_role<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+) -> Result<Option<role::Model>, DbErr> {
+    RoleEntity::find()
+        .filter(role::Column::Name.eq(name))
+        .one(db)
+        .await
+}
+
+//Built-in admin always keeps every permission, user role can be changed but not removed
+async fn find_editable_role<C: ConnectionTrait>(db: &C, id: i32) -> Result<role::Model, Response> {
+    match RoleEntity::find_by_id(id).one(db).await {
+        Ok(Some(role)) if role.name == role::ADMIN => {
+            let tmp = "Built-in admin role can't be changed".to_string();
+            Err(to_response(
+                (
+                    StatusCode::CONFLICT,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ))
+        }
+        Ok(Some(role)) => Ok(role),
+        Ok(None) => {
+            let tmp = format!("No role with {} id was found", id);
+            Err(to_response(
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            ))
+        }
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+//Inner error names the first unknown permission
+async fn find_permissions<C: ConnectionTrait>(
+    db: &C,
+    names: &[String],
+) -> Result<Result<Vec<permission::Model>, String>, DbErr> {
+    let names: HashSet<&String> = names.iter().collect();
+    if names.is_empty() {
+        return Ok(Ok(Vec::new()));
+    }
+
+    let permissions = PermissionEntity::find()
+        .filter(permission::Column::Name.is_in(names.iter().map(|name| name.as_str())))
+        .all(db)
+        .await?;
+
+    if let Some(unknown) = names
+        .iter()
+        .find(|name| !permissions.iter().any(|p| &&p.name == *name))
+    {
+        return Ok(Err(format!("Permission {} doesn't exist", unknown)));
+    }
+
+    Ok(Ok(permissions))
+}
+
+//Only permissions the caller has can be granted, otherwise `role:manage` would give out everything.
+//Returns the 403 response for the first permission the caller lacks.
+fn check_grantable(
+    caller_permissions: &Permissions,
+    permissions: &[permission::Model],
+) -> Option<Response> {
+    let requested = Permissions(permissions.iter().map(|p| p.name.clone()).collect());
+    if caller_permissions.covers(&requested) {
+        return None;
+    }
+
+    requested
+        .0
+        .iter()
+        .find(|permission| !caller_permissions.has(permission))
+        .map(|permission| {
+            let tmp = format!("Permission {} can't be granted", permission);
+            to_response(
+                (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({
+                        "error": tmp
+                    })),
+                ),
+                Err(ApiError::General(tmp)),
+            )
+        })
+}
+
+async fn set_permissions<C: ConnectionTrait>(
+    db: &C,
+    role_id: i32,
+    permissions: &[permission::Model],
+) -> Result<(), DbErr> {
+    role_permission::Entity::delete_many()
+        .filter(role_permission::Column::RoleId.eq(role_id))
+        .exec(db)
+        .await?;
+
+    for permission in permissions {
+        role_permission::ActiveModel {
+            role_id: Set(role_id),
+            permission_id: Set(permission.id),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+//Structs
+#[derive(Deserialize, Validate)]
+struct CreateRole {
+    #[validate(length(min = 3, max = 25))]
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    permissions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RolePermissionsPayload {
+    permissions: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RoleResponse {
+    id: i32,
+    name: String,
+    description: Option<String>,
+    permissions: Vec<String>,
+}
@@ -0,0 +1,533 @@
+use axum::{
+    extract::{ConnectInfo, Extension},
+    http::StatusCode,
+    middleware,
+    response::Response,
+    routing::post,
+    Json, Router,
+};
+use chrono::Utc;
+use rand::RngCore;
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DbErr, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::entities::{
+    permission, recovery_code,
+    user::{self, ActiveModel, Entity as UserEntity},
+};
+use crate::middleware::{
+    auth::{auth_middleware, hash_token, Claims},
+    logging::{to_response, ApiError},
+};
+use crate::routes::auth_routes::check_current_password;
+use dotenvy::dotenv;
+
+//Standard authenticator app settings, codes are checked one step back and forward
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECONDS: u64 = 30;
+const TOTP_SKEW_STEPS: i64 = 1;
+const TOTP_SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub fn two_factor_routes() -> Router {
+    Router::new()
+        .route("/profile/2fa/enroll", post(enroll))
+        .route("/profile/2fa/confirm", post(confirm))
+        .route("/profile/2fa/disable", post(disable))
+        .layer(middleware::from_fn_with_state(
+            permission::PROFILE_MANAGE,
+            auth_middleware,
+        ))
+}
+
+//ROUTES
+//Stores a new secret, 2FA is enabled only after `confirm` receives a valid code
+async fn enroll(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let model = match find_user(&txn, claims.user_id).await {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+
+    if model.totp_enabled {
+        let tmp = "Two-factor authentication is already enabled".to_string();
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    let mut secret = vec![0u8; TOTP_SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    let totp = match build_totp(secret, &model.username) {
+        Ok(totp) => totp,
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::General(err)),
+            );
+        }
+    };
+
+    let mut model: ActiveModel = model.into();
+    model.totp_secret = Set(Some(totp.get_secret_base32()));
+    model.totp_last_step = Set(None);
+
+    let result = match model.update(&txn).await {
+        Ok(_) => txn.commit().await,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok(_) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "secret": totp.get_secret_base32(),
+                    "otpauth_uri": totp.get_url()
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Enables 2FA and returns recovery codes, they are shown only once
+async fn confirm(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    Json(payload): Json<ConfirmTwoFactor>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let model = match find_user(&txn, claims.user_id).await {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+
+    if model.totp_enabled {
+        let tmp = "Two-factor authentication is already enabled".to_string();
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    if model.totp_secret.is_none() {
+        let tmp = "Two-factor enrollment wasn't started".to_string();
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    match verify_totp(&txn, &model, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return invalid_code(),
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let user_id = model.id;
+    let mut model: ActiveModel = model.into();
+    model.totp_enabled = Set(true);
+
+    let result = match model.update(&txn).await {
+        Ok(_) => replace_recovery_codes(&txn, user_id).await,
+        Err(err) => Err(err),
+    };
+
+    let result = match result {
+        Ok(codes) => txn.commit().await.map(|_| codes),
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok(codes) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "message": "Two-factor authentication enabled",
+                    "recovery_codes": codes
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//Needs the password and a second factor, so a stolen session alone can't turn it off
+async fn disable(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(claims): Extension<Claims>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(payload): Json<DisableTwoFactor>,
+) -> Response {
+    let txn = match db.begin().await {
+        Ok(txn) => txn,
+        Err(_) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::TransactionCreationFailed),
+            );
+        }
+    };
+
+    let model = match find_user(&txn, claims.user_id).await {
+        Ok(model) => model,
+        Err(response) => return response,
+    };
+
+    if !model.totp_enabled {
+        let tmp = "Two-factor authentication is not enabled".to_string();
+        return to_response(
+            (
+                StatusCode::CONFLICT,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::General(tmp)),
+        );
+    }
+
+    let txn =
+        match check_current_password(txn, &model, &payload.password, addr.ip().to_string()).await {
+            Ok(txn) => txn,
+            Err(response) => return response,
+        };
+
+    match verify_second_factor(&txn, &model, &payload.code).await {
+        Ok(true) => {}
+        Ok(false) => return invalid_code(),
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    }
+
+    let user_id = model.id;
+    let mut model: ActiveModel = model.into();
+    model.totp_enabled = Set(false);
+    model.totp_secret = Set(None);
+    model.totp_last_step = Set(None);
+
+    let result = match model.update(&txn).await {
+        Ok(_) => recovery_code::Entity::delete_many()
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await
+            .map(|_| ()),
+        Err(err) => Err(err),
+    };
+
+    let result = match result {
+        Ok(_) => txn.commit().await,
+        Err(err) => {
+            let _ = txn.rollback().await;
+            Err(err)
+        }
+    };
+
+    match result {
+        Ok(_) => to_response(
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "message": "Two-factor authentication disabled"
+                })),
+            ),
+            Ok(()),
+        ),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
+//utils
+//Accepts a TOTP code or an unused recovery code, an accepted code can't be used again
+pub async fn verify_second_factor<C: ConnectionTrait>(
+    db: &C,
+    user: &user::Model,
+    code: &str,
+) -> Result<bool, DbErr> {
+    if verify_totp(db, user, code).await? {
+        return Ok(true);
+    }
+
+    let result = recovery_code::Entity::update_many()
+        .col_expr(recovery_code::Column::UsedAt, Expr::value(Utc::now()))
+        .filter(recovery_code::Column::UserId.eq(user.id))
+        .filter(recovery_code::Column::CodeHash.eq(hash_recovery_code(code)))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+//Steps up to the last accepted one are rejected, the step is stored with a
+//conditional update so two requests can't use the same code
+async fn verify_totp<C: ConnectionTrait>(
+    db: &C,
+    user: &user::Model,
+    code: &str,
+) -> Result<bool, DbErr> {
+    let code = code.trim();
+    let totp = match user
+        .totp_secret
+        .as_ref()
+        .and_then(|secret| Secret::Encoded(secret.clone()).to_bytes().ok())
+        .and_then(|secret| build_totp(secret, &user.username).ok())
+    {
+        Some(totp) => totp,
+        None => return Ok(false),
+    };
+
+    let current = Utc::now().timestamp() / TOTP_STEP_SECONDS as i64;
+    let step = match (current - TOTP_SKEW_STEPS..=current + TOTP_SKEW_STEPS)
+        .find(|step| totp.check(code, *step as u64 * TOTP_STEP_SECONDS))
+    {
+        Some(step) => step,
+        None => return Ok(false),
+    };
+
+    let result = UserEntity::update_many()
+        .col_expr(user::Column::TotpLastStep, Expr::value(step))
+        .filter(user::Column::Id.eq(user.id))
+        .filter(
+            Condition::any()
+                .add(user::Column::TotpLastStep.is_null())
+                .add(user::Column::TotpLastStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+fn build_totp(secret: Vec<u8>, username: &str) -> Result<TOTP, String> {
+    //Skew is handled by `verify_totp` to know which step was used
+    TOTP::new(
+        Algorithm::SHA1,
+        TOTP_DIGITS,
+        0,
+        TOTP_STEP_SECONDS,
+        secret,
+        Some(get_issuer()),
+        username.to_owned(),
+    )
+    .map_err(|err| err.to_string())
+}
+
+//Previous codes of the user are dropped, only hashes are stored
+async fn replace_recovery_codes<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+) -> Result<Vec<String>, DbErr> {
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    let models = codes.iter().map(|code| recovery_code::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(hash_recovery_code(code)),
+        used_at: Set(None),
+        ..Default::default()
+    });
+
+    recovery_code::Entity::insert_many(models).exec(db).await?;
+    Ok(codes)
+}
+
+//Formatted as "xxxx-xxxx-xxxx-xxxx"
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}",
+        &code[0..4],
+        &code[4..8],
+        &code[8..12],
+        &code[12..16]
+    )
+}
+
+//Dashes, spaces and case don't matter when the code is typed in
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+async fn find_user<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<user::Model, Response> {
+    match UserEntity::find_by_id(user_id).one(db).await {
+        Ok(Some(model)) => Ok(model),
+        Ok(None) => Err(to_response(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "Unauthorized access"
+                })),
+            ),
+            Err(ApiError::General("User profile not found".to_string())),
+        )),
+        Err(err) => Err(to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        )),
+    }
+}
+
+fn invalid_code() -> Response {
+    let tmp = "Invalid two-factor code".to_string();
+    to_response(
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "error": tmp
+            })),
+        ),
+        Err(ApiError::General(tmp)),
+    )
+}
+
+fn get_issuer() -> String {
+    dotenv().ok();
+    std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "rust-baranki".to_string())
+}
+
+//Structs
+#[derive(Deserialize)]
+struct ConfirmTwoFactor {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct DisableTwoFactor {
+    password: String,
+    code: String,
+}
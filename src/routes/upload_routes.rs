@@ -8,46 +8,87 @@ use axum::{
     routing::{patch, post},
     Json, Router,
 };
+use bytes::Bytes;
 use dotenvy::dotenv;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, Set,
-    TransactionTrait,
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use tokio::fs as tokio_fs;
 use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::entities::image::FileExtension;
-use crate::entities::{image, image::Entity as ImageEntity, user::Role};
+use crate::entities::{
+    category, image, image::Entity as ImageEntity, image_size, permission, product, product_image,
+};
+use crate::imaging::{self, ImagingError};
 use crate::middleware::{
     auth::auth_middleware,
     logging::{to_response, ApiError},
 };
+use crate::routes::product_routes::remove_from_gallery;
+use crate::storage::{Storage, StorageError};
 
 //Routers
 pub fn public_image_router() -> Router {
     Router::new().route("/image/:id", get(print_image))
 }
 
+pub fn admin_image_routes() -> Router {
+    Router::new()
+        .route("/image/duplicates", get(get_duplicates))
+        .layer(middleware::from_fn_with_state(
+            permission::IMAGE_WRITE,
+            auth_middleware,
+        ))
+}
+
 pub fn upload_routes() -> Router {
+    //Bad image settings fail at boot, not at the first upload
+    Lazy::force(&ALLOWED_EXTENSIONS);
+    Lazy::force(&MAX_IMAGE_PIXELS);
     Router::new()
         .route("/image", post(upload).get(get_images))
         .route("/image/:id", patch(patch_image).delete(delete_image))
-        .layer(middleware::from_fn_with_state(Role::Admin, auth_middleware))
+        .layer(middleware::from_fn_with_state(
+            permission::IMAGE_WRITE,
+            auth_middleware,
+        ))
 }
 
 //Routes
+//`size` picks a resized copy by name, `w` the smallest one at least that wide.
+//The original is served when there's no such copy.
 pub async fn print_image(
     Path(id): Path<i32>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
+    Query(query): Query<PrintImageQuery>,
 ) -> Response {
+    if let Some(size) = query
+        .size
+        .as_deref()
+        .filter(|size| !imaging::is_known_size(size))
+    {
+        let tmp = format!("Unknown image size {}", size);
+        return to_response(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": tmp
+                })),
+            ),
+            Err(ApiError::ValidationFail(tmp)),
+        );
+    }
+
     let txn = match db.begin().await {
         Ok(txn) => txn,
         Err(_) => {
@@ -63,10 +104,8 @@ pub async fn print_image(
         }
     };
 
-    let path = match ImageEntity::find_by_id(id).one(&txn).await {
-        Ok(Some(model)) => {
-            "./uploads/".to_owned() + &model.path_name + "." + &model.extension.to_string()
-        }
+    let model = match ImageEntity::find_by_id(id).one(&txn).await {
+        Ok(Some(model)) => model,
         Ok(None) => {
             let tmp = format!("Image not found with {id} id");
             return to_response(
@@ -92,9 +131,39 @@ pub async fn print_image(
         }
     };
 
-    let file = match tokio::fs::File::open(&path).await {
-        Ok(file) => file,
+    let sizes = match image_size::Entity::find()
+        .filter(image_size::Column::ImageId.eq(id))
+        .order_by_asc(image_size::Column::Width)
+        .all(&txn)
+        .await
+    {
+        Ok(sizes) => sizes,
         Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::DbError(err.to_string())),
+            );
+        }
+    };
+
+    let size = match (&query.size, query.w) {
+        (Some(name), _) => sizes.iter().find(|size| &size.size == name),
+        (None, Some(width)) => sizes.iter().find(|size| size.width as u32 >= width),
+        (None, None) => None,
+    };
+    let key = match size {
+        Some(size) => sized_object_key(&model.path_name, &size.size, model.extension),
+        None => object_key(&model.path_name, model.extension),
+    };
+
+    let file = match storage.get(&key).await {
+        Ok(file) => file,
+        Err(err @ StorageError::NotFound(_)) => {
             return to_response(
                 (
                     StatusCode::NOT_FOUND,
@@ -105,9 +174,20 @@ pub async fn print_image(
                 Err(ApiError::General(err.to_string())),
             )
         }
+        Err(err) => {
+            return to_response(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({
+                        "error": "Internal server error"
+                    })),
+                ),
+                Err(ApiError::General(err.to_string())),
+            )
+        }
     };
 
-    let content_type = mime_guess::from_path(&path)
+    let content_type = mime_guess::from_path(&key)
         .first_raw()
         .unwrap_or("application/octet-stream");
 
@@ -130,6 +210,7 @@ pub async fn print_image(
 
 async fn upload(
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
     mut multipart: Multipart,
 ) -> Response {
     let txn = match db.begin().await {
@@ -161,8 +242,10 @@ async fn upload(
                     }
                 };
 
-                let file_extension = match allowed_content_types().get(content_type.as_str()) {
-                    Some(&ext) => ext.to_owned(),
+                let file_extension = match FileExtension::from_content_type(&content_type)
+                    .filter(|ext| ALLOWED_EXTENSIONS.contains(ext))
+                {
+                    Some(ext) => ext,
                     None => {
                         let tmp = "Unsupported content type.";
                         return to_response(
@@ -222,43 +305,142 @@ async fn upload(
                     );
                 }
 
+                //Content type comes from the client, the file has to really be in that format
+                if imaging::detect_format(&data) != Some(file_extension) {
+                    let tmp = "File content doesn't match its content type.";
+                    return to_response(
+                        (StatusCode::BAD_REQUEST, Json(json!({"error": tmp}))),
+                        Err(ApiError::General(format!(
+                            "{} Declared {}",
+                            tmp, content_type
+                        ))),
+                    );
+                }
+
+                //Drops metadata, rejects broken images and decompression bombs before storing
+                let data = match tokio::task::spawn_blocking(move || {
+                    imaging::reencode(&data, file_extension, *MAX_IMAGE_PIXELS)
+                })
+                .await
+                {
+                    Ok(Ok(data)) => Bytes::from(data),
+                    Ok(Err(err @ ImagingError::TooLarge { .. })) => {
+                        return to_response(
+                            (
+                                StatusCode::PAYLOAD_TOO_LARGE,
+                                Json(json!({
+                                    "error": "Image has too many pixels"
+                                })),
+                            ),
+                            Err(ApiError::General(err.to_string())),
+                        );
+                    }
+                    Ok(Err(err)) => {
+                        return to_response(
+                            (
+                                StatusCode::BAD_REQUEST,
+                                Json(json!({
+                                    "error": "Invalid image file."
+                                })),
+                            ),
+                            Err(ApiError::General(err.to_string())),
+                        );
+                    }
+                    Err(err) => {
+                        return to_response(
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Json(json!({
+                                    "error": "Internal server error"
+                                })),
+                            ),
+                            Err(ApiError::General(err.to_string())),
+                        );
+                    }
+                };
+
+                //Same content is stored once, the existing image is returned instead
+                let sha256 = hex::encode(Sha256::digest(&data));
+                match ImageEntity::find()
+                    .filter(image::Column::Sha256.eq(&sha256))
+                    .one(&txn)
+                    .await
+                {
+                    Ok(Some(existing)) => {
+                        return to_response(
+                            (
+                                StatusCode::OK,
+                                Json(json!({
+                                    "message": "Image already uploaded.",
+                                    "id": existing.id,
+                                    "file_name": existing.file_name
+                                })),
+                            ),
+                            Ok(()),
+                        );
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        return to_response(
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                Json(json!({
+                                    "error": "Internal server error"
+                                })),
+                            ),
+                            Err(ApiError::DbError(err.to_string())),
+                        );
+                    }
+                }
+
                 let id = Uuid::new_v4().to_string();
                 let new_image = image::ActiveModel {
                     file_name: Set(file_name.clone()),
                     path_name: Set(id.clone()),
                     extension: Set(file_extension),
+                    sha256: Set(sha256),
                     ..Default::default()
                 };
 
                 match ImageEntity::insert(new_image).exec(&txn).await {
-                    Ok(_) => {
-                        return match std::fs::write(
-                            format!(
-                                "/workspaces/rust-baranki/uploads/{}.{}",
-                                id,
-                                file_extension.to_string()
-                            ),
-                            data,
-                        ) {
+                    Ok(inserted) => {
+                        let key = object_key(&id, file_extension);
+                        return match storage.put(&key, data.clone(), &content_type).await {
                             Ok(_) => match txn.commit().await {
-                                Ok(_) => to_response(
-                                    (
-                                        StatusCode::CREATED,
-                                        Json(json!({
-                                            "message": "File uploaded successfully."
-                                        })),
-                                    ),
-                                    Ok(()),
-                                ),
-                                Err(err) => to_response(
-                                    (
-                                        StatusCode::INTERNAL_SERVER_ERROR,
-                                        Json(json!({
-                                            "error": "Internal server error."
-                                        })),
-                                    ),
-                                    Err(ApiError::DbError(err.to_string())),
-                                ),
+                                Ok(_) => {
+                                    generate_sizes(
+                                        db.clone(),
+                                        storage.clone(),
+                                        inserted.last_insert_id,
+                                        id,
+                                        file_extension,
+                                        content_type,
+                                        data,
+                                    );
+                                    to_response(
+                                        (
+                                            StatusCode::CREATED,
+                                            Json(json!({
+                                                "message": "File uploaded successfully.",
+                                                "id": inserted.last_insert_id
+                                            })),
+                                        ),
+                                        Ok(()),
+                                    )
+                                }
+                                Err(err) => {
+                                    //Row wasn't saved, the object would be an orphan
+                                    delete_objects(storage.as_ref(), &[key]).await;
+                                    to_response(
+                                        (
+                                            StatusCode::INTERNAL_SERVER_ERROR,
+                                            Json(json!({
+                                                "error": "Internal server error."
+                                            })),
+                                        ),
+                                        Err(ApiError::DbError(err.to_string())),
+                                    )
+                                }
                             },
                             Err(err) => {
                                 let _ = txn.rollback().await;
@@ -269,7 +451,7 @@ async fn upload(
                                             "error": "Failed to upload file to the server"
                                         })),
                                     ),
-                                    Err(ApiError::DbError(err.to_string())),
+                                    Err(ApiError::General(err.to_string())),
                                 )
                             }
                         };
@@ -351,6 +533,22 @@ async fn get_images(
     }
 }
 
+//Images sharing the same content, left by concurrent uploads
+async fn get_duplicates(Extension(db): Extension<Arc<DatabaseConnection>>) -> Response {
+    match find_duplicates(&*db).await {
+        Ok(duplicates) => to_response((StatusCode::OK, Json(duplicates)), Ok(())),
+        Err(err) => to_response(
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": "Internal server error"
+                })),
+            ),
+            Err(ApiError::DbError(err.to_string())),
+        ),
+    }
+}
+
 async fn patch_image(
     Path(id): Path<i32>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
@@ -452,6 +650,7 @@ async fn patch_image(
 async fn delete_image(
     Path(id): Path<i32>,
     Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(storage): Extension<Arc<dyn Storage>>,
 ) -> Response {
     let txn = match db.begin().await {
         Ok(txn) => txn,
@@ -470,46 +669,101 @@ async fn delete_image(
 
     match ImageEntity::find_by_id(id).one(&txn).await {
         Ok(Some(image)) => {
-            let file_path = image.path_name.clone();
+            //Cover image can't be removed from the product, so it blocks deletion
+            let covers: Vec<i32> = match product::Entity::find()
+                .filter(product::Column::ImageId.eq(id))
+                .select_only()
+                .column(product::Column::Id)
+                .into_tuple()
+                .all(&txn)
+                .await
+            {
+                Ok(covers) => covers,
+                Err(err) => {
+                    return to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({
+                                "error": "Internal server error"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    );
+                }
+            };
+
+            if !covers.is_empty() {
+                let _ = txn.rollback().await;
+                return to_response(
+                    (
+                        StatusCode::CONFLICT,
+                        Json(json!({
+                            "error": "Image is used as a cover of some products",
+                            "product_ids": covers
+                        })),
+                    ),
+                    Err(ApiError::General(format!(
+                        "Image {} is a cover of products {:?}",
+                        id, covers
+                    ))),
+                );
+            }
+
+            if let Err(err) = detach_image(&txn, id).await {
+                let _ = txn.rollback().await;
+                return to_response(
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(json!({
+                            "error": "Internal server error"
+                        })),
+                    ),
+                    Err(ApiError::DbError(err.to_string())),
+                );
+            }
+
+            let keys = match remove_sizes(&txn, &image).await {
+                Ok(keys) => keys,
+                Err(err) => {
+                    let _ = txn.rollback().await;
+                    return to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({
+                                "error": "Internal server error"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    );
+                }
+            };
 
             let image_active: image::ActiveModel = image.into();
             match image_active.delete(&txn).await {
-                Ok(_) => {
-                    match tokio_fs::remove_file(format!("./uploads/{}.jpg", &file_path)).await {
-                        Ok(_) => match txn.commit().await {
-                            Ok(_) => to_response(
-                                (
-                                    StatusCode::OK,
-                                    Json(json!({
-                                        "message": "Resource deleted successfully."
-                                    })),
-                                ),
-                                Ok(()),
-                            ),
-                            Err(err) => to_response(
-                                (
-                                    StatusCode::INTERNAL_SERVER_ERROR,
-                                    Json(json!({
-                                        "error": "Internal server error"
-                                    })),
-                                ),
-                                Err(ApiError::DbError(err.to_string())),
+                //Files go only after the rows, so a failed commit can't leave rows without files
+                Ok(_) => match txn.commit().await {
+                    Ok(_) => {
+                        delete_objects(&*storage, &keys).await;
+                        to_response(
+                            (
+                                StatusCode::OK,
+                                Json(json!({
+                                    "message": "Resource deleted successfully."
+                                })),
                             ),
-                        },
-                        Err(err) => {
-                            let _ = txn.rollback().await;
-                            to_response(
-                                (
-                                    StatusCode::BAD_REQUEST,
-                                    Json(json!({
-                                        "error": "Failed to delete this resource"
-                                    })),
-                                ),
-                                Err(ApiError::DbError(err.to_string())),
-                            )
-                        }
+                            Ok(()),
+                        )
                     }
-                }
+                    Err(err) => to_response(
+                        (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            Json(json!({
+                                "error": "Internal server error"
+                            })),
+                        ),
+                        Err(ApiError::DbError(err.to_string())),
+                    ),
+                },
                 Err(err) => {
                     let _ = txn.rollback().await;
                     to_response(
@@ -563,14 +817,195 @@ struct ImagesQuery {
     query: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct PrintImageQuery {
+    w: Option<u32>,
+    size: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DuplicateImages {
+    sha256: String,
+    images: Vec<image::Model>,
+}
+
 //utils
-fn allowed_content_types() -> HashMap<&'static str, FileExtension> {
-    HashMap::from([
-        ("image/jpeg", FileExtension::JPG),
-        ("image/png", FileExtension::PNG),
-    ])
+//Removes image from product galleries and categories, so nothing points to it after deletion
+async fn detach_image<C: ConnectionTrait>(db: &C, image_id: i32) -> Result<(), DbErr> {
+    let entries = product_image::Entity::find()
+        .filter(product_image::Column::ImageId.eq(image_id))
+        .all(db)
+        .await?;
+    for entry in entries {
+        remove_from_gallery(db, entry).await?;
+    }
+
+    category::Entity::update_many()
+        .col_expr(category::Column::ImageId, Expr::value(Option::<i32>::None))
+        .filter(category::Column::ImageId.eq(image_id))
+        .exec(db)
+        .await
+        .map(|_| ())
+}
+
+//Name of the image file in storage
+pub fn object_key(path_name: &str, extension: FileExtension) -> String {
+    format!("{}.{}", path_name, extension.to_string())
 }
 
+pub fn sized_object_key(path_name: &str, size: &str, extension: FileExtension) -> String {
+    object_key(&format!("{}_{}", path_name, size), extension)
+}
+
+//Resized copies are made in background, the original is served until they are ready
+fn generate_sizes(
+    db: Arc<DatabaseConnection>,
+    storage: Arc<dyn Storage>,
+    image_id: i32,
+    path_name: String,
+    extension: FileExtension,
+    content_type: String,
+    data: Bytes,
+) {
+    tokio::spawn(async move {
+        if let Err(err) = store_sizes(
+            &db,
+            &*storage,
+            image_id,
+            &path_name,
+            extension,
+            &content_type,
+            data,
+        )
+        .await
+        {
+            tracing::error!(event = "image_resize_failed", image_id, error = %err);
+        }
+    });
+}
+
+async fn store_sizes(
+    db: &DatabaseConnection,
+    storage: &dyn Storage,
+    image_id: i32,
+    path_name: &str,
+    extension: FileExtension,
+    content_type: &str,
+    data: Bytes,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let sizes = tokio::task::spawn_blocking(move || imaging::resize(&data, extension)).await??;
+
+    for resized in sizes {
+        let key = sized_object_key(path_name, resized.size, extension);
+        storage.put(&key, resized.data.into(), content_type).await?;
+
+        let result = image_size::ActiveModel {
+            image_id: Set(image_id),
+            size: Set(resized.size.to_string()),
+            width: Set(resized.width as i32),
+            height: Set(resized.height as i32),
+            ..Default::default()
+        }
+        .insert(db)
+        .await;
+        //Image could be deleted in the meantime
+        if let Err(err) = result {
+            let _ = storage.delete(&key).await;
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+//Deletes resized copies from the database, returns storage keys of the image and its copies
+async fn remove_sizes<C: ConnectionTrait>(
+    db: &C,
+    image: &image::Model,
+) -> Result<Vec<String>, DbErr> {
+    let sizes = image_size::Entity::find()
+        .filter(image_size::Column::ImageId.eq(image.id))
+        .all(db)
+        .await?;
+    image_size::Entity::delete_many()
+        .filter(image_size::Column::ImageId.eq(image.id))
+        .exec(db)
+        .await?;
+
+    let mut keys = vec![object_key(&image.path_name, image.extension)];
+    keys.extend(
+        sizes
+            .iter()
+            .map(|size| sized_object_key(&image.path_name, &size.size, image.extension)),
+    );
+    Ok(keys)
+}
+
+async fn find_duplicates<C: ConnectionTrait>(db: &C) -> Result<Vec<DuplicateImages>, DbErr> {
+    let hashes: Vec<String> = ImageEntity::find()
+        .select_only()
+        .column(image::Column::Sha256)
+        .group_by(image::Column::Sha256)
+        .having(Expr::expr(Expr::col(image::Column::Id).count()).gt(1))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let images = ImageEntity::find()
+        .filter(image::Column::Sha256.is_in(hashes))
+        .order_by_asc(image::Column::Sha256)
+        .order_by_asc(image::Column::Id)
+        .all(db)
+        .await?;
+
+    let mut duplicates: Vec<DuplicateImages> = Vec::new();
+    for image in images {
+        match duplicates.last_mut() {
+            Some(group) if group.sha256 == image.sha256 => group.images.push(image),
+            _ => duplicates.push(DuplicateImages {
+                sha256: image.sha256.clone(),
+                images: vec![image],
+            }),
+        }
+    }
+    Ok(duplicates)
+}
+
+//Best-effort, objects that fail to delete are only logged
+async fn delete_objects(storage: &dyn Storage, keys: &[String]) {
+    for key in keys {
+        if let Err(err) = storage.delete(key).await {
+            tracing::error!(event = "image_object_delete_failed", key = %key, error = %err);
+        }
+    }
+}
+
+//ALLOWED_IMAGE_FORMATS is a comma separated list of extensions, e.g. "jpg,png,webp".
+//AVIF is out of scope: it's recognized, but `image` can't decode it without the native dav1d
+//library, so it can't be re-encoded and is never allowed
+static ALLOWED_EXTENSIONS: Lazy<Vec<FileExtension>> = Lazy::new(|| {
+    dotenv().ok();
+    std::env::var("ALLOWED_IMAGE_FORMATS")
+        .unwrap_or_else(|_| "jpg,png".to_string())
+        .split(',')
+        .map(|ext| match ext.trim().parse::<FileExtension>() {
+            Ok(FileExtension::AVIF) => {
+                panic!("AVIF can't be allowed in ALLOWED_IMAGE_FORMATS, it can't be re-encoded")
+            }
+            Ok(extension) => extension,
+            Err(_) => panic!("Unsupported image format {} in ALLOWED_IMAGE_FORMATS", ext),
+        })
+        .collect()
+});
+
+//Width times height, frames of GIF animation count too
+static MAX_IMAGE_PIXELS: Lazy<u64> = Lazy::new(|| {
+    dotenv().ok();
+    std::env::var("MAX_IMAGE_PIXELS")
+        .ok()
+        .map(|pixels| pixels.parse().expect("Failed to parse MAX_IMAGE_PIXELS"))
+        .unwrap_or(40_000_000)
+});
+
 static FILE_NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_]{3,25}$").unwrap());
 
 fn get_file_size_limit() -> usize {
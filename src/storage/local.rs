@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::{io::ErrorKind, path::PathBuf};
+
+use super::{ObjectReader, Storage, StorageError};
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    //Directory is created on startup if it doesn't exist
+    pub fn from_env() -> Self {
+        let root = PathBuf::from(
+            std::env::var("STORAGE_ROOT").unwrap_or_else(|_| "./uploads".to_string()),
+        );
+        std::fs::create_dir_all(&root).expect("Failed to create STORAGE_ROOT directory");
+        Self::new(root)
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> Result<(), StorageError> {
+        tokio::fs::write(self.root.join(key), data)
+            .await
+            .map_err(|err| StorageError::WriteFail(err.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError> {
+        match tokio::fs::File::open(self.root.join(key)).await {
+            Ok(file) => Ok(Box::pin(file)),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Err(StorageError::NotFound(key.to_string()))
+            }
+            Err(err) => Err(StorageError::ReadFail(err.to_string())),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.root.join(key)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(StorageError::DeleteFail(err.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
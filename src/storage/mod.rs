@@ -0,0 +1,44 @@
+pub mod local;
+pub mod s3;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use dotenvy::dotenv;
+use std::{pin::Pin, sync::Arc};
+use thiserror::Error;
+use tokio::io::AsyncRead;
+
+use local::LocalStorage;
+use s3::S3Storage;
+
+pub type ObjectReader = Pin<Box<dyn AsyncRead + Send>>;
+
+//Handlers get it as `Extension<Arc<dyn Storage>>`, keys look like `<uuid>.<extension>`
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> Result<(), StorageError>;
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError>;
+    //Deleting a missing object isn't an error, same as in S3
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Object {0} not found")]
+    NotFound(String),
+    #[error("Failed to write object: {0}")]
+    WriteFail(String),
+    #[error("Failed to read object: {0}")]
+    ReadFail(String),
+    #[error("Failed to delete object: {0}")]
+    DeleteFail(String),
+}
+
+//STORAGE=s3 keeps images in S3_BUCKET, anything else in STORAGE_ROOT directory
+pub fn from_env() -> Arc<dyn Storage> {
+    dotenv().ok();
+    match std::env::var("STORAGE").as_deref() {
+        Ok("s3") => Arc::new(S3Storage::from_env()),
+        _ => Arc::new(LocalStorage::from_env()),
+    }
+}
@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    config::{Credentials, Region},
+    error::DisplayErrorContext,
+    operation::get_object::GetObjectError,
+    primitives::ByteStream,
+    Client, Config,
+};
+use bytes::Bytes;
+
+use super::{ObjectReader, Storage, StorageError};
+
+//Works with AWS and S3-compatible servers like MinIO
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+}
+
+impl S3Storage {
+    pub fn new(client: Client, bucket: String) -> Self {
+        Self { client, bucket }
+    }
+
+    //S3_ENDPOINT is set for S3-compatible servers, they get path-style URLs.
+    //The bucket has to exist already.
+    pub fn from_env() -> Self {
+        let bucket = std::env::var("S3_BUCKET").expect("S3_BUCKET not found in .env file");
+        let access_key =
+            std::env::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY not found in .env file");
+        let secret_key =
+            std::env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY not found in .env file");
+        let region = std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+
+        let mut builder = Config::builder()
+            .credentials_provider(Credentials::new(access_key, secret_key, None, None, "env"))
+            .region(Region::new(region));
+        if let Ok(endpoint) = std::env::var("S3_ENDPOINT") {
+            builder = builder.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Self::new(Client::from_conf(builder.build()), bucket)
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> Result<(), StorageError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| StorageError::WriteFail(DisplayErrorContext(err).to_string()))
+    }
+
+    async fn get(&self, key: &str) -> Result<ObjectReader, StorageError> {
+        match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Box::pin(output.body.into_async_read())),
+            Err(err)
+                if err
+                    .as_service_error()
+                    .is_some_and(GetObjectError::is_no_such_key) =>
+            {
+                Err(StorageError::NotFound(key.to_string()))
+            }
+            Err(err) => Err(StorageError::ReadFail(DisplayErrorContext(err).to_string())),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|err| StorageError::DeleteFail(DisplayErrorContext(err).to_string()))
+    }
+}
@@ -1,5 +1,8 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use reqwest::{header, Client};
 use tokio;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
 
 // Test if the server is running and responds to a health check
 #[tokio::test]
@@ -115,3 +118,1332 @@ async fn test_login() {
     println!("{:?}", response);
 }
 
+#[tokio::test]
+async fn test_refresh_and_logout() {
+    let client = Client::new();
+
+    let payload = serde_json::json!({
+        "username": "refresh_user",
+        "password": "Secret15"
+    });
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&payload)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&payload)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let refresh_token = body["refresh_token"]
+        .as_str()
+        .expect("Refresh token not found in login response")
+        .to_owned();
+
+    //Refresh token is rotated
+    let response = client
+        .post("http://127.0.0.1:3000/refresh")
+        .json(&serde_json::json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in refresh response")
+        .to_owned();
+    let new_refresh_token = body["refresh_token"]
+        .as_str()
+        .expect("Refresh token not found in refresh response")
+        .to_owned();
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to insert header"),
+    );
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Logout revokes the session, access token stops working
+    let response = client
+        .post("http://127.0.0.1:3000/logout")
+        .json(&serde_json::json!({ "refresh_token": new_refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:3000/refresh")
+        .json(&serde_json::json!({ "refresh_token": new_refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_refresh_token_reuse() {
+    let client = Client::new();
+
+    let payload = serde_json::json!({
+        "username": "user",
+        "password": "Secret15"
+    });
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&payload)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let refresh_token = body["refresh_token"]
+        .as_str()
+        .expect("Refresh token not found in login response")
+        .to_owned();
+
+    let body = client
+        .post("http://127.0.0.1:3000/refresh")
+        .json(&serde_json::json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let new_refresh_token = body["refresh_token"]
+        .as_str()
+        .expect("Refresh token not found in refresh response")
+        .to_owned();
+
+    //Old token used again, the whole family is revoked
+    let response = client
+        .post("http://127.0.0.1:3000/refresh")
+        .json(&serde_json::json!({ "refresh_token": refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:3000/refresh")
+        .json(&serde_json::json!({ "refresh_token": new_refresh_token }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_admin_uses_user_routes() {
+    let client = Client::new();
+
+    let payload = serde_json::json!({
+        "username": "admin",
+        "password": "Secret15"
+    });
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&payload)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to insert header"),
+    );
+
+    //Admin role holds every permission, so user endpoints are open for it
+    for url in [
+        "http://127.0.0.1:3000/api/cart",
+        "http://127.0.0.1:3000/api/profile",
+    ] {
+        let response = client
+            .get(url)
+            .headers(headers.clone())
+            .send()
+            .await
+            .expect("Failed to send request to protected url");
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_user_cant_use_admin_routes() {
+    let client = Client::new();
+
+    let payload = serde_json::json!({
+        "username": "user",
+        "password": "Secret15"
+    });
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&payload)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to insert header"),
+    );
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/admin/user")
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_runtime_role_permissions() {
+    let client = Client::new();
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+
+    //Role may be left from the previous run, its permissions are reset below
+    client
+        .post("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({
+            "name": "cart_keeper",
+            "description": "Can only use the cart"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create role request");
+
+    let roles = client
+        .get("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get roles request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse roles JSON");
+    let role_id = roles
+        .as_array()
+        .expect("Roles should be an array")
+        .iter()
+        .find(|role| role["name"] == "cart_keeper")
+        .expect("Created role not found")["id"]
+        .as_i64()
+        .expect("Role id not found");
+
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({ "permissions": ["cart:use", "no:such"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({ "permissions": ["cart:use"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    client
+        .post("http://127.0.0.1:3000/api/admin/user")
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({
+            "username": "cart_keeper",
+            "password": "Secret15",
+            "role": "cart_keeper"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create user request");
+
+    let headers = login_headers(&client, "cart_keeper", "Secret15").await;
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    //Permissions are read on every request, the same token follows the change
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({ "permissions": ["profile:manage"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    //Role is in use
+    let response = client
+        .delete(format!("http://127.0.0.1:3000/api/admin/role/{}", role_id))
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send delete role request");
+    assert_eq!(response.status(), reqwest::StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_user_manager_cant_escalate() {
+    let client = Client::new();
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+
+    //Role may be left from the previous run, its permissions are reset below
+    client
+        .post("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({
+            "name": "user_manager",
+            "description": "Can only manage users"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create role request");
+
+    let roles = client
+        .get("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get roles request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse roles JSON");
+    let role_id = roles
+        .as_array()
+        .expect("Roles should be an array")
+        .iter()
+        .find(|role| role["name"] == "user_manager")
+        .expect("Created role not found")["id"]
+        .as_i64()
+        .expect("Role id not found");
+
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({ "permissions": ["user:manage"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    client
+        .post("http://127.0.0.1:3000/api/admin/user")
+        .headers(admin_headers)
+        .json(&serde_json::json!({
+            "username": "user_manager",
+            "password": "Secret15",
+            "role": "user_manager"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create user request");
+
+    let headers = login_headers(&client, "user_manager", "Secret15").await;
+
+    let users = client
+        .get("http://127.0.0.1:3000/api/admin/user?query=_manager")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get users request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse users JSON");
+    let own_id = users
+        .as_array()
+        .expect("Users should be an array")
+        .iter()
+        .find(|user| user["username"] == "user_manager")
+        .expect("Created user not found")["id"]
+        .as_i64()
+        .expect("User id not found");
+
+    let users = client
+        .get("http://127.0.0.1:3000/api/admin/user?role=admin")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get users request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse users JSON");
+    let admin_id = users
+        .as_array()
+        .expect("Users should be an array")
+        .iter()
+        .find(|user| user["username"] == "admin")
+        .expect("Admin not found")["id"]
+        .as_i64()
+        .expect("User id not found");
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/admin/user")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "username": "sneaky_admin",
+            "password": "Secret15",
+            "role": "admin"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create user request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .patch(format!("http://127.0.0.1:3000/api/admin/user/{}", own_id))
+        .headers(headers.clone())
+        .json(&serde_json::json!({ "role": "admin" }))
+        .send()
+        .await
+        .expect("Failed to send patch user request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .patch(format!("http://127.0.0.1:3000/api/admin/user/{}", admin_id))
+        .headers(headers.clone())
+        .json(&serde_json::json!({ "password": "Hijacked15" }))
+        .send()
+        .await
+        .expect("Failed to send patch user request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/user/{}/password-reset",
+            admin_id
+        ))
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send password reset request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_role_manager_cant_escalate() {
+    let client = Client::new();
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+
+    //Role may be left from the previous run, its permissions are reset below
+    client
+        .post("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({
+            "name": "role_manager",
+            "description": "Can only manage roles"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create role request");
+
+    let roles = client
+        .get("http://127.0.0.1:3000/api/admin/role")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get roles request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse roles JSON");
+    let role_id = roles
+        .as_array()
+        .expect("Roles should be an array")
+        .iter()
+        .find(|role| role["name"] == "role_manager")
+        .expect("Created role not found")["id"]
+        .as_i64()
+        .expect("Role id not found");
+
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&serde_json::json!({ "permissions": ["role:manage"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    client
+        .post("http://127.0.0.1:3000/api/admin/user")
+        .headers(admin_headers)
+        .json(&serde_json::json!({
+            "username": "role_manager",
+            "password": "Secret15",
+            "role": "role_manager"
+        }))
+        .send()
+        .await
+        .expect("Failed to send create user request");
+
+    let headers = login_headers(&client, "role_manager", "Secret15").await;
+
+    for permission in ["user:manage", "product:write"] {
+        let response = client
+            .put(format!(
+                "http://127.0.0.1:3000/api/admin/role/{}/permission",
+                role_id
+            ))
+            .headers(headers.clone())
+            .json(&serde_json::json!({ "permissions": ["role:manage", permission] }))
+            .send()
+            .await
+            .expect("Failed to send put permissions request");
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/admin/role")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "name": "product_writer",
+            "permissions": ["product:write"]
+        }))
+        .send()
+        .await
+        .expect("Failed to send create role request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    //Permissions the caller has can still be given out
+    let response = client
+        .put(format!(
+            "http://127.0.0.1:3000/api/admin/role/{}/permission",
+            role_id
+        ))
+        .headers(headers)
+        .json(&serde_json::json!({ "permissions": ["role:manage"] }))
+        .send()
+        .await
+        .expect("Failed to send put permissions request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_forced_password_change() {
+    let client = Client::new();
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": "pwd_changer",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+
+    let users = client
+        .get("http://127.0.0.1:3000/api/admin/user?query=pwd_changer")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get users request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse users JSON");
+    let user_id = users[0]["id"].as_i64().expect("User id not found");
+
+    let headers = login_headers(&client, "pwd_changer", "Secret15").await;
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/user/{}/password-reset",
+            user_id
+        ))
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send password reset request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Everything except password change is blocked
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/password")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "current_password": "Wrong1515",
+            "new_password": "Newpass15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send change password request");
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/password")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "current_password": "Secret15",
+            "new_password": "Newpass15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send change password request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Old session is revoked
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let headers = login_headers(&client, "pwd_changer", "Newpass15").await;
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Restore password for the next run
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/password")
+        .headers(headers)
+        .json(&serde_json::json!({
+            "current_password": "Newpass15",
+            "new_password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send change password request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_forgot_and_reset_password() {
+    let client = Client::new();
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": "reset_user",
+            "password": "Secret15",
+            "email": "reset_user@example.com"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+
+    let sent_before = reset_tokens("reset_user@example.com").len();
+
+    //Same answer for existing and unknown users
+    let mut answers = Vec::new();
+    for username in ["reset_user", "no_such_user"] {
+        let response = client
+            .post("http://127.0.0.1:3000/password/forgot")
+            .json(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to send forgot password request");
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        answers.push(response.text().await.expect("Failed to read response"));
+    }
+    assert_eq!(answers[0], answers[1]);
+
+    let token = wait_for_reset_token("reset_user@example.com", sent_before).await;
+
+    let response = client
+        .post("http://127.0.0.1:3000/password/reset")
+        .json(&serde_json::json!({
+            "token": token,
+            "new_password": "Newpass15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send reset password request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Token is single-use
+    let response = client
+        .post("http://127.0.0.1:3000/password/reset")
+        .json(&serde_json::json!({
+            "token": token,
+            "new_password": "Other1515"
+        }))
+        .send()
+        .await
+        .expect("Failed to send reset password request");
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    let headers = login_headers(&client, "reset_user", "Newpass15").await;
+
+    //Restore password for the next run
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/password")
+        .headers(headers)
+        .json(&serde_json::json!({
+            "current_password": "Newpass15",
+            "new_password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send change password request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_forgot_password_rate_limit() {
+    let client = Client::new();
+    let username = format!("flood_{}", &Uuid::new_v4().simple().to_string()[..8]);
+
+    //LOGIN_MAX_ATTEMPTS=5 in .env, requests for one user are limited like failed logins
+    for _ in 1..=5 {
+        let response = client
+            .post("http://127.0.0.1:3000/password/forgot")
+            .json(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to send forgot password request");
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+
+    let response = client
+        .post("http://127.0.0.1:3000/password/forgot")
+        .json(&serde_json::json!({ "username": username }))
+        .send()
+        .await
+        .expect("Failed to send forgot password request");
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+}
+
+#[tokio::test]
+async fn test_change_password_lockout() {
+    let client = Client::new();
+    let username = format!("guess_{}", &Uuid::new_v4().simple().to_string()[..8]);
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+    let headers = login_headers(&client, &username, "Secret15").await;
+
+    //Wrong current passwords count as failed logins, LOGIN_MAX_ATTEMPTS=5 in .env
+    for _ in 1..=5 {
+        let response = client
+            .post("http://127.0.0.1:3000/api/profile/password")
+            .headers(headers.clone())
+            .json(&serde_json::json!({
+                "current_password": "Wrong1515",
+                "new_password": "Newpass15"
+            }))
+            .send()
+            .await
+            .expect("Failed to send change password request");
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    //Right password doesn't help while locked, neither does login
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/password")
+        .headers(headers)
+        .json(&serde_json::json!({
+            "current_password": "Secret15",
+            "new_password": "Newpass15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send change password request");
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    let response = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&serde_json::json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request");
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn test_login_lockout() {
+    let client = Client::new();
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": "lockout_user",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+
+    //LOGIN_MAX_ATTEMPTS=5 in .env, the fifth failure locks the account
+    for attempt in 1..=5 {
+        let response = client
+            .post("http://127.0.0.1:3000/login")
+            .json(&serde_json::json!({
+                "username": "lockout_user",
+                "password": "Wrong1515"
+            }))
+            .send()
+            .await
+            .expect("Failed to send login request");
+        if attempt < 5 {
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        } else {
+            assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+            assert!(response.headers().contains_key(header::RETRY_AFTER));
+        }
+    }
+
+    //Right password doesn't help while locked
+    let response = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&serde_json::json!({
+            "username": "lockout_user",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request");
+    assert_eq!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS);
+
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+    let users = client
+        .get("http://127.0.0.1:3000/api/admin/user?query=lockout_user")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get users request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse users JSON");
+    let user_id = users[0]["id"].as_i64().expect("User id not found");
+
+    let response = client
+        .patch(format!("http://127.0.0.1:3000/api/admin/user/{}", user_id))
+        .headers(admin_headers)
+        .json(&serde_json::json!({ "unlock": true }))
+        .send()
+        .await
+        .expect("Failed to send unlock request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&serde_json::json!({
+            "username": "lockout_user",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_two_factor_login() {
+    let client = Client::new();
+    //Accounts with 2FA can't be reused, so every run gets its own
+    let username = format!("tfa_{}", &Uuid::new_v4().simple().to_string()[..8]);
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+    let headers = login_headers(&client, &username, "Secret15").await;
+
+    let body = client
+        .post("http://127.0.0.1:3000/api/profile/2fa/enroll")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send enroll request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse enroll JSON");
+    assert!(body["otpauth_uri"]
+        .as_str()
+        .expect("otpauth URI not found")
+        .starts_with("otpauth://totp/"));
+    let secret = body["secret"].as_str().expect("Secret not found");
+
+    let code = TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        Secret::Encoded(secret.to_string())
+            .to_bytes()
+            .expect("Invalid secret"),
+        None,
+        username.clone(),
+    )
+    .expect("Failed to build TOTP")
+    .generate_current()
+    .expect("Failed to generate code");
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/2fa/confirm")
+        .headers(headers.clone())
+        .json(&serde_json::json!({ "code": code }))
+        .send()
+        .await
+        .expect("Failed to send confirm request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse confirm JSON");
+    let recovery_codes: Vec<String> =
+        serde_json::from_value(body["recovery_codes"].clone()).expect("Recovery codes not found");
+    assert_eq!(recovery_codes.len(), 10);
+
+    //Password alone gives only the pending token
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&serde_json::json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login JSON");
+    assert_eq!(body["two_factor_required"], true);
+    assert!(body["token"].is_null());
+    let pending_token = body["pending_token"]
+        .as_str()
+        .expect("Pending token not found");
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .bearer_auth(pending_token)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    //Code used for the confirmation can't be replayed
+    let response = client
+        .post("http://127.0.0.1:3000/login/2fa")
+        .json(&serde_json::json!({
+            "pending_token": pending_token,
+            "code": code
+        }))
+        .send()
+        .await
+        .expect("Failed to send 2fa login request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:3000/login/2fa")
+        .json(&serde_json::json!({
+            "pending_token": pending_token,
+            "code": recovery_codes[0]
+        }))
+        .send()
+        .await
+        .expect("Failed to send 2fa login request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse 2fa login JSON");
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!(
+            "Bearer {}",
+            body["token"].as_str().expect("Token not found")
+        ))
+        .expect("Failed to insert header"),
+    );
+
+    //Recovery codes are single-use
+    let response = client
+        .post("http://127.0.0.1:3000/login/2fa")
+        .json(&serde_json::json!({
+            "pending_token": pending_token,
+            "code": recovery_codes[0]
+        }))
+        .send()
+        .await
+        .expect("Failed to send 2fa login request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/2fa/disable")
+        .headers(headers)
+        .json(&serde_json::json!({
+            "password": "Secret15",
+            "code": recovery_codes[1]
+        }))
+        .send()
+        .await
+        .expect("Failed to send disable request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    login_headers(&client, &username, "Secret15").await;
+}
+
+#[tokio::test]
+async fn test_key_rotation_and_jwks() {
+    let client = Client::new();
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+    let old_kid = token_kid(&admin_headers);
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/admin/key/rotate")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send rotate request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse rotate JSON");
+    let new_kid = body["kid"].as_str().expect("Kid not found").to_string();
+    assert_ne!(old_kid, new_kid);
+
+    //Tokens of the retired key still work until they expire
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send request to protected url");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let admin_headers = login_headers(&client, "admin", "Secret15").await;
+    assert_eq!(token_kid(&admin_headers), new_kid);
+
+    let jwks = client
+        .get("http://127.0.0.1:3000/.well-known/jwks.json")
+        .send()
+        .await
+        .expect("Failed to send jwks request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse jwks JSON");
+    let kids: Vec<&str> = jwks["keys"]
+        .as_array()
+        .expect("Keys not found")
+        .iter()
+        .filter_map(|key| key["kid"].as_str())
+        .collect();
+    assert!(kids.contains(&old_kid.as_str()));
+    assert!(kids.contains(&new_kid.as_str()));
+
+    //Only admins rotate keys
+    let response = client
+        .post("http://127.0.0.1:3000/api/admin/key/rotate")
+        .headers(login_headers(&client, "user", "Secret15").await)
+        .send()
+        .await
+        .expect("Failed to send rotate request");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_api_keys() {
+    let client = Client::new();
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&serde_json::json!({
+            "username": "api_key_user",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+    let headers = login_headers(&client, "api_key_user", "Secret15").await;
+
+    //Scopes can't exceed the role
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/api-key")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "name": "too much",
+            "scopes": ["user:manage"],
+            "expires_in_days": 30
+        }))
+        .send()
+        .await
+        .expect("Failed to send create api key request");
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/api-key")
+        .headers(headers.clone())
+        .json(&serde_json::json!({
+            "name": "cart script",
+            "scopes": ["cart:use"],
+            "expires_in_days": 30
+        }))
+        .send()
+        .await
+        .expect("Failed to send create api key request");
+    assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse api key JSON");
+    let key = body["key"].as_str().expect("Key not found").to_string();
+    let key_id = body["api_key"]["id"].as_i64().expect("Key id not found");
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .header("X-Api-Key", &key)
+        .send()
+        .await
+        .expect("Failed to send request with api key");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .bearer_auth(&key)
+        .send()
+        .await
+        .expect("Failed to send request with api key");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    //Out of scope
+    let response = client
+        .get("http://127.0.0.1:3000/api/profile")
+        .header("X-Api-Key", &key)
+        .send()
+        .await
+        .expect("Failed to send request with api key");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    //Only the prefix is listed, usage is recorded
+    let keys = client
+        .get("http://127.0.0.1:3000/api/profile/api-key")
+        .headers(headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get api keys request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse api keys JSON");
+    let listed = keys
+        .as_array()
+        .expect("Keys not found")
+        .iter()
+        .find(|listed| listed["id"].as_i64() == Some(key_id))
+        .expect("Created key not listed");
+    assert!(listed["key"].is_null());
+    assert!(key.starts_with(listed["key_prefix"].as_str().expect("Prefix not found")));
+    assert!(!listed["last_used_at"].is_null());
+
+    let response = client
+        .delete(format!(
+            "http://127.0.0.1:3000/api/profile/api-key/{}",
+            key_id
+        ))
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send delete api key request");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    let response = client
+        .get("http://127.0.0.1:3000/api/cart")
+        .header("X-Api-Key", &key)
+        .send()
+        .await
+        .expect("Failed to send request with api key");
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+}
+
+//utils
+//`kid` header of the bearer token in `headers`
+fn token_kid(headers: &header::HeaderMap) -> String {
+    let token = headers[header::AUTHORIZATION]
+        .to_str()
+        .expect("Invalid authorization header")
+        .trim_start_matches("Bearer ");
+    let header = URL_SAFE_NO_PAD
+        .decode(token.split('.').next().expect("Invalid token"))
+        .expect("Failed to decode token header");
+    serde_json::from_slice::<serde_json::Value>(&header).expect("Failed to parse token header")
+        ["kid"]
+        .as_str()
+        .expect("Kid not found")
+        .to_string()
+}
+
+//Reset tokens sent to `email`, read from the file mailer output (MAILER="file" in .env)
+fn reset_tokens(email: &str) -> Vec<String> {
+    std::fs::read_to_string("mail.log")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|mail| mail["to"] == email)
+        .filter_map(|mail| {
+            mail["body"]
+                .as_str()
+                .and_then(|body| body.split(": ").nth(1))
+                .and_then(|rest| rest.split_whitespace().next())
+                .map(|token| token.to_string())
+        })
+        .collect()
+}
+
+//Mail is sent in background, waits for the one after `sent_before` mails
+async fn wait_for_reset_token(email: &str, sent_before: usize) -> String {
+    for _ in 0..20 {
+        if let Some(token) = reset_tokens(email).get(sent_before) {
+            return token.clone();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("Reset mail for {} not found", email);
+}
+
+async fn login_headers(client: &Client, username: &str, password: &str) -> header::HeaderMap {
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&serde_json::json!({
+            "username": username,
+            "password": password
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to insert header"),
+    );
+    headers
+}
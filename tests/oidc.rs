@@ -0,0 +1,384 @@
+use axum::{
+    extract::{Form, Query, State},
+    http::StatusCode as MockStatus,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use reqwest::{header, redirect, Client, StatusCode, Url};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+//OIDC_ISSUER in .env points here
+const MOCK_ISSUER: &str = "http://127.0.0.1:3001";
+const CLIENT_ID: &str = "rust-baranki";
+
+#[tokio::test]
+async fn test_oidc_login_and_link() {
+    let idp = start_mock_idp().await;
+    //Redirects are followed by hand to check every step, cookies bind the login to the client
+    let client = Client::builder()
+        .redirect(redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .expect("Failed to build client");
+
+    //First login creates the user from `preferred_username`
+    let username = format!("oidc_{}", &Uuid::new_v4().simple().to_string()[..8]);
+    idp.sign_in_as(&Uuid::new_v4().to_string(), &username);
+    let (callback, body) = oidc_login(&client, "http://127.0.0.1:3000/auth/oidc/start").await;
+    assert_eq!(profile_username(&client, &body).await, username);
+
+    //Same provider account logs into the same user
+    let (_, body) = oidc_login(&client, "http://127.0.0.1:3000/auth/oidc/start").await;
+    assert_eq!(profile_username(&client, &body).await, username);
+
+    //State is single-use
+    let response = client
+        .get(callback)
+        .send()
+        .await
+        .expect("Failed to send callback request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    //Callback URL of a login started by another browser is refused
+    let response = client
+        .get("http://127.0.0.1:3000/auth/oidc/start")
+        .send()
+        .await
+        .expect("Failed to send start request");
+    let response = client
+        .get(location(&response))
+        .send()
+        .await
+        .expect("Failed to send authorize request");
+    let response = Client::new()
+        .get(location(&response))
+        .send()
+        .await
+        .expect("Failed to send callback request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    //Logged in user links another provider account
+    let local_name = format!("local_{}", &Uuid::new_v4().simple().to_string()[..8]);
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&json!({
+            "username": local_name,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": local_name,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login JSON");
+    let body = client
+        .post("http://127.0.0.1:3000/api/profile/oidc/link")
+        .bearer_auth(body["token"].as_str().expect("Token not found"))
+        .send()
+        .await
+        .expect("Failed to send link request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse link JSON");
+    let authorization_url = body["authorization_url"]
+        .as_str()
+        .expect("Authorization URL not found")
+        .to_string();
+
+    idp.sign_in_as(&Uuid::new_v4().to_string(), "ignored_name");
+    let (_, body) = follow_provider(&client, authorization_url).await;
+    assert_eq!(profile_username(&client, &body).await, local_name);
+
+    let (_, body) = oidc_login(&client, "http://127.0.0.1:3000/auth/oidc/start").await;
+    assert_eq!(profile_username(&client, &body).await, local_name);
+}
+
+#[tokio::test]
+async fn test_oidc_link_rejects_api_key() {
+    let client = Client::new();
+    let username = format!("key_{}", &Uuid::new_v4().simple().to_string()[..8]);
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send register request");
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": username,
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login JSON");
+    let body = client
+        .post("http://127.0.0.1:3000/api/profile/api-key")
+        .bearer_auth(body["token"].as_str().expect("Token not found"))
+        .json(&json!({
+            "name": "profile script",
+            "scopes": ["profile:manage"],
+            "expires_in_days": 1
+        }))
+        .send()
+        .await
+        .expect("Failed to send create api key request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse api key JSON");
+
+    let response = client
+        .post("http://127.0.0.1:3000/api/profile/oidc/link")
+        .header("X-Api-Key", body["key"].as_str().expect("Key not found"))
+        .send()
+        .await
+        .expect("Failed to send link request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_oidc_callback_rejects_unknown_state() {
+    let response = Client::new()
+        .get("http://127.0.0.1:3000/auth/oidc/callback?code=abc&state=unknown")
+        .send()
+        .await
+        .expect("Failed to send callback request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+//utils
+//Goes through start -> provider -> callback, returns callback URL and its JSON
+async fn oidc_login(client: &Client, start_url: &str) -> (String, serde_json::Value) {
+    let response = client
+        .get(start_url)
+        .send()
+        .await
+        .expect("Failed to send start request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    follow_provider(client, location(&response)).await
+}
+
+async fn follow_provider(
+    client: &Client,
+    authorization_url: String,
+) -> (String, serde_json::Value) {
+    let response = client
+        .get(authorization_url)
+        .send()
+        .await
+        .expect("Failed to send authorize request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let callback = location(&response);
+
+    let response = client
+        .get(&callback)
+        .send()
+        .await
+        .expect("Failed to send callback request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse callback JSON");
+    (callback, body)
+}
+
+fn location(response: &reqwest::Response) -> String {
+    response.headers()[header::LOCATION]
+        .to_str()
+        .expect("Invalid location header")
+        .to_string()
+}
+
+async fn profile_username(client: &Client, body: &serde_json::Value) -> String {
+    client
+        .get("http://127.0.0.1:3000/api/profile")
+        .bearer_auth(body["token"].as_str().expect("Token not found"))
+        .send()
+        .await
+        .expect("Failed to send profile request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse profile JSON")["username"]
+        .as_str()
+        .expect("Username not found")
+        .to_string()
+}
+
+//Mock provider
+struct MockIdp {
+    key: Vec<u8>,
+    public_key: Vec<u8>,
+    //Account that signs in at the next authorize request: (sub, preferred_username)
+    account: Mutex<(String, String)>,
+    //code -> (code_challenge, nonce, sub, preferred_username)
+    codes: Mutex<HashMap<String, (String, String, String, String)>>,
+}
+
+impl MockIdp {
+    fn sign_in_as(&self, sub: &str, username: &str) {
+        *self.account.lock().unwrap() = (sub.to_string(), username.to_string());
+    }
+}
+
+async fn start_mock_idp() -> Arc<MockIdp> {
+    let key = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .expect("Failed to generate key")
+        .as_ref()
+        .to_vec();
+    let public_key = Ed25519KeyPair::from_pkcs8(&key)
+        .expect("Failed to parse key")
+        .public_key()
+        .as_ref()
+        .to_vec();
+    let idp = Arc::new(MockIdp {
+        key,
+        public_key,
+        account: Mutex::new((String::new(), String::new())),
+        codes: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(mock_discovery))
+        .route("/authorize", get(mock_authorize))
+        .route("/token", post(mock_token))
+        .route("/jwks", get(mock_jwks))
+        .with_state(idp.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:3001")
+        .await
+        .expect("Failed to bind mock provider");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    idp
+}
+
+async fn mock_discovery() -> Json<serde_json::Value> {
+    Json(json!({
+        "issuer": MOCK_ISSUER,
+        "authorization_endpoint": format!("{}/authorize", MOCK_ISSUER),
+        "token_endpoint": format!("{}/token", MOCK_ISSUER),
+        "jwks_uri": format!("{}/jwks", MOCK_ISSUER),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "code_challenge_methods_supported": ["S256"]
+    }))
+}
+
+async fn mock_authorize(
+    State(idp): State<Arc<MockIdp>>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    if query.get("client_id").map(String::as_str) != Some(CLIENT_ID)
+        || query.get("response_type").map(String::as_str) != Some("code")
+        || query.get("code_challenge_method").map(String::as_str) != Some("S256")
+        || !query["scope"].split(' ').any(|scope| scope == "openid")
+    {
+        return MockStatus::BAD_REQUEST.into_response();
+    }
+
+    let code = Uuid::new_v4().to_string();
+    let (sub, username) = idp.account.lock().unwrap().clone();
+    idp.codes.lock().unwrap().insert(
+        code.clone(),
+        (
+            query["code_challenge"].clone(),
+            query["nonce"].clone(),
+            sub,
+            username,
+        ),
+    );
+
+    let redirect = Url::parse_with_params(
+        &query["redirect_uri"],
+        &[("code", code.as_str()), ("state", query["state"].as_str())],
+    )
+    .expect("Invalid redirect uri");
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+async fn mock_token(
+    State(idp): State<Arc<MockIdp>>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Response {
+    let entry = idp.codes.lock().unwrap().remove(&form["code"]);
+    let (challenge, nonce, sub, username) = match entry {
+        Some(entry) => entry,
+        None => return MockStatus::BAD_REQUEST.into_response(),
+    };
+
+    //PKCE, the verifier has to match the challenge from the authorize request
+    if URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes())) != challenge
+        || form["grant_type"] != "authorization_code"
+    {
+        return MockStatus::BAD_REQUEST.into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("mock-key".to_string());
+    let id_token = encode(
+        &header,
+        &json!({
+            "iss": MOCK_ISSUER,
+            "aud": CLIENT_ID,
+            "sub": sub,
+            "iat": now,
+            "exp": now + 300,
+            "nonce": nonce,
+            "preferred_username": username,
+            "email": format!("{}@idp.example.com", username),
+            "email_verified": true
+        }),
+        &EncodingKey::from_ed_der(&idp.key),
+    )
+    .expect("Failed to sign id token");
+
+    Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token
+    }))
+    .into_response()
+}
+
+async fn mock_jwks(State(idp): State<Arc<MockIdp>>) -> Json<serde_json::Value> {
+    Json(json!({
+        "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "kid": "mock-key",
+            "alg": "EdDSA",
+            "use": "sig",
+            "x": URL_SAFE_NO_PAD.encode(&idp.public_key)
+        }]
+    }))
+}
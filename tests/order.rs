@@ -0,0 +1,922 @@
+use reqwest::{header, multipart, Client, StatusCode};
+use serde_json::json;
+
+#[tokio::test]
+async fn test_checkout_empty_cart() {
+    let client = Client::new();
+
+    // Step 1: Register a fresh user, so the cart is guaranteed to be empty
+    let credentials = json!({
+        "username": "order_empty_cart",
+        "password": "Secret15"
+    });
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&credentials)
+        .send()
+        .await
+        .expect("Failed to send register request");
+
+    // Step 2: Authenticate and retrieve token
+    let login_response = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&credentials)
+        .send()
+        .await
+        .expect("Failed to send login request");
+
+    assert_eq!(login_response.status(), StatusCode::OK);
+
+    let login_body = login_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+
+    let token = login_body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    // Step 3: Set Authorization Header
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to create Authorization header"),
+    );
+
+    // Step 4: Try to checkout an empty cart
+    let checkout_response = client
+        .post("http://127.0.0.1:3000/api/order")
+        .headers(headers)
+        .send()
+        .await
+        .expect("Failed to send checkout request");
+
+    assert_eq!(checkout_response.status(), StatusCode::BAD_REQUEST);
+
+    let checkout_body = checkout_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse checkout response JSON");
+
+    assert_eq!(checkout_body["error"].as_str(), Some("Cart is empty"));
+}
+
+#[tokio::test]
+async fn test_checkout_unauthorized() {
+    let client = Client::new();
+
+    let checkout_response = client
+        .post("http://127.0.0.1:3000/api/order")
+        .send()
+        .await
+        .expect("Failed to send checkout request");
+
+    assert_eq!(checkout_response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_order_status_transitions() {
+    let client = Client::new();
+
+    // Step 1: Place an order as a fresh user
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_status_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "status").await;
+
+    // Step 2: Move order forward
+    let forward_response = client
+        .patch(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/status",
+            order_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "status": "processing" }))
+        .send()
+        .await
+        .expect("Failed to send patch status request");
+
+    assert_eq!(forward_response.status(), StatusCode::OK);
+
+    // Step 3: Moving order back is rejected
+    let backward_response = client
+        .patch(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/status",
+            order_id
+        ))
+        .headers(admin_headers)
+        .json(&json!({ "status": "created" }))
+        .send()
+        .await
+        .expect("Failed to send patch status request");
+
+    assert_eq!(backward_response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_cancel_and_refund_order() {
+    let client = Client::new();
+
+    // Step 1: Place an order as a fresh user
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_cancel_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "cancel").await;
+
+    // Step 2: User cancels his order
+    let cancel_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/order/{}/cancel",
+            order_id
+        ))
+        .headers(user_headers.clone())
+        .json(&json!({ "reason": "Changed my mind" }))
+        .send()
+        .await
+        .expect("Failed to send cancel request");
+
+    assert_eq!(cancel_response.status(), StatusCode::OK);
+
+    // Step 3: Cancelled order can't be cancelled again
+    let second_cancel_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/order/{}/cancel",
+            order_id
+        ))
+        .headers(user_headers)
+        .json(&json!({ "reason": "Changed my mind" }))
+        .send()
+        .await
+        .expect("Failed to send cancel request");
+
+    assert_eq!(second_cancel_response.status(), StatusCode::CONFLICT);
+
+    // Step 4: Admin refunds cancelled order
+    let refund_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/refund",
+            order_id
+        ))
+        .headers(admin_headers)
+        .json(&json!({ "reason": "Paid before cancellation" }))
+        .send()
+        .await
+        .expect("Failed to send refund request");
+
+    assert_eq!(refund_response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_get_orders() {
+    let client = Client::new();
+
+    // Step 1: Place an order as a fresh user
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_history_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "history").await;
+
+    // Step 2: Order is in the history
+    let list_response = client
+        .get("http://127.0.0.1:3000/api/order?status=created&sort_by=total&order=desc")
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get orders request");
+
+    assert_eq!(list_response.status(), StatusCode::OK);
+
+    let list_body = list_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get orders response JSON");
+
+    assert!(list_body
+        .as_array()
+        .expect("Orders should be an array")
+        .iter()
+        .any(|order| order["id"] == order_id));
+
+    // Page 0 is the first page
+    let list_response = client
+        .get("http://127.0.0.1:3000/api/order?page=0")
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get orders request");
+
+    assert_eq!(list_response.status(), StatusCode::OK);
+
+    // Step 3: Order details contain ordered lines
+    let get_response = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(user_headers)
+        .send()
+        .await
+        .expect("Failed to send get order request");
+
+    assert_eq!(get_response.status(), StatusCode::OK);
+
+    let get_body = get_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+
+    assert_eq!(get_body["items"][0]["name"].as_str(), Some("order_history"));
+    assert_eq!(get_body["items"][0]["quantity"].as_i64(), Some(2));
+
+    // Step 4: Other users can't see this order
+    let other_headers = auth_headers(&client, "order_other_user").await;
+    let other_response = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(other_headers)
+        .send()
+        .await
+        .expect("Failed to send get order request");
+
+    assert_eq!(other_response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_edit_order() {
+    let client = Client::new();
+
+    // Step 1: Place an order as a fresh user
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_admin_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "admin_edit").await;
+
+    // Step 2: Admin finds the order by username
+    let list_response = client
+        .get("http://127.0.0.1:3000/api/admin/order?query=order_admin_user&page=0&page_size=100")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get orders request");
+
+    assert_eq!(list_response.status(), StatusCode::OK);
+
+    let list_body = list_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get orders response JSON");
+
+    assert!(list_body
+        .as_array()
+        .expect("Orders should be an array")
+        .iter()
+        .any(|order| order["id"] == order_id));
+
+    // Step 3: Get order line id
+    let get_body = client
+        .get(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}",
+            order_id
+        ))
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get order request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+
+    let item_id = get_body["items"][0]["id"]
+        .as_i64()
+        .expect("Order item not found");
+
+    // Step 4: Change quantity, total is recomputed
+    let patch_response = client
+        .patch(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/item/{}",
+            order_id, item_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "quantity": 1 }))
+        .send()
+        .await
+        .expect("Failed to send patch order item request");
+
+    assert_eq!(patch_response.status(), StatusCode::OK);
+
+    let patch_body = patch_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse patch order item response JSON");
+
+    assert_eq!(patch_body["total"].as_i64(), Some(1050));
+
+    // Step 5: Quantity that doesn't fit into the stock is rejected
+    let patch_response = client
+        .patch(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/item/{}",
+            order_id, item_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "quantity": u32::MAX }))
+        .send()
+        .await
+        .expect("Failed to send patch order item request");
+
+    assert_eq!(patch_response.status(), StatusCode::BAD_REQUEST);
+
+    // Step 6: Last item can't be removed
+    let remove_response = client
+        .delete(format!(
+            "http://127.0.0.1:3000/api/admin/order/{}/item/{}",
+            order_id, item_id
+        ))
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send remove order item request");
+
+    assert_eq!(remove_response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_checkout_takes_stock() {
+    let client = Client::new();
+
+    // Step 1: Place an order as a fresh user
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_stock_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "stock").await;
+
+    // Step 2: Get ordered product id
+    let get_body = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get order request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+
+    let product_id = get_body["items"][0]["product_id"]
+        .as_i64()
+        .expect("Order item not found");
+
+    // Step 3: Checkout movement is logged
+    let movements = client
+        .get(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/stock",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get stock movements request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get stock movements response JSON");
+
+    assert!(movements
+        .as_array()
+        .expect("Stock movements should be an array")
+        .iter()
+        .any(|movement| movement["reason"] == "checkout" && movement["change"] == -2));
+
+    // Step 4: Cart can't hold more than the remaining stock
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "quantity": 9 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+
+    assert_eq!(add_response.status(), StatusCode::CONFLICT);
+
+    // Step 5: Quantities that don't fit into the stock are rejected
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "quantity": 1 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert!(add_response.status().is_success());
+
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers)
+        .json(&json!({ "product_id": product_id, "quantity": u32::MAX }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert_eq!(add_response.status(), StatusCode::BAD_REQUEST);
+
+    let restock_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/restock",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "quantity": i32::MAX as u32 + 1 }))
+        .send()
+        .await
+        .expect("Failed to send restock request");
+    assert_eq!(restock_response.status(), StatusCode::BAD_REQUEST);
+
+    let adjust_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/stock",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "change": i32::MIN, "note": "overflow" }))
+        .send()
+        .await
+        .expect("Failed to send adjust stock request");
+    assert_eq!(adjust_response.status(), StatusCode::BAD_REQUEST);
+
+    // Step 6: Stock can't be pushed past the limit
+    let restock_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/restock",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({ "quantity": i32::MAX as u32 }))
+        .send()
+        .await
+        .expect("Failed to send restock request");
+    assert_eq!(restock_response.status(), StatusCode::CONFLICT);
+
+    let adjust_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/stock",
+            product_id
+        ))
+        .headers(admin_headers)
+        .json(&json!({ "change": i32::MAX, "note": "overflow" }))
+        .send()
+        .await
+        .expect("Failed to send adjust stock request");
+    assert_eq!(adjust_response.status(), StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn test_checkout_variant() {
+    let client = Client::new();
+
+    // Step 1: Place an order to get a product, then add a variant to it
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_variant_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "variant").await;
+
+    let order_body = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get order request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+    let product_id = order_body["items"][0]["product_id"]
+        .as_i64()
+        .expect("Order item not found");
+
+    let create_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/variant",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&json!({
+            "name": "Poppy seed, 10 pack",
+            "flavour": "poppy",
+            "pack_count": 10,
+            "price": 700,
+            "stock": 3
+        }))
+        .send()
+        .await
+        .expect("Failed to send create variant request");
+    assert_eq!(create_response.status(), StatusCode::CREATED);
+
+    let variant_id = create_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse create variant response JSON")["variant_id"]
+        .as_i64()
+        .expect("Variant id not found in create variant response");
+
+    // Step 2: Product lists its variants
+    let product_body = client
+        .get(format!("http://127.0.0.1:3000/api/product/{}", product_id))
+        .send()
+        .await
+        .expect("Failed to send get product request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get product response JSON");
+    assert_eq!(product_body["variants"][0]["id"].as_i64(), Some(variant_id));
+
+    // Step 3: Product with variants can't be added without one
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "quantity": 1 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert_eq!(add_response.status(), StatusCode::BAD_REQUEST);
+
+    // Step 4: Variant is ordered with its own price
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "variant_id": variant_id, "quantity": 2 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert!(add_response.status().is_success());
+
+    // Step 5: Cart is filtered by the variant price
+    let cart_body = client
+        .get("http://127.0.0.1:3000/api/cart?price_bottom=700&price_top=700")
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get cart request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get cart response JSON");
+    assert_eq!(cart_body[0]["variant_id"].as_i64(), Some(variant_id));
+
+    let checkout_response = client
+        .post("http://127.0.0.1:3000/api/order")
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send checkout request");
+    assert_eq!(checkout_response.status(), StatusCode::CREATED);
+
+    let checkout_body = checkout_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse checkout response JSON");
+    assert_eq!(checkout_body["total"].as_i64(), Some(1400));
+
+    // Step 6: Units of a deleted variant don't go back to the product stock
+    let delete_response = client
+        .delete(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/variant/{}",
+            product_id, variant_id
+        ))
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send delete variant request");
+    assert_eq!(delete_response.status(), StatusCode::OK);
+
+    let cancel_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/order/{}/cancel",
+            checkout_body["order_id"].as_i64().expect("Order id not found")
+        ))
+        .headers(user_headers)
+        .json(&json!({ "reason": "Variant is gone" }))
+        .send()
+        .await
+        .expect("Failed to send cancel request");
+    assert_eq!(cancel_response.status(), StatusCode::OK);
+
+    let movements = client
+        .get(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/stock",
+            product_id
+        ))
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send get stock movements request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get stock movements response JSON");
+    assert!(!movements
+        .as_array()
+        .expect("Stock movements should be an array")
+        .iter()
+        .any(|movement| movement["reason"] == "cancellation"));
+}
+
+#[tokio::test]
+async fn test_checkout_total_overflow() {
+    let client = Client::new();
+
+    // Step 1: Add a variant that costs as much as a total can hold
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_overflow_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "overflow").await;
+
+    let order_body = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get order request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+    let product_id = order_body["items"][0]["product_id"]
+        .as_i64()
+        .expect("Order item not found");
+
+    let variant_id = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/variant",
+            product_id
+        ))
+        .headers(admin_headers)
+        .json(&json!({
+            "name": "Golden, 1 pack",
+            "pack_count": 1,
+            "price": i64::MAX,
+            "stock": 3
+        }))
+        .send()
+        .await
+        .expect("Failed to send create variant request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse create variant response JSON")["variant_id"]
+        .as_i64()
+        .expect("Variant id not found in create variant response");
+
+    // Step 2: Order total that doesn't fit is rejected
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "variant_id": variant_id, "quantity": 2 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert!(add_response.status().is_success());
+
+    let checkout_response = client
+        .post("http://127.0.0.1:3000/api/order")
+        .headers(user_headers)
+        .send()
+        .await
+        .expect("Failed to send checkout request");
+    assert_eq!(checkout_response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_product_gallery() {
+    let client = Client::new();
+
+    // Step 1: Place an order to get a product with a cover image
+    let admin_headers = auth_headers(&client, "admin").await;
+    let user_headers = auth_headers(&client, "order_gallery_user").await;
+    let order_id = place_order(&client, &admin_headers, &user_headers, "gallery").await;
+
+    let order_body = client
+        .get(format!("http://127.0.0.1:3000/api/order/{}", order_id))
+        .headers(user_headers)
+        .send()
+        .await
+        .expect("Failed to send get order request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get order response JSON");
+    let product_id = order_body["items"][0]["product_id"]
+        .as_i64()
+        .expect("Order item not found");
+
+    let product_body = client
+        .get(format!("http://127.0.0.1:3000/api/product/{}", product_id))
+        .send()
+        .await
+        .expect("Failed to send get product request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get product response JSON");
+    let cover_id = product_body["image_id"]
+        .as_i64()
+        .expect("Cover image not found");
+
+    // Step 2: Attach cover image to the gallery, only once
+    let attach_payload = json!({ "image_id": cover_id, "alt_text": "Cover" });
+    let attach_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/image",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&attach_payload)
+        .send()
+        .await
+        .expect("Failed to send attach image request");
+    assert_eq!(attach_response.status(), StatusCode::CREATED);
+
+    let attach_response = client
+        .post(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/image",
+            product_id
+        ))
+        .headers(admin_headers.clone())
+        .json(&attach_payload)
+        .send()
+        .await
+        .expect("Failed to send attach image request");
+    assert_eq!(attach_response.status(), StatusCode::CONFLICT);
+
+    // Step 3: Product returns its gallery
+    let product_body = client
+        .get(format!("http://127.0.0.1:3000/api/product/{}", product_id))
+        .send()
+        .await
+        .expect("Failed to send get product request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get product response JSON");
+    assert_eq!(
+        product_body["images"][0]["image_id"].as_i64(),
+        Some(cover_id)
+    );
+    assert_eq!(product_body["images"][0]["alt_text"], "Cover");
+
+    // Step 4: Image used as a cover can't be deleted
+    let delete_response = client
+        .delete(format!("http://127.0.0.1:3000/api/image/{}", cover_id))
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send delete image request");
+    assert_eq!(delete_response.status(), StatusCode::CONFLICT);
+
+    // Step 5: Detached image leaves the gallery
+    let detach_response = client
+        .delete(format!(
+            "http://127.0.0.1:3000/api/admin/product/{}/image/{}",
+            product_id, cover_id
+        ))
+        .headers(admin_headers)
+        .send()
+        .await
+        .expect("Failed to send detach image request");
+    assert_eq!(detach_response.status(), StatusCode::OK);
+}
+
+//utils
+//Registers user (if needed) and returns headers with its bearer token
+async fn auth_headers(client: &Client, username: &str) -> header::HeaderMap {
+    let credentials = json!({
+        "username": username,
+        "password": "Secret15"
+    });
+
+    client
+        .post("http://127.0.0.1:3000/register")
+        .json(&credentials)
+        .send()
+        .await
+        .expect("Failed to send register request");
+
+    let login_body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&credentials)
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+
+    let token = login_body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .expect("Failed to create Authorization header"),
+    );
+    headers
+}
+
+//Creates image, category and product named after `tag`, puts product into cart and checkouts
+async fn place_order(
+    client: &Client,
+    admin_headers: &header::HeaderMap,
+    user_headers: &header::HeaderMap,
+    tag: &str,
+) -> i64 {
+    let name = format!("order_{}", tag);
+
+    //Random color, so uploads aren't deduplicated into one image
+    let [r, g, b, ..] = *uuid::Uuid::new_v4().as_bytes();
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(1, 1, image::Rgb([r, g, b]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .expect("Failed to encode image");
+    let form = multipart::Form::new().part(
+        name.clone(),
+        multipart::Part::bytes(png)
+            .file_name(format!("{}.png", name))
+            .mime_str("image/png")
+            .expect("Failed to set mime type"),
+    );
+    client
+        .post("http://127.0.0.1:3000/api/image")
+        .headers(admin_headers.clone())
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send upload request");
+
+    let images = client
+        .get(format!("http://127.0.0.1:3000/api/image?query={}", name))
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get images request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get images response JSON");
+    let image_id = images[0]["id"].as_i64().expect("Image not found");
+
+    client
+        .post("http://127.0.0.1:3000/api/admin/category")
+        .headers(admin_headers.clone())
+        .json(&json!({ "name": name, "image_id": image_id }))
+        .send()
+        .await
+        .expect("Failed to send create category request");
+
+    let categories = client
+        .get("http://127.0.0.1:3000/api/admin/category?page_size=1000")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get categories request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get categories response JSON");
+    let category_id = find_id_by_name(&categories, &name);
+
+    client
+        .post("http://127.0.0.1:3000/api/admin/product")
+        .headers(admin_headers.clone())
+        .json(&json!({
+            "name": name,
+            "price": 1050,
+            "description": "Order test product",
+            "image_id": image_id,
+            "category_id": category_id,
+            "is_available": true,
+            "stock": 10
+        }))
+        .send()
+        .await
+        .expect("Failed to send create product request");
+
+    let products = client
+        .get("http://127.0.0.1:3000/api/product?page_size=1000")
+        .headers(admin_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send get products request")
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse get products response JSON");
+    let product_id = find_id_by_name(&products, &name);
+
+    let add_response = client
+        .post("http://127.0.0.1:3000/api/cart")
+        .headers(user_headers.clone())
+        .json(&json!({ "product_id": product_id, "quantity": 2 }))
+        .send()
+        .await
+        .expect("Failed to send add product request");
+    assert!(add_response.status().is_success());
+
+    let checkout_response = client
+        .post("http://127.0.0.1:3000/api/order")
+        .headers(user_headers.clone())
+        .send()
+        .await
+        .expect("Failed to send checkout request");
+    assert_eq!(checkout_response.status(), StatusCode::CREATED);
+
+    checkout_response
+        .json::<serde_json::Value>()
+        .await
+        .expect("Failed to parse checkout response JSON")["order_id"]
+        .as_i64()
+        .expect("Order id not found in checkout response")
+}
+
+fn find_id_by_name(items: &serde_json::Value, name: &str) -> i64 {
+    items
+        .as_array()
+        .and_then(|items| items.iter().find(|item| item["name"] == name))
+        .and_then(|item| item["id"].as_i64())
+        .expect("Entry not found by name")
+}
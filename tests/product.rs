@@ -41,7 +41,7 @@ async fn test_create_product() {
     // Step 3: Define payload for creating a product
     let create_payload = json!({
         "name": "Test Product",
-        "price": 100.0,
+        "price": 10000,
         "description": "A test product",
         "image_id": 1,
         "category_id": 1,
@@ -221,7 +221,7 @@ async fn test_patch_product() {
     // Step 3: Define payload for updating a product
     let patch_payload = json!({
         "name": "Updated Test Product",
-        "price": 120.0
+        "price": 12000
     });
 
     // Step 4: Send request to patch product (replace 1 with actual product id)
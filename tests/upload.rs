@@ -214,3 +214,495 @@ async fn test_delete_image() {
 
     println!("Delete Image Response: {:?}", delete_body);
 }
+
+//Backend agnostic, run the server with STORAGE="s3" and a local MinIO to check the S3 backend
+#[tokio::test]
+async fn test_image_storage_round_trip() {
+    let client = Client::new();
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": "admin",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    // Step 1: Upload image
+    let name = format!(
+        "storage_{}",
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    );
+    let form = multipart::Form::new().part(
+        name.clone(),
+        multipart::Part::bytes(PNG_1X1.to_vec())
+            .file_name(format!("{}.png", name))
+            .mime_str("image/png")
+            .expect("Failed to set mime type"),
+    );
+    let upload_response = client
+        .post("http://127.0.0.1:3000/api/image")
+        .bearer_auth(token)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send upload request");
+    assert_eq!(upload_response.status(), StatusCode::CREATED);
+
+    let images = client
+        .get(format!("http://127.0.0.1:3000/api/image?query={}", name))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send get images request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse get images response JSON");
+    let image_id = images[0]["id"].as_i64().expect("Image not found");
+
+    // Step 2: Image is served from storage
+    let get_response = client
+        .get(format!("http://127.0.0.1:3000/image/{}", image_id))
+        .send()
+        .await
+        .expect("Failed to send GET image request");
+    assert_eq!(get_response.status(), StatusCode::OK);
+    assert_eq!(get_response.headers()["content-type"], "image/png");
+    let bytes = get_response.bytes().await.expect("Failed to read image");
+    //Uploads are re-encoded, pixels stay the same
+    let served = image::load_from_memory(&bytes)
+        .expect("Failed to decode image")
+        .to_rgb8();
+    assert_eq!(served.dimensions(), (1, 1));
+    assert_eq!(served.get_pixel(0, 0), &image::Rgb([255, 0, 0]));
+
+    // Step 3: Deleted image is gone from storage too
+    let delete_response = client
+        .delete(format!("http://127.0.0.1:3000/api/image/{}", image_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send DELETE image request");
+    assert_eq!(delete_response.status(), StatusCode::OK);
+
+    let get_response = client
+        .get(format!("http://127.0.0.1:3000/image/{}", image_id))
+        .send()
+        .await
+        .expect("Failed to send GET image request");
+    assert_eq!(get_response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_image_sizes() {
+    let client = Client::new();
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": "admin",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    // Step 1: Upload image wide enough for the thumbnail only
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(400, 100, image::Rgb([200, 120, 40]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .expect("Failed to encode image");
+
+    let name = format!("sizes_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let form = multipart::Form::new().part(
+        name.clone(),
+        multipart::Part::bytes(png)
+            .file_name(format!("{}.png", name))
+            .mime_str("image/png")
+            .expect("Failed to set mime type"),
+    );
+    let upload_response = client
+        .post("http://127.0.0.1:3000/api/image")
+        .bearer_auth(token)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send upload request");
+    assert_eq!(upload_response.status(), StatusCode::CREATED);
+
+    let images = client
+        .get(format!("http://127.0.0.1:3000/api/image?query={}", name))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send get images request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse get images response JSON");
+    let image_id = images[0]["id"].as_i64().expect("Image not found");
+
+    // Step 2: Thumbnail is made in background
+    let mut thumb_width = 0;
+    for _ in 0..50 {
+        thumb_width = image_width(&client, image_id, "size=thumb").await;
+        if thumb_width != 400 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(thumb_width, 160);
+
+    // Step 3: Closest size is served, the original when there's no bigger copy
+    assert_eq!(image_width(&client, image_id, "w=100").await, 160);
+    assert_eq!(image_width(&client, image_id, "w=300").await, 400);
+    assert_eq!(image_width(&client, image_id, "size=medium").await, 400);
+    assert_eq!(image_width(&client, image_id, "").await, 400);
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:3000/image/{}?size=huge",
+            image_id
+        ))
+        .send()
+        .await
+        .expect("Failed to send GET image request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let delete_response = client
+        .delete(format!("http://127.0.0.1:3000/api/image/{}", image_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send DELETE image request");
+    assert_eq!(delete_response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_upload_checks_real_format() {
+    let client = Client::new();
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": "admin",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let [r, g, b, ..] = *uuid::Uuid::new_v4().as_bytes();
+    let mut webp = Vec::new();
+    image::RgbaImage::from_pixel(2, 2, image::Rgba([r, g, b, 255]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut webp),
+            image::ImageFormat::WebP,
+        )
+        .expect("Failed to encode image");
+    let mut gif = Vec::new();
+    image::RgbaImage::from_pixel(1, 1, image::Rgba([r, g, b, 255]))
+        .write_to(&mut std::io::Cursor::new(&mut gif), image::ImageFormat::Gif)
+        .expect("Failed to encode image");
+    //Only the `ftyp` box is checked
+    let avif = b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf\x00\x00\x00\x00";
+
+    // Step 1: Formats from ALLOWED_IMAGE_FORMATS are accepted
+    for (bytes, mime) in [(webp.clone(), "image/webp"), (gif, "image/gif")] {
+        let status = upload_bytes(&client, token, bytes, mime).await;
+        assert_eq!(status, StatusCode::CREATED, "{} upload failed", mime);
+    }
+
+    // Step 2: Declared content type has to match the file
+    assert_eq!(
+        upload_bytes(&client, token, PNG_1X1.to_vec(), "image/jpeg").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        upload_bytes(&client, token, webp, "image/png").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        upload_bytes(&client, token, b"<svg></svg>".to_vec(), "image/png").await,
+        StatusCode::BAD_REQUEST
+    );
+
+    // Step 3: Unknown content types and formats left out of ALLOWED_IMAGE_FORMATS are rejected
+    assert_eq!(
+        upload_bytes(&client, token, b"<svg></svg>".to_vec(), "image/svg+xml").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        upload_bytes(&client, token, avif.to_vec(), "image/avif").await,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn test_upload_strips_metadata() {
+    let client = Client::new();
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": "admin",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    // Step 1: JPEG with EXIF camera make and orientation "rotate 90° clockwise"
+    let [r, g, b, ..] = *uuid::Uuid::new_v4().as_bytes();
+    let mut jpeg = Vec::new();
+    image::RgbImage::from_pixel(40, 20, image::Rgb([r, g, b]))
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageFormat::Jpeg,
+        )
+        .expect("Failed to encode image");
+    let mut tiff = b"MM\x00\x2a\x00\x00\x00\x08\x00\x02".to_vec();
+    tiff.extend_from_slice(b"\x01\x0f\x00\x02\x00\x00\x00\x09\x00\x00\x00\x26");
+    tiff.extend_from_slice(b"\x01\x12\x00\x03\x00\x00\x00\x01\x00\x06\x00\x00");
+    tiff.extend_from_slice(b"\x00\x00\x00\x00LeakyCam\x00");
+    let mut app1 = vec![0xFF, 0xE1];
+    app1.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\x00\x00");
+    app1.extend_from_slice(&tiff);
+    jpeg.splice(2..2, app1);
+
+    let name = format!("exif_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let form = multipart::Form::new().part(
+        name.clone(),
+        multipart::Part::bytes(jpeg)
+            .file_name(format!("{}.jpg", name))
+            .mime_str("image/jpeg")
+            .expect("Failed to set mime type"),
+    );
+    let upload_response = client
+        .post("http://127.0.0.1:3000/api/image")
+        .bearer_auth(token)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send upload request");
+    assert_eq!(upload_response.status(), StatusCode::CREATED);
+
+    let images = client
+        .get(format!("http://127.0.0.1:3000/api/image?query={}", name))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send get images request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse get images response JSON");
+    let image_id = images[0]["id"].as_i64().expect("Image not found");
+
+    // Step 2: Served image is rotated and has no metadata
+    let bytes = client
+        .get(format!("http://127.0.0.1:3000/image/{}", image_id))
+        .send()
+        .await
+        .expect("Failed to send GET image request")
+        .bytes()
+        .await
+        .expect("Failed to read image");
+    let served = image::load_from_memory(&bytes).expect("Failed to decode image");
+    assert_eq!((served.width(), served.height()), (20, 40));
+    assert!(!bytes.windows(4).any(|window| window == b"Exif"));
+    assert!(!bytes.windows(8).any(|window| window == b"LeakyCam"));
+
+    // Step 3: Broken images and decompression bombs are rejected
+    let mut broken = PNG_1X1[..40].to_vec();
+    broken.extend_from_slice(b"not really a png");
+    assert_eq!(
+        upload_bytes(&client, token, broken, "image/png").await,
+        StatusCode::BAD_REQUEST
+    );
+
+    //65535x65535 canvas, way over MAX_IMAGE_PIXELS
+    let mut bomb = GIF_1X1.to_vec();
+    bomb[6..10].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(
+        upload_bytes(&client, token, bomb, "image/gif").await,
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+}
+
+#[tokio::test]
+async fn test_upload_deduplicates_content() {
+    let client = Client::new();
+
+    let body = client
+        .post("http://127.0.0.1:3000/login")
+        .json(&json!({
+            "username": "admin",
+            "password": "Secret15"
+        }))
+        .send()
+        .await
+        .expect("Failed to send login request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse login response JSON");
+    let token = body["token"]
+        .as_str()
+        .expect("Token not found in login response");
+
+    let [r, g, b, ..] = *uuid::Uuid::new_v4().as_bytes();
+    let mut png = Vec::new();
+    image::RgbImage::from_pixel(3, 3, image::Rgb([r, g, b]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .expect("Failed to encode image");
+
+    // Step 1: First upload stores the image
+    let upload = |name: String, bytes: Vec<u8>| {
+        let form = multipart::Form::new().part(
+            name.clone(),
+            multipart::Part::bytes(bytes)
+                .file_name(name)
+                .mime_str("image/png")
+                .expect("Failed to set mime type"),
+        );
+        client
+            .post("http://127.0.0.1:3000/api/image")
+            .bearer_auth(token)
+            .multipart(form)
+            .send()
+    };
+    let first_name = format!("dedup_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let response = upload(first_name.clone(), png.clone())
+        .await
+        .expect("Failed to send upload request");
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response
+        .json::<Value>()
+        .await
+        .expect("Failed to parse upload JSON");
+    let image_id = body["id"].as_i64().expect("Image id not found");
+
+    // Step 2: Same content under another name returns the existing image
+    let second_name = format!("dedup_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let response = upload(second_name.clone(), png)
+        .await
+        .expect("Failed to send upload request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response
+        .json::<Value>()
+        .await
+        .expect("Failed to parse upload JSON");
+    assert_eq!(body["id"].as_i64(), Some(image_id));
+    assert_eq!(body["file_name"], first_name);
+
+    let images = client
+        .get(format!(
+            "http://127.0.0.1:3000/api/image?query={}",
+            second_name
+        ))
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send get images request")
+        .json::<Value>()
+        .await
+        .expect("Failed to parse get images response JSON");
+    assert_eq!(images.as_array().map(Vec::len), Some(0));
+
+    // Step 3: Report lists groups of images with the same content only
+    let response = client
+        .get("http://127.0.0.1:3000/api/admin/image/duplicates")
+        .bearer_auth(token)
+        .send()
+        .await
+        .expect("Failed to send duplicates request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = response
+        .json::<Value>()
+        .await
+        .expect("Failed to parse duplicates JSON");
+    for group in report.as_array().expect("Report is not a list") {
+        let images = group["images"].as_array().expect("Images not found");
+        assert!(images.len() > 1);
+        assert!(images
+            .iter()
+            .all(|image| image["sha256"] == group["sha256"]));
+        assert!(images
+            .iter()
+            .all(|image| image["id"].as_i64() != Some(image_id)));
+    }
+}
+
+//utils
+async fn image_width(client: &Client, image_id: i64, query: &str) -> u32 {
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:3000/image/{}?{}",
+            image_id, query
+        ))
+        .send()
+        .await
+        .expect("Failed to send GET image request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.bytes().await.expect("Failed to read image");
+    image::load_from_memory(&bytes)
+        .expect("Failed to decode image")
+        .width()
+}
+
+//Uploads under a random name, returns response status
+async fn upload_bytes(client: &Client, token: &str, bytes: Vec<u8>, mime: &str) -> StatusCode {
+    let name = format!("format_{}", &uuid::Uuid::new_v4().simple().to_string()[..8]);
+    let form = multipart::Form::new().part(
+        name.clone(),
+        multipart::Part::bytes(bytes)
+            .file_name(name)
+            .mime_str(mime)
+            .expect("Failed to set mime type"),
+    );
+    client
+        .post("http://127.0.0.1:3000/api/image")
+        .bearer_auth(token)
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send upload request")
+        .status()
+}
+
+//Smallest valid PNG, one red pixel
+const PNG_1X1: &[u8] = &[
+    0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90, 0x77, 0x53,
+    0xde, 0x00, 0x00, 0x00, 0x0c, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8, 0xcf, 0xc0, 0x00,
+    0x00, 0x03, 0x01, 0x01, 0x00, 0xc9, 0xfe, 0x92, 0xef, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e,
+    0x44, 0xae, 0x42, 0x60, 0x82,
+];
+
+//Smallest valid GIF, one white pixel
+const GIF_1X1: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\xff\xff\xff\x00\x00\x00!\xf9\x04\x01\x00\x00\x00\x00,\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02D\x01\x00;";